@@ -1,15 +1,92 @@
 //! Helpers for comparing [`BezPath`]
 
-use kurbo::{BezPath, ParamCurve, ParamCurveNearest, Point};
+use std::{
+    fmt::{self, Display},
+    ops::{Range, RangeInclusive},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+};
+
+use kurbo::{
+    BezPath, ParamCurve, ParamCurveArclen, ParamCurveNearest, PathEl, PathSeg, Point, Rect, Shape,
+};
 use thiserror::Error;
 
 const NEAREST_EPSILON: f64 = 0.0000001;
 
+/// Coordinates at most this far apart are considered identical by [`nearly_identical`]
+const IDENTICAL_EPSILON: f64 = 0.000001;
+
+/// Absolute accuracy of the segment lengths that decide how many points to sample
+const ARCLEN_ACCURACY: f64 = 0.1;
+
+/// Default [`RulesOfSimilarity::samples_per_1000_units`], 11 points on a 100 unit segment
+pub const DEFAULT_SAMPLES_PER_1000_UNITS: f64 = 100.0;
+
+/// Default [`RulesOfSimilarity::min_samples`], the ends and the middle
+pub const DEFAULT_MIN_SAMPLES: usize = 3;
+
+/// Default [`RulesOfSimilarity::max_samples`]
+pub const DEFAULT_MAX_SAMPLES: usize = 101;
+
+/// The units per em permitted by the OpenType spec
+pub const VALID_UPEM: RangeInclusive<u16> = 16..=16384;
+
+/// How close two paths must be to count as approximately equal
+///
+/// Each sampled point of one path is measured against the nearest point on the other,
+/// and if symmetric the other way round as well.
 #[derive(Debug, Clone, Copy)]
 pub struct RulesOfSimilarity {
+    /// Points at most this far apart are considered the same, costing nothing
     pub equivalence: f64,
+    /// The sum of squared separations of points beyond equivalence may not exceed this
     pub budget: f64,
+    /// Any point further apart than this means the paths differ
     pub error: f64,
+    /// If set, letterforms whose advances differ by more than this are different without
+    /// comparing outlines
+    pub advance_tolerance: Option<f64>,
+    /// How much closer than the nearest point found so far a segment may be and still be
+    /// skipped when searching for the nearest point. 0 finds the true nearest point.
+    ///
+    /// Larger values skip more segments, so are faster, but may overstate separations
+    /// by up to this much.
+    pub nearest_slack: f64,
+    /// If set, also measure the other path against this one and require both directions to
+    /// pass, so whether two paths match doesn't depend on which is compared to which
+    ///
+    /// Without it a thin stroke lying within a thick one can match it one way but not the
+    /// other. Turning it off roughly halves the cost of paths that match.
+    pub symmetric: bool,
+    /// How the separations of sampled points are judged
+    pub metric: ComparisonMetric,
+    /// How many points to sample per 1000 units of segment length, so long segments are
+    /// sampled as densely as short ones
+    pub samples_per_1000_units: f64,
+    /// The fewest points sampled from a segment, including its ends. At least 2.
+    pub min_samples: usize,
+    /// The most points sampled from a segment, including its ends
+    pub max_samples: usize,
+    /// If set, paths with different numbers of contours differ without sampling them
+    ///
+    /// A cheap way to tell apart, say, a dotted and a dotless i but the same shape drawn
+    /// as one contour or as several overlapping ones no longer matches.
+    pub same_contour_count: bool,
+}
+
+/// How the separations between the sampled points of two paths are judged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ComparisonMetric {
+    /// Separations beyond equivalence are paid for, squared, from the budget and none may
+    /// exceed error. Tolerates a spike under error if the rest of the path is close.
+    #[default]
+    Budget,
+    /// Only the largest separation, the Hausdorff distance, counts and it may not exceed
+    /// error. Equivalence and budget are ignored.
+    Hausdorff,
 }
 
 impl RulesOfSimilarity {
@@ -17,26 +94,108 @@ impl RulesOfSimilarity {
         if upem == 1000 {
             return self;
         };
+        if !VALID_UPEM.contains(&upem) {
+            log::warn!("Not scaling rules for upem {upem}, it is outside {VALID_UPEM:?}");
+            return self;
+        }
         let scale = upem as f64 / 1000.0;
         Self {
             equivalence: self.equivalence * scale,
-            budget: self.budget * scale,
+            // budget is spent in squared separations
+            budget: self.budget * scale * scale,
             error: self.error * scale,
+            advance_tolerance: self.advance_tolerance.map(|t| t * scale),
+            nearest_slack: self.nearest_slack * scale,
+            symmetric: self.symmetric,
+            metric: self.metric,
+            // the same density relative to the em
+            samples_per_1000_units: self.samples_per_1000_units / scale,
+            min_samples: self.min_samples,
+            max_samples: self.max_samples,
+            same_contour_count: self.same_contour_count,
         }
     }
 }
 
 #[derive(Error, Debug)]
 pub enum ApproximatelyEqualError {
-    #[error("{separation:.2} exceeds error limit. {rules:?}.")]
+    #[error("{separation:.2} exceeds error limit{}. {rules:?}.", at(.drift))]
     BrokeTheHardDeck {
         separation: f64,
+        /// Where, unless the extents of the paths were enough to tell
+        drift: Option<Box<Drift>>,
         rules: RulesOfSimilarity,
     },
-    #[error("Exhaused budget. {0:?}.")]
-    ExhaustedBudget(RulesOfSimilarity),
+    /// With the point that was furthest from the other path
+    #[error("Exhaused budget, furthest {1}. {0:?}.")]
+    ExhaustedBudget(RulesOfSimilarity, Box<Drift>),
     #[error("One of Self and other is empty")]
     EmptinessMismatch,
+    #[error("Advances differ by {0:.2}")]
+    AdvanceMismatch(f64),
+    #[error("{0} contours vs {1}")]
+    ContourCountMismatch(usize, usize),
+}
+
+impl ApproximatelyEqualError {
+    /// The sample point that failed, if the failure was at a point
+    pub fn drift(&self) -> Option<Drift> {
+        match self {
+            ApproximatelyEqualError::BrokeTheHardDeck { drift, .. } => drift.as_deref().copied(),
+            ApproximatelyEqualError::ExhaustedBudget(_, drift) => Some(**drift),
+            _ => None,
+        }
+    }
+}
+
+/// A sample point and the nearest point on the path it was measured against, for
+/// debugging letterforms that don't match
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Drift {
+    pub point: Point,
+    pub nearest: Point,
+    /// The index of the segment of the sampled path the point is on
+    pub segment: usize,
+    /// If set the point is on the other path, sampled when comparing symmetrically
+    pub on_other: bool,
+}
+
+impl Drift {
+    pub fn separation(&self) -> f64 {
+        (self.point - self.nearest).length()
+    }
+
+    /// The same drift, from the point of view of the other path
+    fn reversed(self) -> Self {
+        Self {
+            on_other: !self.on_other,
+            ..self
+        }
+    }
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "({:.1}, {:.1}) on segment {} of {}, {:.2} from ({:.1}, {:.1})",
+            self.point.x,
+            self.point.y,
+            self.segment,
+            if self.on_other { "other" } else { "self" },
+            self.separation(),
+            self.nearest.x,
+            self.nearest.y,
+        )
+    }
+}
+
+/// Where a failure happened, if known, for error messages
+fn at(drift: &Option<Box<Drift>>) -> String {
+    drift
+        .as_ref()
+        .map(|d| format!(" at {d}"))
+        .unwrap_or_default()
 }
 
 pub trait AboutTheSame<T = Self> {
@@ -47,54 +206,953 @@ pub trait AboutTheSame<T = Self> {
     ) -> Result<(), ApproximatelyEqualError>;
 }
 
-fn nearest(p: Point, other: &BezPath) -> Point {
-    other
-        .segments()
-        .map(|s| {
-            let nearest = s.nearest(p, NEAREST_EPSILON);
-            (nearest.distance_sq, s.eval(nearest.t))
-        })
-        .reduce(|acc, e| if acc.0 <= e.0 { acc } else { e })
-        .expect("Don't use this with empty paths")
-        .1
+/// How many segments [`nearest`] has measured the distance to
+///
+/// Counting is contended in the hottest loop we have so it's only for profiling.
+#[cfg(feature = "count-nearest")]
+static NUM_NEAREST: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of segments measured while searching for nearest points so far
+///
+/// None unless built with the count-nearest feature.
+#[cfg(feature = "count-nearest")]
+pub fn num_nearest() -> Option<usize> {
+    Some(NUM_NEAREST.load(Ordering::Relaxed))
+}
+
+/// The number of segments measured while searching for nearest points so far
+///
+/// None unless built with the count-nearest feature.
+#[cfg(not(feature = "count-nearest"))]
+pub fn num_nearest() -> Option<usize> {
+    None
+}
+
+/// How many times [`AboutTheSame::approximately_equal`] has compared two paths
+static NUM_COMPARISONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of pairs of paths compared so far
+pub fn num_comparisons() -> usize {
+    NUM_COMPARISONS.load(Ordering::Relaxed)
+}
+
+/// The control box of a segment, which contains it
+fn control_box(segment: PathSeg) -> Rect {
+    match segment {
+        PathSeg::Line(l) => Rect::from_points(l.p0, l.p1),
+        PathSeg::Quad(q) => Rect::from_points(q.p0, q.p1).union_pt(q.p2),
+        PathSeg::Cubic(c) => Rect::from_points(c.p0, c.p1).union_pt(c.p2).union_pt(c.p3),
+    }
+}
+
+/// The distance from p to the nearest point of rect, 0 if p is inside
+fn distance_to_rect(p: Point, rect: Rect) -> f64 {
+    let dx = (rect.x0 - p.x).max(p.x - rect.x1).max(0.0);
+    let dy = (rect.y0 - p.y).max(p.y - rect.y1).max(0.0);
+    dx.hypot(dy)
+}
+
+/// The distance from p to the point of segment nearest it, and that point
+///
+/// Horizontal and vertical lines, most of the segments of most fonts, are their own
+/// degenerate control box so the nearest point is just p clamped to it.
+fn nearest_on_segment(p: Point, segment: PathSeg, bbox: Rect) -> (f64, Point) {
+    if matches!(segment, PathSeg::Line(..)) && (bbox.width() == 0.0 || bbox.height() == 0.0) {
+        let nearest = Point::new(p.x.clamp(bbox.x0, bbox.x1), p.y.clamp(bbox.y0, bbox.y1));
+        return ((p - nearest).hypot(), nearest);
+    }
+    let nearest = segment.nearest(p, NEAREST_EPSILON);
+    (nearest.distance_sq.sqrt(), segment.eval(nearest.t))
+}
+
+/// Segments per leaf of a [`SegmentIndex`], below which segments are simply scanned
+const LEAF_SIZE: usize = 8;
+
+/// The segments of a path in a bounding volume hierarchy of their control boxes so
+/// [`nearest`] can skip whole regions of the path that can't hold the nearest point
+#[derive(Clone)]
+struct SegmentIndex {
+    segments: Vec<(PathSeg, Rect)>,
+    /// The root is first
+    nodes: Vec<SegmentNode>,
+}
+
+#[derive(Clone)]
+struct SegmentNode {
+    /// Contains the control boxes of every segment under this node
+    bounds: Rect,
+    children: Option<(usize, usize)>,
+    /// The range of segments under this node
+    segments: Range<usize>,
+}
+
+impl SegmentIndex {
+    fn new(path: &BezPath) -> Self {
+        let mut segments = path
+            .segments()
+            .map(|segment| (segment, control_box(segment)))
+            .collect::<Vec<_>>();
+        let mut nodes = Vec::new();
+        if !segments.is_empty() {
+            let all = 0..segments.len();
+            Self::build(&mut segments, all, &mut nodes);
+        }
+        Self { segments, nodes }
+    }
+
+    /// Add the node for range, splitting it at the median along its longer side if it's
+    /// too big to be a leaf, returning its index
+    fn build(
+        segments: &mut [(PathSeg, Rect)],
+        range: Range<usize>,
+        nodes: &mut Vec<SegmentNode>,
+    ) -> usize {
+        let bounds = segments[range.clone()]
+            .iter()
+            .map(|(_, bbox)| *bbox)
+            .reduce(|a, b| a.union(b))
+            .expect("Nodes are never empty");
+        let i = nodes.len();
+        nodes.push(SegmentNode {
+            bounds,
+            children: None,
+            segments: range.clone(),
+        });
+        if range.len() > LEAF_SIZE {
+            let center = |bbox: &Rect| {
+                if bounds.width() >= bounds.height() {
+                    bbox.center().x
+                } else {
+                    bbox.center().y
+                }
+            };
+            segments[range.clone()].sort_by(|a, b| center(&a.1).total_cmp(&center(&b.1)));
+            let mid = range.start + range.len() / 2;
+            let left = Self::build(segments, range.start..mid, nodes);
+            let right = Self::build(segments, mid..range.end, nodes);
+            nodes[i].children = Some((left, right));
+        }
+        i
+    }
+}
+
+/// The [`SegmentIndex`] of a path, built the first time it's needed and then kept so a
+/// path compared many times is only indexed once
+///
+/// Whoever holds it must [`reset`](Self::reset) it whenever the path changes.
+#[derive(Clone, Default)]
+pub(crate) struct LazySegmentIndex(OnceLock<SegmentIndex>);
+
+impl LazySegmentIndex {
+    /// The index of path, which must be the path any earlier call was given
+    fn get(&self, path: &BezPath) -> &SegmentIndex {
+        self.0.get_or_init(|| SegmentIndex::new(path))
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.0 = OnceLock::new();
+    }
+}
+
+impl fmt::Debug for LazySegmentIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LazySegmentIndex")
+            .field("built", &self.0.get().is_some())
+            .finish()
+    }
+}
+
+/// The point on the indexed path nearest p
+///
+/// Segments, and nodes of the index, whose control box is no more than slack closer than
+/// the best point found so far are skipped, see [`RulesOfSimilarity::nearest_slack`].
+fn nearest(p: Point, other: &SegmentIndex, slack: f64) -> Point {
+    assert!(!other.nodes.is_empty(), "Don't use this with empty paths");
+    let mut best: Option<(f64, Point)> = None;
+    let mut pending = Vec::with_capacity(16);
+    pending.push(0);
+    while let Some(i) = pending.pop() {
+        let node = &other.nodes[i];
+        if let Some((distance, _)) = best {
+            if distance_to_rect(p, node.bounds) >= distance - slack {
+                continue;
+            }
+        }
+        if let Some((left, right)) = node.children {
+            // visit the nearer child first, it's more likely to tighten best
+            let to = |i: usize| distance_to_rect(p, other.nodes[i].bounds);
+            if to(left) <= to(right) {
+                pending.extend([right, left]);
+            } else {
+                pending.extend([left, right]);
+            }
+            continue;
+        }
+        for (segment, bbox) in other.segments[node.segments.clone()].iter() {
+            if let Some((distance, _)) = best {
+                if distance_to_rect(p, *bbox) >= distance - slack {
+                    continue;
+                }
+            }
+            #[cfg(feature = "count-nearest")]
+            NUM_NEAREST.fetch_add(1, Ordering::Relaxed);
+            let (distance, nearest) = nearest_on_segment(p, *segment, *bbox);
+            if best.map(|(d, _)| distance < d).unwrap_or(true) {
+                best = Some((distance, nearest));
+            }
+        }
+    }
+    best.expect("Nodes are never empty").1
+}
+
+/// The number of contours of path, each starting with a move
+fn num_contours(path: &BezPath) -> usize {
+    path.elements()
+        .iter()
+        .filter(|el| matches!(el, PathEl::MoveTo(..)))
+        .count()
+}
+
+/// How far bbox extends beyond other on its furthest side, 0 if it's within other
+///
+/// The extreme point of a path on that side is at least this far from every point of a
+/// path bounded by other, so it's a lower bound on the largest separation.
+fn overhang(bbox: Rect, other: Rect) -> f64 {
+    [
+        other.x0 - bbox.x0,
+        bbox.x1 - other.x1,
+        other.y0 - bbox.y0,
+        bbox.y1 - other.y1,
+    ]
+    .into_iter()
+    .fold(0.0, f64::max)
+}
+
+/// True if path and other have the same elements with coordinates within
+/// [`IDENTICAL_EPSILON`], in which case they certainly match
+fn nearly_identical(path: &BezPath, other: &BezPath) -> bool {
+    let close = |a: &Point, b: &Point| {
+        (a.x - b.x).abs() <= IDENTICAL_EPSILON && (a.y - b.y).abs() <= IDENTICAL_EPSILON
+    };
+    path.elements().len() == other.elements().len()
+        && path
+            .elements()
+            .iter()
+            .zip(other.elements())
+            .all(|(a, b)| match (a, b) {
+                (PathEl::MoveTo(a), PathEl::MoveTo(b)) | (PathEl::LineTo(a), PathEl::LineTo(b)) => {
+                    close(a, b)
+                }
+                (PathEl::QuadTo(a0, a1), PathEl::QuadTo(b0, b1)) => close(a0, b0) && close(a1, b1),
+                (PathEl::CurveTo(a0, a1, a2), PathEl::CurveTo(b0, b1, b2)) => {
+                    close(a0, b0) && close(a1, b1) && close(a2, b2)
+                }
+                (PathEl::ClosePath, PathEl::ClosePath) => true,
+                _ => false,
+            })
+}
+
+/// The points of path compared against the other path: the ends of each segment and
+/// evenly spaced points between them, more for longer segments, with the index of the
+/// segment they're on
+fn sample_points(
+    path: &BezPath,
+    rules: RulesOfSimilarity,
+) -> impl Iterator<Item = (usize, Point)> + '_ {
+    let min = rules.min_samples.max(2);
+    let max = rules.max_samples.max(min);
+    let per_unit = rules.samples_per_1000_units / 1000.0;
+    path.segments().enumerate().flat_map(move |(s, segment)| {
+        let length = segment.arclen(ARCLEN_ACCURACY);
+        let n = ((length * per_unit).round() as usize + 1).clamp(min, max);
+        let intervals = (n - 1) as f64;
+        (0..n).map(move |i| (s, segment.eval(i as f64 / intervals)))
+    })
+}
+
+/// The distance from each sample point of path to the nearest point on other
+///
+/// This is what [`AboutTheSame::approximately_equal`] judges, exposed for threshold tuning.
+/// Empty if either path is empty.
+pub fn separations(path: &BezPath, other: &BezPath, rules: RulesOfSimilarity) -> Vec<f64> {
+    if other.is_empty() {
+        return Vec::new();
+    }
+    let other = SegmentIndex::new(other);
+    sample_points(path, rules)
+        .map(|(_, p)| (p - nearest(p, &other, 0.0)).length())
+        .collect()
 }
 
 impl AboutTheSame for BezPath {
     /// Meant to work with non-adversarial, similar, curves like letterforms
     ///
-    /// Think the same I drawn with two different sets of drawing commands    
+    /// Think the same I drawn with two different sets of drawing commands
+    ///
+    /// The number of contours is not compared unless [`RulesOfSimilarity::same_contour_count`]
+    /// is set: the same shape may be drawn as one contour or as several overlapping ones.
     fn approximately_equal(
         &self,
         other: &Self,
         rules: RulesOfSimilarity,
     ) -> Result<(), ApproximatelyEqualError> {
-        let mut budget = rules.budget;
+        approximately_equal_indexed(
+            (self, &LazySegmentIndex::default()),
+            (other, &LazySegmentIndex::default()),
+            rules,
+        )
+    }
+}
 
-        if self.is_empty() != other.is_empty() {
-            return Err(ApproximatelyEqualError::EmptinessMismatch);
-        }
+/// [`AboutTheSame::approximately_equal`] for paths whose segment indices may already be
+/// built, building them only if sampling is needed
+pub(crate) fn approximately_equal_indexed(
+    (path, index): (&BezPath, &LazySegmentIndex),
+    (other, other_index): (&BezPath, &LazySegmentIndex),
+    rules: RulesOfSimilarity,
+) -> Result<(), ApproximatelyEqualError> {
+    NUM_COMPARISONS.fetch_add(1, Ordering::Relaxed);
 
-        for segment in self.segments() {
-            for t in 0..=10 {
-                let t = t as f64 / 10.0;
-                let pt_self = segment.eval(t);
-                let pt_other = nearest(pt_self, other);
-                let separation = (pt_self - pt_other).length();
+    if path.is_empty() != other.is_empty() {
+        return Err(ApproximatelyEqualError::EmptinessMismatch);
+    }
+    if rules.same_contour_count {
+        let (contours, other_contours) = (num_contours(path), num_contours(other));
+        if contours != other_contours {
+            return Err(ApproximatelyEqualError::ContourCountMismatch(
+                contours,
+                other_contours,
+            ));
+        }
+    }
+    // The common case of an exact copy needs no nearest point searches
+    if nearly_identical(path, other) {
+        return Ok(());
+    }
+    // Paths whose extents differ by more than error can't match, no need to sample them
+    let (bbox, other_bbox) = (path.bounding_box(), other.bounding_box());
+    let mut separation = overhang(bbox, other_bbox);
+    if rules.symmetric {
+        separation = separation.max(overhang(other_bbox, bbox));
+    }
+    if separation > rules.error {
+        return Err(ApproximatelyEqualError::BrokeTheHardDeck {
+            separation,
+            drift: None,
+            rules,
+        });
+    }
 
-                if separation <= rules.equivalence {
-                    continue;
-                }
-                if separation > rules.error {
-                    return Err(ApproximatelyEqualError::BrokeTheHardDeck { separation, rules });
-                }
-                budget -= separation.powf(2.0);
-                log::debug!("Nearest {pt_self:?} is {pt_other:?}, {separation:.2} apart. {}/{} budget remains.", budget, rules.budget);
-                if budget < 0.0 {
-                    log::debug!("Fail due to exhausted budget");
-                    return Err(ApproximatelyEqualError::ExhaustedBudget(rules));
+    match rules.metric {
+        ComparisonMetric::Budget => {
+            measure_against(path, other_index.get(other), rules)?;
+            if rules.symmetric {
+                measure_against(other, index.get(path), rules).map_err(|e| match e {
+                    ApproximatelyEqualError::BrokeTheHardDeck {
+                        separation,
+                        drift,
+                        rules,
+                    } => ApproximatelyEqualError::BrokeTheHardDeck {
+                        separation,
+                        drift: drift.map(|d| Box::new(d.reversed())),
+                        rules,
+                    },
+                    ApproximatelyEqualError::ExhaustedBudget(rules, drift) => {
+                        ApproximatelyEqualError::ExhaustedBudget(rules, Box::new(drift.reversed()))
+                    }
+                    e => e,
+                })?;
+            }
+        }
+        ComparisonMetric::Hausdorff => {
+            let mut drift = hausdorff_against(path, other_index.get(other), rules);
+            if rules.symmetric {
+                let reverse = hausdorff_against(other, index.get(path), rules).reversed();
+                if reverse.separation() > drift.separation() {
+                    drift = reverse;
                 }
             }
+            let separation = drift.separation();
+            if separation > rules.error {
+                log::debug!("Fail, {separation:.2} apart at {drift}");
+                return Err(ApproximatelyEqualError::BrokeTheHardDeck {
+                    separation,
+                    drift: Some(Box::new(drift)),
+                    rules,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The sample point of path furthest from the nearest point on other, whose separation is
+/// the directed Hausdorff distance
+fn hausdorff_against(path: &BezPath, other: &SegmentIndex, rules: RulesOfSimilarity) -> Drift {
+    let mut furthest = (0.0, Drift::default());
+    for (segment, point) in sample_points(path, rules) {
+        let nearest = nearest(point, other, rules.nearest_slack);
+        let separation_sq = (point - nearest).hypot2();
+        if separation_sq > furthest.0 {
+            furthest = (
+                separation_sq,
+                Drift {
+                    point,
+                    nearest,
+                    segment,
+                    on_other: false,
+                },
+            );
+        }
+    }
+    furthest.1
+}
+
+/// Measure each sample point of path against the nearest point on other, one direction of
+/// [`AboutTheSame::approximately_equal`]
+fn measure_against(
+    path: &BezPath,
+    other: &SegmentIndex,
+    rules: RulesOfSimilarity,
+) -> Result<(), ApproximatelyEqualError> {
+    let mut budget = rules.budget;
+    // Work with squared separations so the hot loop never takes a sqrt
+    let equivalence_sq = rules.equivalence * rules.equivalence;
+    let error_sq = rules.error * rules.error;
+    // the point furthest from other so far, for the error if the budget runs out
+    let mut furthest = (0.0, Drift::default());
+    for (segment, pt_self) in sample_points(path, rules) {
+        let pt_other = nearest(pt_self, other, rules.nearest_slack);
+        let separation_sq = (pt_self - pt_other).hypot2();
+
+        if separation_sq <= equivalence_sq {
+            continue;
+        }
+        let drift = Drift {
+            point: pt_self,
+            nearest: pt_other,
+            segment,
+            on_other: false,
+        };
+        if separation_sq > error_sq {
+            log::debug!("Fail, beyond error at {drift}");
+            return Err(ApproximatelyEqualError::BrokeTheHardDeck {
+                separation: separation_sq.sqrt(),
+                drift: Some(Box::new(drift)),
+                rules,
+            });
+        }
+        if separation_sq > furthest.0 {
+            furthest = (separation_sq, drift);
+        }
+        budget -= separation_sq;
+        log::debug!(
+            "Nearest {pt_self:?} is {pt_other:?}, {:.2} apart. {}/{} budget remains.",
+            separation_sq.sqrt(),
+            budget,
+            rules.budget
+        );
+        if budget < 0.0 {
+            log::debug!("Fail due to exhausted budget, furthest {}", furthest.1);
+            return Err(ApproximatelyEqualError::ExhaustedBudget(
+                rules,
+                Box::new(furthest.1),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Compare two arbitrary paths, e.g. from SVG or UFO, with no font involved
+///
+/// The caller is responsible for normalization: paths are compared exactly where they are,
+/// so translate them to a common origin and scale them to a common size first, and pick
+/// rules suited to that size. Each sampled point of a is measured against b and, if
+/// [`RulesOfSimilarity::symmetric`], of b against a.
+pub fn paths_about_the_same(
+    a: &BezPath,
+    b: &BezPath,
+    rules: RulesOfSimilarity,
+) -> Result<(), ApproximatelyEqualError> {
+    a.approximately_equal(b, rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use kurbo::{
+        Affine, BezPath, Circle, Line, ParamCurve, ParamCurveNearest, PathSeg, Point, Rect, Shape,
+    };
+
+    use crate::{
+        about_the_same::{
+            approximately_equal_indexed, control_box, nearest, nearest_on_segment,
+            nearly_identical, paths_about_the_same, separations, AboutTheSame,
+            ApproximatelyEqualError, ComparisonMetric, LazySegmentIndex, RulesOfSimilarity,
+            SegmentIndex, DEFAULT_MIN_SAMPLES,
+        },
+        test_fonts::RULES,
+    };
+
+    fn square() -> BezPath {
+        Rect::new(0.0, 0.0, 100.0, 100.0).to_path(0.1)
+    }
+
+    fn transformed(path: &BezPath, transform: Affine) -> BezPath {
+        let mut path = path.clone();
+        path.apply_affine(transform);
+        path
+    }
+
+    #[test]
+    fn indexes_paths_only_when_sampling() {
+        let (a, b) = (
+            square(),
+            transformed(&square(), Affine::translate((1.0, 0.0))),
+        );
+        let (a_index, b_index) = (LazySegmentIndex::default(), LazySegmentIndex::default());
+        approximately_equal_indexed((&a, &a_index), (&a, &b_index), RULES).unwrap();
+        assert_eq!(
+            (false, false),
+            (a_index.0.get().is_some(), b_index.0.get().is_some())
+        );
+
+        approximately_equal_indexed((&a, &a_index), (&b, &b_index), RULES).unwrap();
+        assert_eq!(
+            (true, true),
+            (a_index.0.get().is_some(), b_index.0.get().is_some())
+        );
+        let built = a_index.get(&a) as *const _;
+        approximately_equal_indexed((&a, &a_index), (&b, &b_index), RULES).unwrap();
+        assert!(std::ptr::eq(built, a_index.get(&a)));
+    }
+
+    #[test]
+    fn identical_paths_match() {
+        square().approximately_equal(&square(), RULES).unwrap();
+    }
+
+    #[test]
+    fn translation_within_equivalence_matches() {
+        let moved = transformed(&square(), Affine::translate((1.0, 1.0)));
+        square().approximately_equal(&moved, RULES).unwrap();
+    }
+
+    #[test]
+    fn translation_beyond_equivalence_exhausts_budget() {
+        let moved = transformed(&square(), Affine::translate((5.0, 0.0)));
+        assert!(matches!(
+            square().approximately_equal(&moved, RULES),
+            Err(ApproximatelyEqualError::ExhaustedBudget(..))
+        ));
+    }
+
+    #[test]
+    fn translation_beyond_error_breaks_the_hard_deck() {
+        let moved = transformed(&square(), Affine::translate((30.0, 0.0)));
+        assert!(matches!(
+            square().approximately_equal(&moved, RULES),
+            Err(ApproximatelyEqualError::BrokeTheHardDeck { .. })
+        ));
+    }
+
+    #[test]
+    fn separations_of_translated_square() {
+        let moved = transformed(&square(), Affine::translate((0.0, 3.0)));
+        let separations = separations(&square(), &moved, RULES);
+        // 4 segments, 11 samples each
+        assert_eq!(44, separations.len());
+        assert!(
+            separations.iter().all(|s| *s <= 3.0 + 1e-9),
+            "{separations:?}"
+        );
+        assert!(
+            separations.iter().any(|s| (*s - 3.0).abs() < 1e-9),
+            "{separations:?}"
+        );
+    }
+
+    #[test]
+    fn pruned_nearest_is_within_slack() {
+        let circle = Circle::new((50.0, 50.0), 50.0).to_path(0.1);
+        for p in [(0.0, 50.0), (20.0, 30.0), (120.0, 120.0), (50.0, 50.0)] {
+            let p = Point::from(p);
+            let brute_force = circle
+                .segments()
+                .map(|s| s.nearest(p, 1e-7).distance_sq.sqrt())
+                .fold(f64::MAX, f64::min);
+            let index = SegmentIndex::new(&circle);
+            let exact = (p - nearest(p, &index, 0.0)).length();
+            let slack = (p - nearest(p, &index, 1.0)).length();
+            assert!(
+                (exact - brute_force).abs() < 1e-6,
+                "{p:?} {exact} {brute_force}"
+            );
+            assert!(
+                slack - brute_force < 1.0 + 1e-6,
+                "{p:?} {slack} {brute_force}"
+            );
+        }
+    }
+
+    #[test]
+    fn nearest_on_axis_aligned_lines() {
+        let lines = [
+            Line::new((10.0, 20.0), (90.0, 20.0)),
+            Line::new((90.0, 20.0), (10.0, 20.0)),
+            Line::new((30.0, 80.0), (30.0, -40.0)),
+            // a point
+            Line::new((5.0, 5.0), (5.0, 5.0)),
+        ];
+        for line in lines {
+            let segment = PathSeg::Line(line);
+            for p in [(0.0, 0.0), (50.0, 20.0), (50.0, 60.0), (100.0, -100.0)] {
+                let p = Point::from(p);
+                let (distance, nearest) = nearest_on_segment(p, segment, control_box(segment));
+                let general = segment.nearest(p, 1e-9);
+                assert!(
+                    (distance - general.distance_sq.sqrt()).abs() < 1e-9,
+                    "{line:?} {p:?}"
+                );
+                assert!(
+                    (nearest - segment.eval(general.t)).hypot() < 1e-9,
+                    "{line:?} {p:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn indexed_nearest_matches_brute_force() {
+        // A jagged polygon with enough segments for a deep index
+        let mut seed = 42_u64;
+        let mut next = || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as f64 / (1u64 << 31) as f64
+        };
+        let mut polygon = BezPath::new();
+        for i in 0..300 {
+            let angle = i as f64 / 300.0 * std::f64::consts::TAU;
+            let radius = 200.0 + 100.0 * next();
+            let pt = (500.0 + radius * angle.cos(), 500.0 + radius * angle.sin());
+            if i == 0 {
+                polygon.move_to(pt);
+            } else {
+                polygon.line_to(pt);
+            }
+        }
+        polygon.close_path();
+        let index = SegmentIndex::new(&polygon);
+        assert!(index.nodes.len() > 1);
+
+        for _ in 0..500 {
+            let p = Point::new(1000.0 * next(), 1000.0 * next());
+            let brute_force = polygon
+                .segments()
+                .map(|s| s.nearest(p, 1e-7).distance_sq.sqrt())
+                .fold(f64::MAX, f64::min);
+            let indexed = (p - nearest(p, &index, 0.0)).length();
+            assert!(
+                (indexed - brute_force).abs() < 1e-6,
+                "{p:?} {indexed} {brute_force}"
+            );
+        }
+    }
+
+    #[test]
+    fn budget_is_sum_of_squared_separations_beyond_equivalence() {
+        let moved = transformed(&square(), Affine::translate((3.0, 0.0)));
+        let spent: f64 = separations(&square(), &moved, RULES)
+            .iter()
+            .filter(|s| **s > RULES.equivalence)
+            .map(|s| s * s)
+            .sum();
+        // The left edge is 3 away at all 11 samples, the right edge at all but its
+        // corners, which lie on the moved top and bottom edges, and the top and
+        // bottom edges only at their left corners
+        assert!((spent - 22.0 * 9.0).abs() < 1e-9, "{spent}");
+
+        let rules = |budget| RulesOfSimilarity { budget, ..RULES };
+        square()
+            .approximately_equal(&moved, rules(spent + 1e-6))
+            .unwrap();
+        assert!(matches!(
+            square().approximately_equal(&moved, rules(spent - 1e-6)),
+            Err(ApproximatelyEqualError::ExhaustedBudget(..))
+        ));
+    }
+
+    #[test]
+    fn compares_paths_without_a_font() {
+        let svg = BezPath::from_svg("M0,0 L100,0 L100,100 L0,100 Z").unwrap();
+        paths_about_the_same(&svg, &square(), RULES).unwrap();
+        // not normalized for you
+        let moved = transformed(&square(), Affine::translate((50.0, 0.0)));
+        assert!(paths_about_the_same(&svg, &moved, RULES).is_err());
+    }
+
+    #[test]
+    fn hausdorff_judges_only_the_largest_separation() {
+        let spiked = |height: f64| {
+            let mut path = BezPath::new();
+            path.move_to((0.0, 0.0));
+            path.line_to((100.0, 0.0));
+            path.line_to((100.0, 100.0));
+            path.line_to((55.0, 100.0));
+            path.line_to((50.0, 100.0 + height));
+            path.line_to((45.0, 100.0));
+            path.line_to((0.0, 100.0));
+            path.close_path();
+            path
+        };
+        let hausdorff = RulesOfSimilarity {
+            metric: ComparisonMetric::Hausdorff,
+            ..RULES
+        };
+
+        // a spike under error is too costly for the budget but within the Hausdorff limit
+        assert!(matches!(
+            spiked(20.0).approximately_equal(&square(), RULES),
+            Err(ApproximatelyEqualError::ExhaustedBudget(..))
+        ));
+        spiked(20.0)
+            .approximately_equal(&square(), hausdorff)
+            .unwrap();
+
+        // beyond error the Hausdorff distance is reported
+        let Err(ApproximatelyEqualError::BrokeTheHardDeck { separation, .. }) =
+            square().approximately_equal(&spiked(30.0), hausdorff)
+        else {
+            panic!("Spike beyond error should fail");
+        };
+        assert!((separation - 30.0).abs() < 0.01, "{separation}");
+    }
+
+    #[test]
+    fn differing_extents_fail_without_sampling() {
+        let tall = Rect::new(0.0, 0.0, 100.0, 600.0).to_path(0.1);
+        let short = Rect::new(0.0, 0.0, 100.0, 300.0).to_path(0.1);
+        // sampling would fail at the first point beyond error, not the top of the tall one
+        let Err(ApproximatelyEqualError::BrokeTheHardDeck { separation, .. }) =
+            tall.approximately_equal(&short, RULES)
+        else {
+            panic!("A tall glyph can't match a short one");
+        };
+        assert_eq!(300.0, separation);
+
+        // one way round a path within the other's extents is left to sampling
+        let one_way = RulesOfSimilarity {
+            symmetric: false,
+            ..RULES
+        };
+        assert!(matches!(
+            short.approximately_equal(&tall, one_way),
+            Err(ApproximatelyEqualError::ExhaustedBudget(..))
+        ));
+    }
+
+    #[test]
+    fn reports_where_paths_drift() {
+        // the square with its right side pushed in to a point at 60,60
+        let mut notched = BezPath::new();
+        notched.move_to((0.0, 0.0));
+        notched.line_to((100.0, 0.0));
+        notched.line_to((60.0, 60.0));
+        notched.line_to((100.0, 100.0));
+        notched.line_to((0.0, 100.0));
+        notched.close_path();
+        let hausdorff = RulesOfSimilarity {
+            metric: ComparisonMetric::Hausdorff,
+            ..RULES
+        };
+
+        let Err(e) = square().approximately_equal(&notched, hausdorff) else {
+            panic!("The notch is beyond error");
+        };
+        let drift = e.drift().unwrap();
+        assert_eq!(
+            (Point::new(60.0, 60.0), 1, true),
+            (drift.point, drift.segment, drift.on_other)
+        );
+        assert_eq!(40.0, drift.separation());
+
+        let Err(e) = notched.approximately_equal(&square(), RULES) else {
+            panic!("The notch is beyond error");
+        };
+        assert!(!e.drift().unwrap().on_other, "{e}");
+    }
+
+    #[test]
+    fn contour_counts_differ() {
+        let dotless = Rect::new(0.0, 0.0, 40.0, 120.0).to_path(0.1);
+        let mut dotted = dotless.clone();
+        dotted.extend(Rect::new(0.0, 160.0, 40.0, 200.0).to_path(0.1));
+        let counted = RulesOfSimilarity {
+            same_contour_count: true,
+            ..RULES
+        };
+
+        assert!(matches!(
+            dotless.approximately_equal(&dotted, RULES),
+            Err(ApproximatelyEqualError::BrokeTheHardDeck { .. })
+        ));
+        assert!(matches!(
+            dotless.approximately_equal(&dotted, counted),
+            Err(ApproximatelyEqualError::ContourCountMismatch(1, 2))
+        ));
+        dotted
+            .approximately_equal(&dotted.clone(), counted)
+            .unwrap();
+    }
+
+    #[test]
+    fn long_segments_are_sampled_densely() {
+        // a long line, and one with a narrow bump that falls between every tenth of it
+        let mut line = BezPath::new();
+        line.move_to((0.0, 0.0));
+        line.line_to((1000.0, 0.0));
+        let mut bumped = BezPath::new();
+        bumped.move_to((0.0, 0.0));
+        bumped.line_to((130.0, 0.0));
+        bumped.line_to((150.0, 20.0));
+        bumped.line_to((170.0, 0.0));
+        bumped.line_to((1000.0, 0.0));
+
+        // measured one way the bump is only seen if the line is sampled near it
+        let one_way = RulesOfSimilarity {
+            symmetric: false,
+            ..RULES
+        };
+        let eleven_per_segment = RulesOfSimilarity {
+            min_samples: 11,
+            max_samples: 11,
+            ..one_way
+        };
+        line.approximately_equal(&bumped, eleven_per_segment)
+            .unwrap();
+        assert!(matches!(
+            line.approximately_equal(&bumped, one_way),
+            Err(ApproximatelyEqualError::ExhaustedBudget(..))
+        ));
+
+        // short segments take fewer samples
+        assert_eq!(
+            4 * DEFAULT_MIN_SAMPLES,
+            separations(
+                &Rect::new(0.0, 0.0, 5.0, 5.0).to_path(0.1),
+                &square(),
+                RULES
+            )
+            .len()
+        );
+    }
+
+    #[test]
+    fn symmetric_rules_measure_both_ways() {
+        // Every point of the square lies on square_and_dot but not the other way round
+        let mut square_and_dot = square();
+        square_and_dot.extend(Rect::new(200.0, 200.0, 210.0, 210.0).to_path(0.1));
+        let one_way = RulesOfSimilarity {
+            symmetric: false,
+            ..RULES
+        };
+        square()
+            .approximately_equal(&square_and_dot, one_way)
+            .unwrap();
+        assert!(square_and_dot
+            .approximately_equal(&square(), one_way)
+            .is_err());
+
+        assert!(square()
+            .approximately_equal(&square_and_dot, RULES)
+            .is_err());
+        assert!(square_and_dot
+            .approximately_equal(&square(), RULES)
+            .is_err());
+    }
+
+    #[test]
+    fn nearly_identical_paths() {
+        let nudged = transformed(&square(), Affine::translate((1e-9, 0.0)));
+        let moved = transformed(&square(), Affine::translate((1.0, 0.0)));
+        let mut mirrored = square();
+        mirrored.apply_affine(Affine::FLIP_X);
+        assert!(nearly_identical(&square(), &square()));
+        assert!(nearly_identical(&square(), &nudged));
+        assert!(!nearly_identical(&square(), &moved));
+        assert!(!nearly_identical(&square(), &mirrored));
+        // not identical, yet still the same
+        square().approximately_equal(&moved, RULES).unwrap();
+    }
+
+    #[test]
+    fn scaling() {
+        let slightly = transformed(&square(), Affine::scale(1.01));
+        let greatly = transformed(&square(), Affine::scale(1.5));
+        square().approximately_equal(&slightly, RULES).unwrap();
+        assert!(square().approximately_equal(&greatly, RULES).is_err());
+    }
+
+    #[test]
+    fn empty_versus_nonempty() {
+        assert!(matches!(
+            square().approximately_equal(&BezPath::new(), RULES),
+            Err(ApproximatelyEqualError::EmptinessMismatch)
+        ));
+        assert!(matches!(
+            BezPath::new().approximately_equal(&square(), RULES),
+            Err(ApproximatelyEqualError::EmptinessMismatch)
+        ));
+    }
+
+    #[test]
+    fn differing_contour_counts_can_match() {
+        // A thin bar, once as a single contour and once as two abutting halves
+        let whole = Rect::new(0.0, 0.0, 200.0, 4.0).to_path(0.1);
+        let mut halves = Rect::new(0.0, 0.0, 100.0, 4.0).to_path(0.1);
+        halves.extend(Rect::new(100.0, 0.0, 200.0, 4.0).to_path(0.1));
+        assert_ne!(
+            whole.elements().len(),
+            halves.elements().len(),
+            "Should be structurally different"
+        );
+
+        whole.approximately_equal(&halves, RULES).unwrap();
+        halves.approximately_equal(&whole, RULES).unwrap();
+    }
+
+    #[test]
+    fn for_upem_decides_the_same_at_any_upem() {
+        let moved = |scale: f64| {
+            transformed(
+                &square(),
+                Affine::scale(scale).then_translate((3.0 * scale, 0.0).into()),
+            )
+        };
+        let big_square = transformed(&square(), Affine::scale(2.0));
+        // spends 198 of the budget at 1000 upem, see above
+        for budget in [150.0, 250.0] {
+            let rules = RulesOfSimilarity { budget, ..RULES };
+            assert_eq!(
+                square().approximately_equal(&moved(1.0), rules).is_ok(),
+                big_square
+                    .approximately_equal(&moved(2.0), rules.for_upem(2000))
+                    .is_ok(),
+                "{budget}"
+            );
+        }
+    }
+
+    #[test]
+    fn for_upem_ignores_absurd_upem() {
+        for upem in [0, 1, 65535] {
+            let rules = RULES.for_upem(upem);
+            assert_eq!(
+                (RULES.equivalence, RULES.budget, RULES.error),
+                (rules.equivalence, rules.budget, rules.error),
+                "{upem}"
+            );
         }
-        Ok(())
     }
 }
@@ -1,14 +1,26 @@
 use std::{
-    collections::HashSet,
-    fs::File,
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fs::{self, File},
     io::{self, BufRead},
-    path::PathBuf,
+    num::ParseIntError,
+    path::{Path, PathBuf},
     str::FromStr,
 };
 
-use clap::{command, Parser};
+use clap::{Parser, ValueEnum};
 
-use crate::about_the_same::RulesOfSimilarity;
+use skrifa::Tag;
+use thiserror::Error;
+
+use crate::{
+    about_the_same::{
+        ComparisonMetric, RulesOfSimilarity, DEFAULT_MAX_SAMPLES, DEFAULT_MIN_SAMPLES,
+        DEFAULT_SAMPLES_PER_1000_UNITS,
+    },
+    letterform::{glyph_name_char, CreateOptions},
+};
 
 /// Reduced https://github.com/googlefonts/glyphsets/blob/main/Lib/glyphsets/definitions/nam/GF_Latin_Core.nam
 const DEFAULT_TEST_STRING: &str = r#"abcdefghijklmnopqrstuvwxyz \
@@ -18,38 +30,186 @@ const DEFAULT_TEST_STRING: &str = r#"abcdefghijklmnopqrstuvwxyz \
 
 const DEFAULT_WORKING_DIR: &str = "build";
 
+/// Subdirectory of the working dir where drawn outlines are kept between runs
+const OUTLINE_CACHE_DIR: &str = "outlines";
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
+    /// Sets --equivalence, --budget and --error together, any of which may still be given
+    /// to override it: strict 0.5/25/5, normal 2/100/25, loose 4/400/50
+    #[arg(long, value_enum)]
+    #[clap(default_value_t = Preset::Normal)]
+    pub preset: Preset,
+
     /// How near the nearest point must be to count as the same when comparing letterforms.
     ///
-    /// Relative to 1000 upem. Default seems shockingly high but reflects actual observed results
+    /// Relative to 1000 upem. Default, from --preset, seems shockingly high but reflects
+    /// actual observed results
     ///
     /// Even very visually similar families have diffs up to 2.5 or so.
     #[arg(long)]
-    #[clap(default_value_t = 2.0)]
-    pub equivalence: f64,
+    pub equivalence: Option<f64>,
 
     /// If the sum of squared distance to nearest for all points exceeds budget consider the letterforms
-    /// different. Relative to 1000 upem.
+    /// different. Relative to 1000 upem. Default from --preset.
+    #[arg(long)]
+    pub budget: Option<f64>,
+
+    /// If any nearest test point is further apart than this consider the letterforms different.
+    /// Default from --preset.
+    #[arg(long)]
+    pub error: Option<f64>,
+
+    /// If set, letterforms whose advance widths differ by more than this are considered different
+    /// without comparing outlines. Relative to 1000 upem.
+    ///
+    /// Off by default as legitimately respaced copies exist.
+    #[arg(long)]
+    pub prefilter_advance: Option<f64>,
+
+    /// Diagnostic: compare outlines exactly as drawn, without scaling to a common upem or
+    /// moving them to the origin. For checking suspected exact copies, including position.
+    ///
+    /// WARNING: thresholds are then in font units, so their meaning depends on upem.
     #[arg(long)]
-    #[clap(default_value_t = 100.0)]
-    pub budget: f64,
+    pub raw: bool,
 
-    /// If any nearest test point is further apart than this consider the letterforms different
+    /// If set, characters a font maps to .notdef count as missing from it. Some fonts map
+    /// codepoints they don't support to a tofu box, which would match every other font's box.
     #[arg(long)]
-    #[clap(default_value_t = 25.0)]
-    pub error: f64,
+    pub ignore_notdef: bool,
+
+    /// Letterforms are scaled to the largest upem of any font. If that exceeds this, scale to
+    /// this instead, bounding coordinate magnitudes at a small cost in precision.
+    ///
+    /// Protects against one huge upem font dictating normalization for the whole run.
+    #[arg(long)]
+    pub max_upem: Option<u16>,
+
+    /// How many threads to extract letterforms with, defaults to the number of CPUs
+    #[arg(long)]
+    pub threads: Option<usize>,
+
+    /// If set, drop contours whose bounding box diagonal is less than this before comparing.
+    /// Relative to 1000 upem.
+    ///
+    /// Keeps slivers and overlap artifacts from causing false negatives.
+    #[arg(long)]
+    pub ignore_tiny_contours: Option<f64>,
+
+    /// If set, replace overlapping contours with their union before comparing so overlapping
+    /// and merged drawings of the same shape match. Slow.
+    #[arg(long)]
+    pub remove_overlaps: bool,
+
+    /// If set, scale each letterform to the same height before comparing so the same shape
+    /// drawn at a different size, say at 80% of the cap height, matches.
+    ///
+    /// Comparison is then blind to size: a bold that's simply a scaled regular, small caps
+    /// and scaled copies all merge with what they were scaled from.
+    #[arg(long)]
+    pub normalize_scale: bool,
+
+    /// If set, scale each font so its cap height is 0.7 em before comparing, rather than
+    /// only scaling to a common upem, so designs drawn at different sizes within the em match.
+    ///
+    /// Cap height comes from OS/2, else the top of H, I, E, T, Z or X as drawn. Fonts with
+    /// neither are only scaled for upem, with a warning. Ignored with --raw.
+    #[arg(long)]
+    pub normalize_cap_height: bool,
+
+    /// When searching for the nearest point skip segments that could be at most this much
+    /// closer than the best found so far. Relative to 1000 upem.
+    ///
+    /// Faster but may overstate separations by up to this much; keep it well below --equivalence.
+    #[arg(long)]
+    #[clap(default_value_t = 0.0)]
+    pub nearest_slack: f64,
+
+    /// If set, only measure each letterform against the one it's compared to, not also the
+    /// other way round. Faster, but whether two letterforms match may then depend on the
+    /// order fonts are compared in.
+    #[arg(long)]
+    #[clap(default_value_t = false)]
+    pub asymmetric: bool,
+
+    /// If set, letterforms with different numbers of contours differ without comparing
+    /// outlines. Fast, but the same shape drawn with overlapping contours no longer matches.
+    #[arg(long)]
+    pub require_same_contour_count: bool,
+
+    /// How separations between letterforms are judged: budget, the default, pays for each
+    /// separation beyond --equivalence from --budget, hausdorff only requires the largest
+    /// separation to be within --error
+    #[arg(long, value_enum)]
+    #[clap(default_value_t = ComparisonMetric::Budget)]
+    pub metric: ComparisonMetric,
+
+    /// How many points to sample per 1000 units of each outline segment's length when
+    /// comparing letterforms, between 3 and 101 per segment. Relative to 1000 upem.
+    ///
+    /// Higher catches narrower differences along long strokes but is slower.
+    #[arg(long)]
+    #[clap(default_value_t = DEFAULT_SAMPLES_PER_1000_UNITS)]
+    pub samples_per_1000_units: f64,
 
     /// If this percentage of the unique characters in --test-string match consider font(s) to match
     #[arg(long)]
     #[clap(default_value_t = 80.0)]
     pub match_pct: f64,
 
-    /// Compare these characters to detect duplication
+    /// If set, weight each char by how many distinct designs it has across all fonts so
+    /// matching on rarely-varied chars, like 'l', counts for less. --match-pct then applies
+    /// to the weighted score.
+    #[arg(long)]
+    #[clap(default_value_t = false)]
+    pub weight_by_diversity: bool,
+
+    /// If set, weight chars as given, e.g. a=2,U+2E=0.2, or, with a leading @, as a JSON
+    /// object in the named file, e.g. @weights.json holding {"a": 2, ".": 0.2}. Unlisted
+    /// chars weigh 1. --match-pct then applies to the weighted score. Combines with
+    /// --weight-by-diversity by multiplying.
+    ///
+    /// Chars may be given as U+hex, necessary for , and =. Double a leading @ to weight @
+    /// itself, e.g. @@=2.
+    #[arg(long)]
+    pub char_weights: Option<CharWeights>,
+
+    /// Waive up to this many mismatched chars per group of fonts, scoring the rest against
+    /// --match-pct, so a copy with a few redrawn glyphs is still reported
+    ///
+    /// The waived chars are listed with each group.
     #[arg(long)]
-    #[clap(default_value_t = DEFAULT_TEST_STRING.to_string())]
-    test_string: String,
+    #[clap(default_value_t = 0)]
+    pub allow_diff: usize,
+
+    /// Only report groups of at least this many fonts, e.g. to look for large clusters of
+    /// clones. Groups are never smaller than 2.
+    #[arg(long)]
+    #[clap(default_value_t = 2)]
+    pub min_group_size: usize,
+
+    /// If set, only compare the test characters present in every font so every font is
+    /// scored on the same characters
+    #[arg(long)]
+    pub intersect_charset: bool,
+
+    /// Compare these characters to detect duplication. Defaults to Latin letters, digits
+    /// and punctuation unless --test-range is given.
+    ///
+    /// A leading @ reads the characters from a file instead, e.g. --test-string @chars.txt.
+    /// Whitespace in the file is ignored. Double a leading @ to test it literally, e.g.
+    /// --test-string '@@#$' tests @, # and $.
+    #[arg(long)]
+    test_string: Option<TestString>,
+
+    /// Comma separated codepoint ranges to compare as well as --test-string or --test-nam,
+    /// e.g. U+0041-005A,U+0061-007A,U+00C0-00FF. A single codepoint, U+00E9, is fine too.
+    ///
+    /// Malformed entries are skipped with a warning.
+    #[arg(long)]
+    test_range: Option<String>,
 
     /// Use .nam file as source of test string. If set, overrides --test-string.
     ///
@@ -57,19 +217,256 @@ pub struct Args {
     #[arg(long)]
     test_nam: Option<String>,
 
+    /// Comma separated glyph names to compare in addition to the test characters, e.g.
+    /// --glyph-names "a.alt,dollar.oldstyle"
+    ///
+    /// Names are looked up in `post`, or the CFF charset, so unencoded glyphs such as
+    /// alternates can be compared. Fonts without a glyph of a given name are treated as
+    /// missing it. Names stand in as private use chars from U+F0000, test chars they
+    /// would collide with are skipped.
+    #[arg(long, visible_alias = "test-glyph-names")]
+    glyph_names: Option<String>,
+
+    /// If set, compare the form of each test char reached by GSUB single substitutions
+    /// rather than the glyph the cmap maps it to, e.g. the isolated form of an Arabic letter
+    ///
+    /// Single substitutions only, this is not shaping: multiple, ligature and contextual
+    /// lookups, and combining marks, are not handled.
+    #[arg(long)]
+    pub single_subst_forms: bool,
+
+    /// Comma separated OpenType features whose single substitutions to apply, e.g.
+    /// --features smcp,onum or --features init to compare initial forms. Requires
+    /// --single-subst-forms.
+    #[arg(long)]
+    features: Option<FeatureTags>,
+
+    /// File of named character buckets, one per line as name=characters, e.g. digits=0123456789.
+    ///
+    /// Each bucket is scored against --match-pct separately and reported alongside the overall score.
+    /// Characters not in the test set are ignored.
+    #[arg(long)]
+    buckets: Option<String>,
+
+    /// If set, every bucket must also clear --match-pct for font(s) to match
+    #[arg(long)]
+    pub require_all_buckets: bool,
+
+    /// If set, report whether the vertical metrics (ascender, descender, line gap, cap height)
+    /// of each reported group agree, along with the values.
+    ///
+    /// Shared metrics plus shared outlines is stronger evidence of duplication than outlines alone.
+    #[arg(long)]
+    pub compare_metrics: bool,
+
+    /// If set, report how many distinct designs of each test character exist and which fonts
+    /// use each, most popular first.
+    ///
+    /// Combine with --test-string to focus on specific characters, e.g. --test-string a.
+    #[arg(long)]
+    pub designs: bool,
+
     /// If set, for each unique character in --test-string write an svg showing variants
     #[arg(long)]
     pub dump_glyphs: bool,
 
+    /// If set, write a CSV of every pair of letterforms compared while grouping, with the
+    /// largest separation and the budget spent: char,font_a,font_b,max_separation,budget_used,passed,drift.
+    ///
+    /// For pairs that failed at a point, drift is where: the sample point, the segment it's
+    /// on, of font_a (self) or font_b (other), and the nearest point of the other.
+    ///
+    /// Meant for tuning --equivalence, --budget and --error. There may be O(fonts²) rows.
+    #[arg(long)]
+    pub dump_scores: Option<PathBuf>,
+
+    /// If set, write index.html to the working dir: for each character an overlay of every
+    /// variant, colored by font, and the fonts in each group. Inconsistent characters, those
+    /// with more than one group, are flagged.
+    #[arg(long)]
+    pub dump_html: bool,
+
     /// If set, write down the sets of files and common glyphs
     #[arg(long)]
     pub dump_groups: bool,
 
+    /// If set, for each distinct design of each character write a clean svg of one
+    /// normalized letterform, named representative_{char}.{group}.svg
+    #[arg(long)]
+    pub dump_representatives: bool,
+
+    /// If set, for each reported group write an svg specimen of the matching characters
+    #[arg(long)]
+    pub dump_clusters: bool,
+
+    /// If set, write one row per pair of fonts meeting --match-pct to this file as tab separated
+    /// font_a, font_b, matched characters, and score (matched / total test characters).
+    ///
+    /// Suitable for loading into graph tools. Tabs, newlines and backslashes in paths are
+    /// backslash escaped.
+    #[arg(long)]
+    pub dump_edges: Option<PathBuf>,
+
+    /// If set, write a CSV histogram of the separations between sample points of letterforms
+    /// that grouped together, per character, to this file. For tuning --equivalence and --error.
+    ///
+    /// Bins are 0.5 units wide, relative to 1000 upem, up to --error. The last bin also
+    /// counts anything larger.
+    #[arg(long)]
+    pub separation_histogram: Option<PathBuf>,
+
+    /// If set, write a CSV with one row per font: path, upem before scaling, how many test
+    /// chars it has, how many it lacks or why it was skipped entirely, and whether it is in
+    /// any reported group
+    #[arg(long)]
+    pub coverage_csv: Option<PathBuf>,
+
+    /// If set, also print the N pairs of fonts with the most matching letterforms, even
+    /// those below --match-pct, for manual review
+    #[arg(long)]
+    pub top: Option<usize>,
+
+    /// If set, also print the font most like each font and whether they are similar enough
+    /// to be duplicates, to show near misses and how isolated each design is
+    #[arg(long)]
+    #[clap(default_value_t = false)]
+    pub nearest_neighbors: bool,
+
+    /// If set, follow each reported group with a short explanation of why it was reported:
+    /// what matched, how closely, and what normalization it took
+    #[arg(long)]
+    #[clap(default_value_t = false)]
+    pub explain_group: bool,
+
+    /// How to print the reported groups: text lines, or a JSON array of objects with files,
+    /// matched_chars, total_chars and score_pct. For json, the summary of settings is
+    /// omitted and timings go to stderr.
+    #[arg(long, value_enum)]
+    #[clap(default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Where to write the report, in whichever --format. - is stdout. Files written to the
+    /// working dir, such as --dump-glyphs, are unaffected.
+    #[arg(long)]
+    #[clap(default_value = "-")]
+    pub output: PathBuf,
+
+    /// If set, log how many fonts have been processed every so often, to stderr
+    #[arg(long)]
+    pub progress: bool,
+
+    /// Log more: -v for info, -vv for debug, -vvv for trace. RUST_LOG, if set, wins.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Log nothing, not even errors. The report is still printed. RUST_LOG, if set, wins.
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// If set, write the reported groups to this file as JSON, for use with --diff
+    #[arg(long)]
+    pub json_report: Option<PathBuf>,
+
+    /// If set, print the JSON Schema of --json-report output and exit
+    #[arg(long)]
+    #[clap(default_value_t = false)]
+    pub json_schema: bool,
+
+    /// If set, compare the reported groups to those in this JSON report from a previous run,
+    /// printing the groups that were added, removed, or changed score as JSON
+    #[arg(long)]
+    pub diff: Option<PathBuf>,
+
     /// Where to read/write temp files. Retention can accelerate repeat executions.
     #[arg(long)]
     #[clap(default_value_t = DEFAULT_WORKING_DIR.to_string())]
     pub working_dir: String,
 
+    /// Don't reuse, or keep, outlines drawn by previous runs in the working dir
+    ///
+    /// Outlines are cached in <working_dir>/outlines, one file per font and options used,
+    /// and deleted once unused for 30 days.
+    #[arg(long)]
+    pub no_outline_cache: bool,
+
+    /// Write the normalized letterforms to this file for reuse by --import-outlines
+    #[arg(long)]
+    pub export_outlines: Option<String>,
+
+    /// Read normalized letterforms written by --export-outlines instead of loading fonts.
+    ///
+    /// Useful to rerun with different thresholds. Ignored, with a warning, if written by a
+    /// different version of find_dups.
+    #[arg(long)]
+    pub import_outlines: Option<String>,
+
+    /// Compare the named instances of this variable font against each other instead of
+    /// comparing fonts. Useful to QA that instances that should differ actually do.
+    ///
+    /// Reports the percentage of test characters matching for each pair of instances.
+    #[arg(long)]
+    pub self_instances: Option<PathBuf>,
+
+    /// Compare every static font and every named instance of every variable font as a separate
+    /// unit, rather than one exemplar per family. Catches families that copied only some weights.
+    ///
+    /// Instances are named path#instance, e.g. Family[wght].ttf#Bold. Results are also
+    /// summarized by family.
+    #[arg(long)]
+    pub all_instances: bool,
+
+    /// Compare variable fonts at this location rather than their default, in user space
+    /// coordinates, e.g. wght=700,wdth=100. Axes a font lacks are ignored.
+    ///
+    /// A value may be a range, start:end:step, e.g. wght=100:900:100, to compare at every
+    /// step. Letterforms must then match at all of them to group and where they first
+    /// diverged is reported.
+    #[arg(long)]
+    pub instance: Option<AxisLocations>,
+
+    /// If set, letterforms that match once rotated by 90, 180 or 270 degrees are grouped
+    /// together and the rotation reported. Meant for symbol fonts.
+    #[arg(long)]
+    pub detect_rotation: bool,
+
+    /// If set, letterforms that match once scaled horizontally to the same aspect ratio are
+    /// grouped together and the scale reported, as they suggest a condensed or extended
+    /// derivative rather than an exact copy
+    #[arg(long)]
+    pub normalize_aspect: bool,
+
+    /// If set, annotate each reported group with the designers declared in METADATA.pb and
+    /// flag groups spanning more than one designer, which are more suspicious
+    #[arg(long)]
+    pub flag_cross_designer: bool,
+
+    /// A font whose shapes are considered the default. Groups whose letterforms match it
+    /// are not counted, as sharing a ubiquitous shape says little about duplication.
+    #[arg(long)]
+    pub baseline: Option<PathBuf>,
+
+    /// If set, fail if any family in --google-fonts is skipped because no exemplar could be picked
+    #[arg(long)]
+    pub strict_exemplar: bool,
+
+    /// If set, skip outline comparison entirely and just report sets of fonts that cover
+    /// exactly the same test characters. A very fast first pass for spotting forks.
+    #[arg(long)]
+    pub charset_only: bool,
+
+    /// EXPERIMENTAL: instead of comparing fonts, compare each uppercase test character to its
+    /// lowercase scaled to the same height, within each font, and report those that match.
+    ///
+    /// Finds fonts whose lowercase is just shrunken uppercase.
+    #[arg(long)]
+    pub experimental_faux_lowercase: bool,
+
+    /// Directory of existing fonts, searched recursively. If set, each input font is
+    /// compared only against these, never against other inputs, and its best match is
+    /// reported. E.g. to check whether a new vendor's fonts copy an existing library.
+    #[arg(long)]
+    pub against: Option<PathBuf>,
+
     /// Path to repository containing subdirectories with font families.
     ///
     /// E.g. clone https://github.com/google/fonts to sibling dir "fonts" then
@@ -77,11 +474,77 @@ pub struct Args {
     #[arg(long)]
     google_fonts: Option<String>,
 
+    /// If set, --google-fonts also picks an italic exemplar from each family, preferring
+    /// Family-Italic, rather than ignoring -Italic files. Families with only italics are
+    /// then compared too.
+    #[arg(long)]
+    pub include_italic: bool,
+
+    /// Read font paths from this file, - for stdin, one per line, in addition to any given
+    /// as arguments. Blank lines and lines starting with # are ignored.
+    #[arg(long)]
+    files_from: Option<PathBuf>,
+
     #[arg(trailing_var_arg = true, allow_hyphen_values = true, hide = true)]
-    files: Vec<String>,
+    files: Vec<PathBuf>,
+}
+
+/// A codepoint written as U+hex
+fn parse_codepoint(s: &str) -> Result<u32, String> {
+    let s = s.trim();
+    let hex = s
+        .strip_prefix("U+")
+        .or_else(|| s.strip_prefix("u+"))
+        .unwrap_or(s);
+    u32::from_str_radix(hex, 16).map_err(|e| format!("{s:?} is not a codepoint: {e}"))
+}
+
+/// The chars in a --test-range, skipping, with a warning, anything malformed
+fn parse_test_range(spec: &str) -> Vec<char> {
+    let mut chars = Vec::new();
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let range = match entry.split_once('-') {
+            Some((start, end)) => parse_codepoint(start).and_then(|start| {
+                let end = parse_codepoint(end)?;
+                if start > end {
+                    return Err(format!("{entry:?} ends before it starts"));
+                }
+                Ok(start..=end)
+            }),
+            None => parse_codepoint(entry).map(|cp| cp..=cp),
+        };
+        let range = match range {
+            Ok(range) => range,
+            Err(e) => {
+                log::warn!("Ignoring --test-range entry {entry:?}: {e}");
+                continue;
+            }
+        };
+        let len = chars.len();
+        chars.extend(range.clone().filter_map(char::from_u32));
+        let skipped = range.count() - (chars.len() - len);
+        if skipped > 0 {
+            log::warn!(
+                "Ignoring {skipped} codepoints of --test-range entry {entry:?} that aren't chars"
+            );
+        }
+    }
+    chars
 }
 
-fn parse_nam_line(line: &str) -> Option<char> {
+/// A line of a .nam file that isn't blank, a comment, or 0x followed by a codepoint
+#[derive(Error, Debug, PartialEq)]
+pub enum NamError {
+    #[error("{0:?} doesn't start with 0x")]
+    NoPrefix(String),
+    #[error("{0:?} isn't a hex codepoint: {1}")]
+    NotHex(String, ParseIntError),
+    #[error("0x{0:04X} isn't a char")]
+    NotAChar(u32),
+}
+
+/// The char of a line of a .nam file, None if there isn't one
+fn parse_nam_line(line: &str) -> Result<Option<char>, NamError> {
     let raw_codepoint = if let Some(cut) = line.find('#') {
         &line[..cut]
     } else {
@@ -89,54 +552,239 @@ fn parse_nam_line(line: &str) -> Option<char> {
     }
     .trim();
     if raw_codepoint.is_empty() {
-        return None;
+        return Ok(None);
     }
-    if !raw_codepoint.starts_with("0x") {
-        log::warn!("Invalid nam line: {line}");
+    let Some(hex) = raw_codepoint.strip_prefix("0x") else {
+        return Err(NamError::NoPrefix(line.to_string()));
+    };
+    let hex = hex
+        .split_once(|c: char| c.is_ascii_whitespace())
+        .map_or(hex, |(hex, _)| hex);
+    let codepoint =
+        u32::from_str_radix(hex, 16).map_err(|e| NamError::NotHex(hex.to_string(), e))?;
+    char::from_u32(codepoint)
+        .map(Some)
+        .ok_or(NamError::NotAChar(codepoint))
+}
+
+/// A named subset of the test characters, scored separately
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucket {
+    pub name: String,
+    pub chars: Vec<char>,
+}
+
+impl Bucket {
+    /// The number of chars that must match for this bucket to pass
+    pub fn limit(&self, match_pct: f64) -> usize {
+        (self.chars.len() as f64 * match_pct / 100.0).ceil() as usize
+    }
+}
+
+fn parse_bucket_line(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
         return None;
     }
-    let l = if let Some(cut) = raw_codepoint.find(|c: char| c.is_ascii_whitespace()) {
-        &raw_codepoint[2..cut]
-    } else {
-        &raw_codepoint[2..]
+    let Some((name, chars)) = line.split_once('=') else {
+        log::warn!("Invalid bucket line: {line}");
+        return None;
     };
-    let codepoint = u32::from_str_radix(l, 16).expect("Bad nam");
-    Some(char::from_u32(codepoint).expect("Bad codepoint"))
+    Some((name.trim(), chars.trim()))
+}
+
+fn parse_path_line(line: &str) -> Option<PathBuf> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    Some(PathBuf::from(line))
 }
 
 impl Args {
+    /// The log level set by --verbose or --quiet, if either was given
+    pub fn log_level(&self) -> Option<log::LevelFilter> {
+        if self.quiet {
+            return Some(log::LevelFilter::Off);
+        }
+        match self.verbose {
+            0 => None,
+            1 => Some(log::LevelFilter::Info),
+            2 => Some(log::LevelFilter::Debug),
+            _ => Some(log::LevelFilter::Trace),
+        }
+    }
+
+    /// Equivalence, budget and error: the --preset, overridden by any given explicitly
+    pub fn tolerances(&self) -> (f64, f64, f64) {
+        let (equivalence, budget, error) = self.preset.tolerances();
+        (
+            self.equivalence.unwrap_or(equivalence),
+            self.budget.unwrap_or(budget),
+            self.error.unwrap_or(error),
+        )
+    }
+
     pub fn rules(&self) -> RulesOfSimilarity {
+        let (equivalence, budget, error) = self.tolerances();
         RulesOfSimilarity {
-            equivalence: self.equivalence,
-            budget: self.budget,
-            error: self.error,
+            equivalence,
+            budget,
+            error,
+            advance_tolerance: self.prefilter_advance,
+            nearest_slack: self.nearest_slack,
+            symmetric: !self.asymmetric,
+            metric: self.metric,
+            samples_per_1000_units: self.samples_per_1000_units,
+            min_samples: DEFAULT_MIN_SAMPLES,
+            max_samples: DEFAULT_MAX_SAMPLES,
+            same_contour_count: self.require_same_contour_count,
+        }
+    }
+
+    /// How to create letterforms, see [`create_letterforms`](crate::letterform::create_letterforms)
+    pub fn create_options(&self) -> CreateOptions {
+        let mut locations = self
+            .instance
+            .as_ref()
+            .map(AxisLocations::locations)
+            .unwrap_or_default()
+            .into_iter();
+        CreateOptions {
+            glyph_names: self.glyph_names(),
+            max_upem: self.max_upem,
+            all_instances: self.all_instances,
+            location: locations.next().unwrap_or_default(),
+            more_locations: locations.collect(),
+            raw: self.raw,
+            ignore_notdef: self.ignore_notdef,
+            single_subst_forms: self.single_subst_forms,
+            features: self.features(),
+            metrics_cap_height: self.compare_metrics,
+            normalize_cap_height: self.normalize_cap_height,
+            cache_dir: (!self.no_outline_cache)
+                .then(|| Path::new(&self.working_dir).join(OUTLINE_CACHE_DIR)),
         }
     }
 
-    // Returns unique, sorted, test characters
+    // Returns unique, sorted, test characters, including stand-ins for --glyph-names
     pub fn test_chars(&self) -> Vec<char> {
         let mut test_chars = if let Some(test_nam) = &self.test_nam {
             io::BufReader::new(File::open(test_nam).expect("Unable to read .nam"))
                 .lines()
-                .filter_map(|l| parse_nam_line(l.as_deref().expect("To read nam lines")))
+                .filter_map(|l| {
+                    parse_nam_line(l.as_deref().expect("To read nam lines"))
+                        .inspect_err(|e| log::warn!("Skipping invalid nam line, {e}"))
+                        .ok()
+                        .flatten()
+                })
                 .collect::<HashSet<_>>()
         } else {
-            self.test_string.chars().collect::<HashSet<_>>()
+            let test_string = match &self.test_string {
+                Some(test_string) => test_string.0.as_str(),
+                None if self.test_range.is_some() => "",
+                None => DEFAULT_TEST_STRING,
+            };
+            test_string.chars().collect::<HashSet<_>>()
+        };
+        if let Some(spec) = &self.test_range {
+            test_chars.extend(parse_test_range(spec));
         }
-        .into_iter()
-        .collect::<Vec<_>>();
+        // real chars that collide with a glyph name stand-in would be compared as that glyph
+        let stand_ins = (0..self.glyph_names().len())
+            .map(glyph_name_char)
+            .collect::<Vec<_>>();
+        test_chars.retain(|c| {
+            let collides = stand_ins.contains(c);
+            if collides {
+                log::warn!(
+                    "Skipping U+{:04X}, it's reserved to stand in for --glyph-names",
+                    *c as u32
+                );
+            }
+            !collides
+        });
+        let mut test_chars = test_chars.into_iter().collect::<Vec<_>>();
+        test_chars.extend(stand_ins);
         test_chars.sort();
         test_chars
     }
 
-    pub fn font_files(&self) -> HashSet<PathBuf> {
+    /// The feature tags given by --features
+    pub fn features(&self) -> Vec<Tag> {
+        self.features
+            .as_ref()
+            .map(|features| features.0.clone())
+            .unwrap_or_default()
+    }
+
+    /// The glyph names to compare, see [`glyph_name_char`]
+    pub fn glyph_names(&self) -> Vec<String> {
+        self.glyph_names
+            .iter()
+            .flat_map(|names| names.split(','))
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect()
+    }
+
+    /// Returns the configured buckets, restricted to the given test characters
+    pub fn buckets(&self, test_chars: &[char]) -> Vec<Bucket> {
+        let Some(buckets) = &self.buckets else {
+            return Vec::new();
+        };
+        io::BufReader::new(File::open(buckets).expect("Unable to read buckets"))
+            .lines()
+            .filter_map(|l| {
+                let l = l.expect("To read bucket lines");
+                let (name, chars) = parse_bucket_line(&l)?;
+                let mut chars = chars
+                    .chars()
+                    .filter(|c| !c.is_whitespace())
+                    .collect::<HashSet<_>>()
+                    .into_iter()
+                    .filter(|c| test_chars.contains(c))
+                    .collect::<Vec<_>>();
+                if chars.is_empty() {
+                    log::warn!("Bucket {name} has no test characters, ignoring it");
+                    return None;
+                }
+                chars.sort();
+                Some(Bucket {
+                    name: name.to_string(),
+                    chars,
+                })
+            })
+            .collect()
+    }
+
+    pub fn font_files(&self) -> FontFiles {
         let mut files = HashSet::new();
-        for file in self.files.iter() {
-            let path = PathBuf::from_str(file).unwrap();
+        let mut families_without_exemplar = Vec::new();
+        for path in self.files.iter() {
             if !path.is_file() {
                 panic!("{path:?} is not a file");
             }
-            files.insert(path);
+            files.insert(path.clone());
+        }
+        if let Some(list) = &self.files_from {
+            let reader: Box<dyn BufRead> = if list == Path::new("-") {
+                Box::new(io::stdin().lock())
+            } else {
+                Box::new(io::BufReader::new(File::open(list).unwrap_or_else(|e| {
+                    panic!("Unable to read --files-from {list:?}: {e}")
+                })))
+            };
+            for line in reader.lines() {
+                let line = line.unwrap_or_else(|e| panic!("Unable to read {list:?}: {e}"));
+                let Some(path) = parse_path_line(&line) else {
+                    continue;
+                };
+                if !path.is_file() {
+                    panic!("{path:?} is not a file");
+                }
+                files.insert(path);
+            }
         }
         if let Some(google_fonts) = &self.google_fonts {
             let mut google_fonts = google_fonts.to_owned();
@@ -144,67 +792,706 @@ impl Args {
                 google_fonts.push('/');
             }
             google_fonts.push_str("**/METADATA.pb");
-            for metadata_file in glob::glob(&google_fonts).unwrap() {
-                let metadata_file = metadata_file.unwrap();
-                let font_dir = metadata_file.parent().unwrap().to_str().unwrap().to_owned();
-                let font_pattern = font_dir + "/*.[ot]tf";
-
-                let mut font_files: Vec<_> = glob::glob(&font_pattern)
-                    .unwrap()
-                    .filter_map(|f| {
-                        let f = f.unwrap();
-                        if f.file_name().unwrap().to_str().unwrap().contains("-Italic") {
-                            return None;
-                        }
-                        Some(f)
-                    })
-                    .collect();
-                if font_files.len() == 1 {
-                    // most VFs should take this path: max 2 files and -Italic was eliminated
-                    let exemplar = font_files.pop().unwrap();
-                    log::debug!("Picked {:?} as exemplar", exemplar);
-                    files.insert(exemplar);
-                } else if let Some(exemplar) = font_files.iter().find(|f| {
-                    // Many static families follow this pattern
-                    f.file_name()
-                        .unwrap()
-                        .to_str()
-                        .unwrap()
-                        .contains("-Regular")
-                }) {
-                    log::debug!("Picked {:?} as exemplar", exemplar);
-                    files.insert(exemplar.clone());
-                } else if let Some(exemplar) = font_files.iter().find(|f| {
-                    // Some old static families just have FamilyName.ttf not FamilyName-Regular.ttf
-                    // For example, Thabit.ttf, Thabit-Bold.ttf, etc
-                    !f.file_name().unwrap().to_str().unwrap().contains("-")
-                }) {
+            for metadata_file in glob::glob(&google_fonts).expect("A valid --google-fonts") {
+                let metadata_file = match metadata_file {
+                    Ok(metadata_file) => metadata_file,
+                    Err(e) => {
+                        log::warn!("Unable to access {e}");
+                        continue;
+                    }
+                };
+                let font_dir = metadata_file.parent().unwrap_or(Path::new("."));
+
+                let present = fonts_in(font_dir);
+                let (italic, upright): (Vec<_>, Vec<_>) = present
+                    .iter()
+                    .cloned()
+                    .partition(|f| file_name(f).contains("-Italic"));
+                let mut styles = vec![upright];
+                if self.include_italic {
+                    styles.push(italic);
+                }
+                styles.retain(|s| !s.is_empty());
+                if self.all_instances {
+                    let font_files = styles.into_iter().flatten().collect::<Vec<_>>();
+                    log::debug!("Taking all {} fonts in {font_dir:?}", font_files.len());
+                    files.extend(font_files);
+                    continue;
+                }
+                let exemplars = styles
+                    .iter()
+                    .filter_map(|font_files| pick_exemplar(font_files))
+                    .collect::<Vec<_>>();
+                if exemplars.is_empty() {
+                    log::warn!("Unable to identify an exemplar from {font_dir:?}");
+                    families_without_exemplar.push(MissingExemplar {
+                        dir: font_dir.to_path_buf(),
+                        files: present,
+                    });
+                }
+                for exemplar in exemplars {
                     log::debug!("Picked {:?} as exemplar", exemplar);
                     files.insert(exemplar.clone());
-                } else {
-                    log::warn!("Unable to identify an exemplar from {font_pattern}");
                 }
             }
         }
-        files
+        FontFiles {
+            files,
+            families_without_exemplar,
+        }
+    }
+}
+
+/// The font that best represents a family, from fonts of the same style (upright or italic)
+fn pick_exemplar(font_files: &[PathBuf]) -> Option<&PathBuf> {
+    if let [exemplar] = font_files {
+        // most VFs should take this path: max 2 files, one per style
+        return Some(exemplar);
+    }
+    font_files
+        .iter()
+        // Many static families follow this pattern
+        .find(|f| file_name(f).contains("-Regular"))
+        .or_else(|| {
+            // Some old static families just have FamilyName.ttf not FamilyName-Regular.ttf
+            // For example, Thabit.ttf, Thabit-Bold.ttf, etc
+            font_files.iter().find(|f| !file_name(f).contains('-'))
+        })
+        .or_else(|| {
+            // The italic of the regular weight, e.g. FamilyName-Italic.ttf
+            font_files.iter().find(|f| {
+                f.file_stem()
+                    .is_some_and(|s| s.to_string_lossy().ends_with("-Italic"))
+            })
+        })
+}
+
+/// The fonts to compare
+#[derive(Debug, Default)]
+pub struct FontFiles {
+    pub files: HashSet<PathBuf>,
+    /// Families in --google-fonts that were skipped because no exemplar could be picked
+    pub families_without_exemplar: Vec<MissingExemplar>,
+}
+
+/// The most locations --instance may expand to
+const MAX_INSTANCE_LOCATIONS: usize = 64;
+
+/// OpenType feature tags, e.g. smcp,onum
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureTags(pub Vec<Tag>);
+
+impl FromStr for FeatureTags {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| {
+                Tag::new_checked(tag.as_bytes())
+                    .map_err(|e| format!("Invalid feature tag {tag:?}: {e}"))
+            })
+            .collect::<Result<_, _>>()
+            .map(FeatureTags)
+    }
+}
+
+/// User space axis coordinates, parsed from tag=value pairs separated by commas
+///
+/// A value may be a range, start:end:step, in which case there is a location for every
+/// step, and for every combination of steps if several axes are ranges.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AxisLocations(pub Vec<(Tag, Vec<f32>)>);
+
+impl AxisLocations {
+    /// Every location, varying the last axis fastest
+    pub fn locations(&self) -> Vec<Vec<(Tag, f32)>> {
+        self.0
+            .iter()
+            .fold(vec![Vec::new()], |locations, (tag, values)| {
+                locations
+                    .iter()
+                    .flat_map(|location| {
+                        values.iter().map(move |value| {
+                            let mut location = location.clone();
+                            location.push((*tag, *value));
+                            location
+                        })
+                    })
+                    .collect()
+            })
+    }
+}
+
+/// The values of an axis, a single value or start:end:step
+fn parse_axis_values(tag: Tag, value: &str) -> Result<Vec<f32>, String> {
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<f32>()
+            .map_err(|e| format!("Invalid value for {tag}: {e}"))
+    };
+    let parts = value.split(':').collect::<Vec<_>>();
+    let [start, end, step] = parts[..] else {
+        if parts.len() == 1 {
+            return Ok(vec![parse(value)?]);
+        }
+        return Err(format!(
+            "{value:?} for {tag} is not a value or start:end:step"
+        ));
+    };
+    let (start, end, step) = (parse(start)?, parse(end)?, parse(step)?);
+    if step <= 0.0 || end < start {
+        return Err(format!(
+            "{value:?} for {tag} needs a positive step and start no greater than end"
+        ));
+    }
+    // tolerate end being a hair off a step
+    let steps = ((end - start) / step + 1e-4).floor() as usize + 1;
+    if steps > MAX_INSTANCE_LOCATIONS {
+        return Err(format!(
+            "{value:?} for {tag} has {steps} steps, at most {MAX_INSTANCE_LOCATIONS} are allowed"
+        ));
+    }
+    Ok((0..steps).map(|i| start + i as f32 * step).collect())
+}
+
+impl FromStr for AxisLocations {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let axes = s
+            .split(',')
+            .map(|setting| {
+                let (tag, value) = setting
+                    .split_once('=')
+                    .ok_or_else(|| format!("{setting:?} is not tag=value"))?;
+                let tag = Tag::new_checked(tag.trim().as_bytes())
+                    .map_err(|e| format!("Invalid axis tag {tag:?}: {e}"))?;
+                Ok((tag, parse_axis_values(tag, value)?))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let num_locations = axes
+            .iter()
+            .map(|(_, values)| values.len())
+            .product::<usize>();
+        if num_locations > MAX_INSTANCE_LOCATIONS {
+            return Err(format!(
+                "{s:?} is {num_locations} locations, at most {MAX_INSTANCE_LOCATIONS} are allowed"
+            ));
+        }
+        Ok(AxisLocations(axes))
+    }
+}
+
+/// The characters given to --test-string, read from a file if it starts with a single @
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestString(pub String);
+
+impl FromStr for TestString {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('@') {
+            Some(literal) if literal.starts_with('@') => Ok(TestString(literal.to_string())),
+            Some(file) => fs::read_to_string(file)
+                .map(|chars| TestString(chars.chars().filter(|c| !c.is_whitespace()).collect()))
+                .map_err(|e| format!("Unable to read test string from {file:?}: {e}")),
+            None => Ok(TestString(s.to_string())),
+        }
+    }
+}
+
+/// How much each char counts towards the score, see --char-weights
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CharWeights(pub HashMap<char, f64>);
+
+impl CharWeights {
+    /// The weight of c, 1 if not given
+    pub fn get(&self, c: char) -> f64 {
+        self.0.get(&c).copied().unwrap_or(1.0)
+    }
+}
+
+impl FromStr for CharWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let weights = if let Some(file) = s.strip_prefix('@').filter(|f| !f.starts_with('@')) {
+            let json =
+                fs::read_to_string(file).map_err(|e| format!("Unable to read {file:?}: {e}"))?;
+            serde_json::from_str::<HashMap<char, f64>>(&json)
+                .map_err(|e| format!("{file:?} is not a JSON object of char to weight: {e}"))?
+        } else {
+            let s = s.strip_prefix('@').unwrap_or(s);
+            s.split(',')
+                .map(|setting| {
+                    let (c, weight) = setting
+                        .split_once('=')
+                        .ok_or_else(|| format!("{setting:?} is not char=weight"))?;
+                    let c = match c.trim() {
+                        c if c.chars().count() == 1 => c.chars().next().unwrap(),
+                        c => {
+                            let cp = parse_codepoint(c)?;
+                            char::from_u32(cp).ok_or_else(|| format!("{c:?} is not a char"))?
+                        }
+                    };
+                    let weight = weight
+                        .trim()
+                        .parse::<f64>()
+                        .map_err(|e| format!("Invalid weight for {c:?}: {e}"))?;
+                    Ok((c, weight))
+                })
+                .collect::<Result<_, String>>()?
+        };
+        if let Some((c, weight)) = weights.iter().find(|(_, w)| !w.is_finite() || **w < 0.0) {
+            return Err(format!("Weight {weight} for {c:?} must be at least 0"));
+        }
+        Ok(CharWeights(weights))
+    }
+}
+
+/// Named sets of tolerances, see --preset
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Near exact copies only
+    Strict,
+    Normal,
+    /// Also lightly edited copies, at the risk of merging similar designs
+    Loose,
+}
+
+impl Preset {
+    /// Equivalence, budget and error, relative to 1000 upem
+    pub fn tolerances(self) -> (f64, f64, f64) {
+        match self {
+            Preset::Strict => (0.5, 25.0, 5.0),
+            Preset::Normal => (2.0, 100.0, 25.0),
+            Preset::Loose => (4.0, 400.0, 50.0),
+        }
+    }
+}
+
+/// How the reported groups are printed, see --format
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// A family directory none of whose fonts could be picked to represent it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingExemplar {
+    pub dir: PathBuf,
+    /// The font files that were present
+    pub files: Vec<PathBuf>,
+}
+
+/// The file name of path, lossily converted for matching against
+fn file_name(path: &Path) -> Cow<'_, str> {
+    path.file_name().unwrap_or_default().to_string_lossy()
+}
+
+/// The .ttf, .otf, .ttc, .woff and .woff2 files within dir and its subdirectories, sorted
+pub fn fonts_under(dir: &Path) -> Vec<PathBuf> {
+    let mut fonts = fonts_in(dir);
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                fonts.extend(fonts_under(&entry.path()));
+            }
+        }
     }
+    fonts.sort();
+    fonts
+}
+
+/// The .ttf, .otf, .ttc, .woff and .woff2 files directly within dir, sorted
+fn fonts_in(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!("Unable to read {dir:?}: {e}");
+            return Vec::new();
+        }
+    };
+    let mut fonts = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.is_file()
+                && matches!(
+                    p.extension().and_then(OsStr::to_str),
+                    Some("ttf") | Some("otf") | Some("ttc") | Some("woff") | Some("woff2")
+                )
+        })
+        .collect::<Vec<_>>();
+    fonts.sort();
+    fonts
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::args::parse_nam_line;
+    use std::{
+        collections::{BTreeSet, HashMap},
+        fs,
+        path::PathBuf,
+    };
+
+    use kurbo::{Rect, Shape};
+    use skrifa::Tag;
+
+    use crate::args::{
+        parse_bucket_line, parse_nam_line, parse_path_line, parse_test_range, Args, CharWeights,
+        MissingExemplar, NamError,
+    };
+    use crate::{
+        letterform::{create_letterforms, group_letterforms, share_counts},
+        test_fonts::build_font,
+    };
+    use clap::Parser;
 
     #[test]
     fn parse_nam_lines() {
         assert_eq!(
-            vec![None, None, None, Some('a'), Some('B')],
+            vec![
+                Ok(None),
+                Ok(None),
+                Err(NamError::NoPrefix("00A0".to_string())),
+                Ok(Some('a')),
+                Ok(Some('B')),
+                Err(NamError::NotAChar(0xD800)),
+            ],
             vec![
                 parse_nam_line(""),
                 parse_nam_line("\t#duck"),
                 parse_nam_line("00A0"),
                 parse_nam_line("0x61"),
                 parse_nam_line("0x0042 DESC # mallard"),
+                parse_nam_line("0xD800 # a lone surrogate"),
+            ]
+        );
+        assert!(matches!(
+            parse_nam_line("0xDUCK"),
+            Err(NamError::NotHex(hex, _)) if hex == "DUCK"
+        ));
+    }
+
+    #[test]
+    fn parse_bucket_lines() {
+        assert_eq!(
+            vec![
+                None,
+                None,
+                None,
+                Some(("digits", "0123")),
+                Some(("lower", "abc"))
+            ],
+            vec![
+                parse_bucket_line(""),
+                parse_bucket_line("  # comment"),
+                parse_bucket_line("nope"),
+                parse_bucket_line("digits=0123"),
+                parse_bucket_line(" lower = abc "),
+            ]
+        )
+    }
+
+    #[test]
+    fn parse_char_weights() {
+        let weights = "a=2, U+2C=0.2".parse::<CharWeights>().unwrap();
+        assert_eq!(
+            (2.0, 0.2, 1.0),
+            (weights.get('a'), weights.get(','), weights.get('b'))
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("weights.json");
+        fs::write(&file, r#"{"a": 2, ".": 0.5}"#).unwrap();
+        let weights = format!("@{}", file.to_str().unwrap())
+            .parse::<CharWeights>()
+            .unwrap();
+        assert_eq!((2.0, 0.5), (weights.get('a'), weights.get('.')));
+        // only a leading @ means a file
+        assert!(file.to_str().unwrap().parse::<CharWeights>().is_err());
+        assert_eq!(3.0, "@@=3".parse::<CharWeights>().unwrap().get('@'));
+
+        assert!("a".parse::<CharWeights>().is_err());
+        assert!("a=-1".parse::<CharWeights>().is_err());
+    }
+
+    #[test]
+    fn presets() {
+        let tolerances =
+            |args: &[&str]| Args::parse_from(["find_dups"].iter().chain(args.iter())).tolerances();
+        assert_eq!((2.0, 100.0, 25.0), tolerances(&[]));
+        assert_eq!((0.5, 25.0, 5.0), tolerances(&["--preset", "strict"]));
+        assert_eq!((2.0, 100.0, 25.0), tolerances(&["--preset", "normal"]));
+        assert_eq!((4.0, 400.0, 50.0), tolerances(&["--preset", "loose"]));
+        assert_eq!(
+            (0.5, 60.0, 5.0),
+            tolerances(&["--preset", "strict", "--budget", "60"])
+        );
+        assert_eq!((3.0, 100.0, 25.0), tolerances(&["--equivalence", "3"]));
+    }
+
+    #[test]
+    fn test_range() {
+        let args = Args::parse_from(["find_dups", "--test-range", "U+0041-0043, u+0061"]);
+        assert_eq!(vec!['A', 'B', 'C', 'a'], args.test_chars());
+
+        // unions with --test-string
+        let args = Args::parse_from([
+            "find_dups",
+            "--test-string",
+            "xa",
+            "--test-range",
+            "U+0061-U+0062",
+        ]);
+        assert_eq!(vec!['a', 'b', 'x'], args.test_chars());
+    }
+
+    #[test]
+    fn glyph_name_stand_ins_are_unique() {
+        let args = Args::parse_from([
+            "find_dups",
+            "--test-string",
+            "a",
+            "--test-range",
+            "U+F0000-F0002",
+            "--glyph-names",
+            "a.alt,b.alt",
+        ]);
+        assert_eq!(
+            vec!['a', '\u{F0000}', '\u{F0001}', '\u{F0002}'],
+            args.test_chars()
+        );
+    }
+
+    #[test]
+    fn malformed_test_range() {
+        assert_eq!(
+            vec!['A', 'z', '\u{D7FF}', '\u{E000}'],
+            parse_test_range("U+0041,U+ZZ,U+0043-0041,,U+007A,U+D7FF-E000,U+110000")
+        );
+    }
+
+    #[test]
+    fn test_string_from_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let chars = tmp.path().join("chars.txt");
+        fs::write(&chars, "cab\n a\r\n").unwrap();
+        let test_string = format!("@{}", chars.to_str().unwrap());
+        let args = Args::parse_from(["find_dups", "--test-string", &test_string]);
+        assert_eq!(vec!['a', 'b', 'c'], args.test_chars());
+
+        let args = Args::parse_from(["find_dups", "--test-string", "@@#$"]);
+        assert_eq!(vec!['#', '$', '@'], args.test_chars());
+
+        let missing = format!("@{}", tmp.path().join("nope.txt").to_str().unwrap());
+        assert!(Args::try_parse_from(["find_dups", "--test-string", &missing]).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_font_files() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let family = tmp.path().join("ofl").join("family");
+        fs::create_dir_all(&family).unwrap();
+        fs::write(family.join("METADATA.pb"), "").unwrap();
+        let font_file = family.join(OsStr::from_bytes(b"Fam\xffily-Regular.ttf"));
+        fs::write(&font_file, "").unwrap();
+        assert!(font_file.to_str().is_none());
+
+        let args = Args::parse_from([
+            "find_dups".as_ref(),
+            "--google-fonts".as_ref(),
+            tmp.path().as_os_str(),
+            font_file.as_os_str(),
+        ]);
+        assert_eq!(
+            vec![&font_file],
+            args.font_files().files.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn parse_instance() {
+        let args = Args::parse_from(["find_dups", "--instance", "wght=700, wdth=87.5"]);
+        assert_eq!(
+            vec![(Tag::new(b"wght"), 700.0), (Tag::new(b"wdth"), 87.5)],
+            args.create_options().location
+        );
+        assert!(Args::try_parse_from(["find_dups", "--instance", "wght"]).is_err());
+        assert!(Args::try_parse_from(["find_dups", "--instance", "wght=bold"]).is_err());
+        assert!(Args::try_parse_from(["find_dups", "--instance", "toolong=1"]).is_err());
+    }
+
+    #[test]
+    fn parse_instance_ranges() {
+        let args = Args::parse_from(["find_dups", "--instance", "wght=100:900:400,wdth=75:100:25"]);
+        let options = args.create_options();
+        let (wght, wdth) = (Tag::new(b"wght"), Tag::new(b"wdth"));
+        assert_eq!(vec![(wght, 100.0), (wdth, 75.0)], options.location);
+        assert_eq!(
+            vec![
+                vec![(wght, 100.0), (wdth, 100.0)],
+                vec![(wght, 500.0), (wdth, 75.0)],
+                vec![(wght, 500.0), (wdth, 100.0)],
+                vec![(wght, 900.0), (wdth, 75.0)],
+                vec![(wght, 900.0), (wdth, 100.0)],
+            ],
+            options.more_locations
+        );
+        for bad in [
+            "wght=900:100:100",
+            "wght=100:900:0",
+            "wght=100:900",
+            "wght=1:1000:1",
+        ] {
+            assert!(
+                Args::try_parse_from(["find_dups", "--instance", bad]).is_err(),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn files_from() {
+        assert_eq!(
+            vec![None, None, None, Some(PathBuf::from("a b.ttf"))],
+            vec![
+                parse_path_line(""),
+                parse_path_line(" \t"),
+                parse_path_line("  # Family-Regular.ttf"),
+                parse_path_line(" a b.ttf \r"),
             ]
+        );
+
+        let tmp = tempfile::tempdir().unwrap();
+        let fonts = ["A.ttf", "B.ttf"].map(|f| tmp.path().join(f));
+        for font in fonts.iter() {
+            fs::write(font, "").unwrap();
+        }
+        let list = tmp.path().join("fonts.txt");
+        fs::write(
+            &list,
+            format!(
+                "# the fonts\n\n{}\n   \n{}\n",
+                fonts[0].display(),
+                fonts[1].display()
+            ),
         )
+        .unwrap();
+
+        let args = Args::parse_from([
+            "find_dups".as_ref(),
+            "--files-from".as_ref(),
+            list.as_os_str(),
+            fonts[0].as_os_str(),
+        ]);
+        let mut files = args.font_files().files.into_iter().collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(fonts.to_vec(), files);
+    }
+
+    #[test]
+    fn google_fonts_end_to_end() {
+        let tmp = tempfile::tempdir().unwrap();
+        let bar = Rect::new(0.0, 0.0, 100.0, 600.0).to_path(0.1);
+        for family in ["original", "copy"] {
+            let dir = tmp.path().join("ofl").join(family);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("METADATA.pb"), "").unwrap();
+            fs::write(
+                dir.join(format!("{family}-Regular.ttf")),
+                build_font(1000, &[('l', bar.clone())]),
+            )
+            .unwrap();
+        }
+
+        let args = Args::parse_from([
+            "find_dups".as_ref(),
+            "--google-fonts".as_ref(),
+            tmp.path().as_os_str(),
+            "--test-string".as_ref(),
+            "l".as_ref(),
+            "--no-outline-cache".as_ref(),
+        ]);
+        let raw_fonts = args
+            .font_files()
+            .files
+            .into_iter()
+            .map(|f| {
+                let bytes = fs::read(&f).unwrap();
+                (f, bytes)
+            })
+            .collect::<HashMap<_, _>>();
+        let test_chars = args.test_chars();
+        let letterforms =
+            create_letterforms(&test_chars, &raw_fonts, &args.create_options()).unwrap();
+        let groups = group_letterforms(args.rules(), &test_chars, &letterforms).unwrap();
+        let share_counts = share_counts(&groups, &letterforms, args.min_group_size);
+        assert_eq!(
+            vec![&BTreeSet::from(['l'])],
+            share_counts.values().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn include_italic() {
+        let tmp = tempfile::tempdir().unwrap();
+        let family = tmp.path().join("ofl").join("family");
+        fs::create_dir_all(&family).unwrap();
+        fs::write(family.join("METADATA.pb"), "").unwrap();
+        let font = family.join("Fam-Italic[wght].ttf");
+        fs::write(&font, "").unwrap();
+
+        let args = Args::parse_from([
+            "find_dups".as_ref(),
+            "--google-fonts".as_ref(),
+            tmp.path().as_os_str(),
+        ]);
+        let font_files = args.font_files();
+        assert!(font_files.files.is_empty());
+        assert_eq!(1, font_files.families_without_exemplar.len());
+
+        let args = Args::parse_from([
+            "find_dups".as_ref(),
+            "--google-fonts".as_ref(),
+            tmp.path().as_os_str(),
+            "--include-italic".as_ref(),
+        ]);
+        let font_files = args.font_files();
+        assert_eq!(vec![&font], font_files.files.iter().collect::<Vec<_>>());
+        assert!(font_files.families_without_exemplar.is_empty());
+    }
+
+    #[test]
+    fn families_without_exemplar() {
+        let tmp = tempfile::tempdir().unwrap();
+        let family = tmp.path().join("ofl").join("family");
+        fs::create_dir_all(&family).unwrap();
+        fs::write(family.join("METADATA.pb"), "").unwrap();
+        let fonts = ["Fam-Bold.ttf", "Fam-Light.ttf"].map(|f| family.join(f));
+        for font in fonts.iter() {
+            fs::write(font, "").unwrap();
+        }
+
+        let args = Args::parse_from([
+            "find_dups".as_ref(),
+            "--google-fonts".as_ref(),
+            tmp.path().as_os_str(),
+        ]);
+        let font_files = args.font_files();
+        assert!(font_files.files.is_empty());
+        assert_eq!(
+            vec![MissingExemplar {
+                dir: family,
+                files: fonts.to_vec()
+            }],
+            font_files.families_without_exemplar
+        );
     }
 }
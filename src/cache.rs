@@ -0,0 +1,221 @@
+//! Version stamping, keying, and pruning of files we persist between runs
+//!
+//! Anything cached or exported must not be silently reused by a binary that would
+//! compute it differently, so every such file starts with a [`CacheStamp`].
+
+use std::{
+    fs, io,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+/// Bump whenever outline extraction, normalization, or comparison changes such that
+/// previously persisted results would be wrong.
+pub const SCHEMA_VERSION: u32 = 2;
+
+const STAMP_PREFIX: &str = "find_dups";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheStamp {
+    pub crate_version: String,
+    pub schema_version: u32,
+}
+
+impl CacheStamp {
+    /// The stamp of the running binary
+    pub fn current() -> Self {
+        Self {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            schema_version: SCHEMA_VERSION,
+        }
+    }
+
+    fn header(&self) -> String {
+        format!(
+            "{STAMP_PREFIX} {} schema {}",
+            self.crate_version, self.schema_version
+        )
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.split_ascii_whitespace();
+        if parts.next() != Some(STAMP_PREFIX) {
+            return None;
+        }
+        let crate_version = parts.next()?.to_string();
+        if parts.next() != Some("schema") {
+            return None;
+        }
+        let schema_version = parts.next()?.parse().ok()?;
+        Some(Self {
+            crate_version,
+            schema_version,
+        })
+    }
+}
+
+/// Prefix content with the current stamp
+pub fn stamp(content: &str) -> String {
+    format!("{}\n{content}", CacheStamp::current().header())
+}
+
+/// Strip the stamp from content, returning None if it wasn't written by this version
+pub fn unstamp(content: &str) -> Option<&str> {
+    let (header, content) = content.split_once('\n').unwrap_or((content, ""));
+    let stamp = CacheStamp::parse(header)?;
+    (stamp == CacheStamp::current()).then_some(content)
+}
+
+/// Write content to path preceded by the current stamp
+pub fn write_stamped(path: &Path, content: &str) -> Result<(), io::Error> {
+    fs::write(path, stamp(content))
+}
+
+/// Read a file written by [`write_stamped`]
+///
+/// Returns None, with a warning, if the file is absent or was written by a different
+/// version or schema. Callers are expected to recompute whatever it held.
+pub fn read_stamped(path: &Path) -> Result<Option<String>, io::Error> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path)?;
+    let content = unstamp(&content).map(str::to_string);
+    if content.is_none() {
+        log::warn!(
+            "{} was written by a different version of find_dups, recomputing. Expected {:?}.",
+            path.display(),
+            CacheStamp::current()
+        );
+    }
+    Ok(content)
+}
+
+/// Mark a cached file as used, so [`prune`] keeps it
+pub fn touch(path: &Path) -> Result<(), io::Error> {
+    fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(SystemTime::now())
+}
+
+/// Delete the files in dir that haven't been written or [`touch`]ed for max_age
+///
+/// Returns how many were deleted.
+pub fn prune(dir: &Path, max_age: Duration) -> Result<usize, io::Error> {
+    let Some(cutoff) = SystemTime::now().checked_sub(max_age) else {
+        return Ok(0);
+    };
+    let mut pruned = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() && metadata.modified()? < cutoff {
+            fs::remove_file(entry.path())?;
+            pruned += 1;
+        }
+    }
+    Ok(pruned)
+}
+
+/// 64 bit FNV-1a, for keys of files we persist
+///
+/// Unlike [`std::hash::DefaultHasher`] its output is fixed, so keys written by one
+/// build are found by the next. Values are written as explicit bytes, rather than
+/// through [`std::hash::Hash`], whose output isn't guaranteed stable either.
+#[derive(Debug, Clone, Copy)]
+pub struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl StableHasher {
+    pub fn write(&mut self, bytes: &[u8]) -> &mut Self {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+        self
+    }
+
+    pub fn write_u64(&mut self, value: u64) -> &mut Self {
+        self.write(&value.to_le_bytes())
+    }
+
+    /// Write bytes preceded by their length, so adjacent values can't run together
+    pub fn write_len_prefixed(&mut self, bytes: &[u8]) -> &mut Self {
+        self.write_u64(bytes.len() as u64).write(bytes)
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        time::{Duration, SystemTime},
+    };
+
+    use crate::cache::{prune, stamp, unstamp, CacheStamp, StableHasher};
+
+    #[test]
+    fn stamp_round_trips() {
+        assert_eq!(Some("a\nb"), unstamp(&stamp("a\nb")));
+    }
+
+    #[test]
+    fn rejects_mismatched_stamps() {
+        let mut old = CacheStamp::current();
+        old.schema_version -= 1;
+        let stale = format!("{}\nstuff", old.header());
+
+        let mut other = CacheStamp::current();
+        other.crate_version = "0.0.0-stale".to_string();
+        let other = format!("{}\nstuff", other.header());
+
+        assert_eq!(
+            vec![None, None, None],
+            vec![unstamp(&stale), unstamp(&other), unstamp("stuff")]
+        );
+    }
+
+    #[test]
+    fn stable_hashes() {
+        // Published FNV-1a test vectors, these must never change
+        assert_eq!(0xcbf29ce484222325, StableHasher::default().finish());
+        assert_eq!(
+            0xaf63dc4c8601ec8c,
+            StableHasher::default().write(b"a").finish()
+        );
+        assert_eq!(
+            0x85944171f73967e8,
+            StableHasher::default().write(b"foobar").finish()
+        );
+    }
+
+    #[test]
+    fn prunes_unused_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let used = dir.path().join("used");
+        let unused = dir.path().join("unused");
+        for file in [&used, &unused] {
+            fs::write(file, "").unwrap();
+        }
+        let long_ago = SystemTime::now() - Duration::from_secs(3600);
+        fs::File::options()
+            .write(true)
+            .open(&unused)
+            .unwrap()
+            .set_modified(long_ago)
+            .unwrap();
+
+        assert_eq!(1, prune(dir.path(), Duration::from_secs(60)).unwrap());
+        assert_eq!((true, false), (used.exists(), unused.exists()));
+    }
+}
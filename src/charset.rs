@@ -0,0 +1,99 @@
+//! Crude, very fast, grouping of fonts by the characters they support, ignoring outlines
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+};
+
+use skrifa::{FontRef, MetadataProvider};
+
+use crate::letterform::{faces, LetterformsError};
+
+/// The chars of test_chars that font maps to a glyph
+pub fn covered_chars(font: &FontRef, test_chars: &[char]) -> BTreeSet<char> {
+    let charmap = font.charmap();
+    test_chars
+        .iter()
+        .copied()
+        .filter(|c| charmap.map(*c).is_some())
+        .collect()
+}
+
+/// Fonts that cover exactly the same chars
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharsetGroup {
+    pub chars: BTreeSet<char>,
+    pub fonts: BTreeSet<PathBuf>,
+}
+
+/// Fonts that cover exactly the same test chars, most fonts first
+///
+/// Only sets of two or more fonts are returned.
+pub fn group_by_charset(
+    test_chars: &[char],
+    raw_fonts: &HashMap<PathBuf, Vec<u8>>,
+) -> Result<Vec<CharsetGroup>, LetterformsError> {
+    let mut groups: HashMap<BTreeSet<char>, BTreeSet<PathBuf>> = HashMap::new();
+    for (path, font) in raw_fonts
+        .iter()
+        .flat_map(|(path, bytes)| faces(path, bytes))
+    {
+        let font = font.map_err(|e| LetterformsError::Load(path.clone(), e))?;
+        groups
+            .entry(covered_chars(&font, test_chars))
+            .or_default()
+            .insert(path);
+    }
+    let mut groups = groups
+        .into_iter()
+        .filter(|(_, fonts)| fonts.len() > 1)
+        .map(|(chars, fonts)| CharsetGroup { chars, fonts })
+        .collect::<Vec<_>>();
+    groups.sort_by(|a, b| {
+        b.fonts
+            .len()
+            .cmp(&a.fonts.len())
+            .then_with(|| a.fonts.cmp(&b.fonts))
+    });
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeSet, HashMap},
+        path::PathBuf,
+    };
+
+    use kurbo::{Rect, Shape};
+
+    use crate::{
+        charset::{group_by_charset, CharsetGroup},
+        test_fonts::build_font,
+    };
+
+    #[test]
+    fn groups_fonts_with_the_same_coverage() {
+        let bar = Rect::new(0.0, 0.0, 100.0, 600.0).to_path(0.1);
+        let dot = Rect::new(0.0, 0.0, 100.0, 100.0).to_path(0.1);
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_font(1000, &[('l', bar.clone()), ('.', dot.clone())]),
+            ),
+            // different outlines, same coverage
+            (
+                PathBuf::from("b.ttf"),
+                build_font(1000, &[('l', dot.clone()), ('.', bar.clone())]),
+            ),
+            (PathBuf::from("c.ttf"), build_font(1000, &[('l', bar)])),
+        ]);
+        assert_eq!(
+            vec![CharsetGroup {
+                chars: BTreeSet::from(['.', 'l']),
+                fonts: BTreeSet::from([PathBuf::from("a.ttf"), PathBuf::from("b.ttf")])
+            }],
+            group_by_charset(&['.', 'l', 'x'], &raw_fonts).unwrap()
+        );
+    }
+}
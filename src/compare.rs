@@ -0,0 +1,147 @@
+//! Compare two fonts directly, for embedding rather than scanning a collection
+
+use skrifa::{instance::LocationRef, raw::TableProvider, FontRef};
+
+use crate::{
+    about_the_same::{separations, RulesOfSimilarity},
+    letterform::{Comparison, Letterform, LetterformError},
+};
+
+/// How the letterforms of one char compare
+#[derive(Debug)]
+pub struct CharReport {
+    pub c: char,
+    pub comparison: Comparison,
+    /// The largest separation between the letterforms, in either direction. None if
+    /// either is missing.
+    pub max_separation: Option<f64>,
+    /// The budget spent by the worse direction, the sum of squared separations beyond
+    /// equivalence. None if either is missing.
+    pub budget_spent: Option<f64>,
+}
+
+impl CharReport {
+    pub fn matched(&self) -> bool {
+        matches!(self.comparison, Comparison::Match)
+    }
+}
+
+/// The result of [`compare_fonts`]
+#[derive(Debug)]
+pub struct CompareReport {
+    /// The upem both fonts were scaled to, separations are in these units
+    pub upem: u16,
+    /// The rules used, scaled to upem
+    pub rules: RulesOfSimilarity,
+    /// One per char compared, in the order given
+    pub chars: Vec<CharReport>,
+}
+
+impl CompareReport {
+    /// The chars whose letterforms match
+    pub fn matched(&self) -> impl Iterator<Item = char> + '_ {
+        self.chars.iter().filter(|c| c.matched()).map(|c| c.c)
+    }
+}
+
+/// The largest separation between a and b, in either direction, and the budget spent by
+/// the worse direction. None if either is missing.
+///
+/// Every point is measured, unlike [`Comparison`] which stops at the first that fails, so
+/// this is for reporting rather than deciding.
+pub fn score(a: &Letterform, b: &Letterform, rules: RulesOfSimilarity) -> Option<(f64, f64)> {
+    if a.is_missing() || b.is_missing() {
+        return None;
+    }
+    let forward = separations(a.path(), b.path(), rules);
+    let backward = separations(b.path(), a.path(), rules);
+    let spent = |separations: &[f64]| {
+        separations
+            .iter()
+            .filter(|s| **s > rules.equivalence)
+            .fold(0.0, |spent, s| spent + s * s)
+    };
+    Some((
+        forward.iter().chain(&backward).copied().fold(0.0, f64::max),
+        spent(&forward).max(spent(&backward)),
+    ))
+}
+
+/// Compare the letterforms of chars in a and b, normalized as when scanning a collection
+///
+/// Both fonts are scaled to the larger upem and rules, relative to 1000 upem, are scaled
+/// to match.
+pub fn compare_fonts(
+    a: &FontRef,
+    b: &FontRef,
+    chars: &[char],
+    rules: RulesOfSimilarity,
+) -> Result<CompareReport, LetterformError> {
+    let upem_a = a.head().map_err(LetterformError::Head)?.units_per_em();
+    let upem_b = b.head().map_err(LetterformError::Head)?.units_per_em();
+    let upem = upem_a.max(upem_b);
+    let rules = rules.for_upem(upem);
+
+    let mut reports = Vec::with_capacity(chars.len());
+    for c in chars.iter().copied() {
+        let letterform_a =
+            Letterform::create(a, c, upem as f64 / upem_a as f64, LocationRef::default())?;
+        let letterform_b =
+            Letterform::create(b, c, upem as f64 / upem_b as f64, LocationRef::default())?;
+        let scores = score(&letterform_a, &letterform_b, rules);
+        reports.push(CharReport {
+            c,
+            comparison: letterform_a.compare(&letterform_b, rules),
+            max_separation: scores.map(|(max_separation, _)| max_separation),
+            budget_spent: scores.map(|(_, budget_spent)| budget_spent),
+        });
+    }
+    Ok(CompareReport {
+        upem,
+        rules,
+        chars: reports,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use kurbo::{Rect, Shape};
+    use skrifa::FontRef;
+
+    use crate::{
+        compare::compare_fonts,
+        letterform::Comparison,
+        test_fonts::{build_font, RULES},
+    };
+
+    #[test]
+    fn compares_two_fonts() {
+        let bar = |w| Rect::new(0.0, 0.0, w, 600.0).to_path(0.1);
+        let a = build_font(1000, &[('l', bar(100.0)), ('o', bar(300.0))]);
+        // the same l at twice the upem, a different o, and an extra x
+        let b = build_font(
+            2000,
+            &[
+                ('l', Rect::new(0.0, 0.0, 200.0, 1200.0).to_path(0.1)),
+                ('o', bar(300.0)),
+                ('x', bar(100.0)),
+            ],
+        );
+        let report = compare_fonts(
+            &FontRef::new(&a).unwrap(),
+            &FontRef::new(&b).unwrap(),
+            &['l', 'o', 'x'],
+            RULES,
+        )
+        .unwrap();
+
+        assert_eq!(2000, report.upem);
+        assert_eq!(vec!['l'], report.matched().collect::<Vec<_>>());
+        let l = &report.chars[0];
+        assert!(l.max_separation.unwrap() < 1e-9);
+        assert_eq!(Some(0.0), l.budget_spent);
+        assert!(report.chars[1].max_separation.unwrap() > RULES.error);
+        assert!(matches!(report.chars[2].comparison, Comparison::OneMissing));
+        assert_eq!(None, report.chars[2].budget_spent);
+    }
+}
@@ -0,0 +1,3834 @@
+//! Extraction and grouping of letterforms
+
+use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::Display,
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use i_overlay::{core::fill_rule::FillRule, float::simplify::SimplifyShape};
+use kurbo::{Affine, BezPath, PathEl, Point};
+use rayon::prelude::*;
+use skrifa::{
+    instance::{Location, LocationRef, Size},
+    outline::{DrawError, DrawSettings, OutlinePen},
+    raw::{
+        tables::{
+            cff::Cff,
+            postscript::{dict, StringId},
+        },
+        FileRef, ReadError, TableProvider,
+    },
+    FontRef, GlyphId, MetadataProvider, Tag,
+};
+use thiserror::Error;
+use write_fonts::pens::BezPathPen;
+
+use crate::{
+    about_the_same::{
+        approximately_equal_indexed, separations, AboutTheSame, ApproximatelyEqualError,
+        LazySegmentIndex, RulesOfSimilarity, VALID_UPEM,
+    },
+    cache,
+    progress::Progress,
+    single_subst::SingleSubstitutions,
+};
+
+#[derive(Error, Debug)]
+pub enum LetterformsError {
+    #[error("Not much to do with no fonts specified")]
+    NoFonts,
+    #[error("None of the {0} fonts could be used, see the log for why")]
+    NoUsableFonts(usize),
+    #[error("Unable to access {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+    #[error("{0:?} has no named instances")]
+    NoInstances(PathBuf),
+    #[error("Unable to load {0:?}: {1}")]
+    Load(PathBuf, ReadError),
+    #[error("Invalid outlines file, line {0}: {1}")]
+    InvalidOutlines(usize, String),
+    #[error("{0:?} '{1}' was placed in more than one group")]
+    MultipleGroups(PathBuf, char),
+    #[error("Unable to create letterforms for {0:?}: {1}")]
+    Letterform(PathBuf, LetterformError),
+}
+
+/// Why the letterform of a single glyph couldn't be created
+#[derive(Error, Debug)]
+pub enum LetterformError {
+    #[error("Unable to read head: {0}")]
+    Head(ReadError),
+    #[error("Unable to read cmap: {0}")]
+    Cmap(ReadError),
+    #[error("Unable to read GSUB: {0}")]
+    Gsub(ReadError),
+    #[error("No outline for glyph {0}")]
+    NoOutline(GlyphId),
+    #[error("Unable to draw glyph {0}: {1}")]
+    Draw(GlyphId, DrawError),
+}
+
+#[derive(Debug, Clone)]
+pub struct Letterform {
+    /// Private so every change goes through methods, which keep the segment index in step
+    path: BezPath,
+    /// Advance width, scaled like path. None if the font has no glyph for the char.
+    pub advance: Option<f64>,
+    /// Built the first time path is compared, then reused for every other comparison
+    index: LazySegmentIndex,
+}
+
+/// The outcome of comparing two letterforms, either of which may be missing from its font
+#[derive(Debug)]
+pub enum Comparison {
+    /// Both present and approximately equal
+    Match,
+    /// Both present but different
+    Differ(ApproximatelyEqualError),
+    /// Present in one font but not the other, a meaningful difference
+    OneMissing,
+    /// Missing from both fonts, evidence of nothing
+    BothMissing,
+}
+
+impl AboutTheSame for Letterform {
+    fn approximately_equal(
+        &self,
+        other: &Self,
+        rules: RulesOfSimilarity,
+    ) -> Result<(), ApproximatelyEqualError> {
+        if let (Some(tolerance), Some(advance), Some(other_advance)) =
+            (rules.advance_tolerance, self.advance, other.advance)
+        {
+            let delta = (advance - other_advance).abs();
+            if delta > tolerance {
+                return Err(ApproximatelyEqualError::AdvanceMismatch(delta));
+            }
+        }
+        approximately_equal_indexed(
+            (&self.path, &self.index),
+            (&other.path, &other.index),
+            rules,
+        )
+    }
+}
+
+/// A pen whose buffer is kept between glyphs so drawing many glyphs doesn't grow a
+/// fresh path for each
+#[derive(Debug, Default)]
+pub struct GlyphPen {
+    path: BezPath,
+}
+
+impl GlyphPen {
+    /// The path drawn since the last take, transformed, leaving the pen empty
+    fn take(&mut self, transform: Affine) -> BezPath {
+        let mut path = std::mem::take(&mut self.path);
+        path.apply_affine(transform);
+        path
+    }
+}
+
+impl OutlinePen for GlyphPen {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.path.move_to((x as f64, y as f64));
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.path.line_to((x as f64, y as f64));
+    }
+
+    fn quad_to(&mut self, cx0: f32, cy0: f32, x: f32, y: f32) {
+        self.path
+            .quad_to((cx0 as f64, cy0 as f64), (x as f64, y as f64));
+    }
+
+    fn curve_to(&mut self, cx0: f32, cy0: f32, cx1: f32, cy1: f32, x: f32, y: f32) {
+        self.path.curve_to(
+            (cx0 as f64, cy0 as f64),
+            (cx1 as f64, cy1 as f64),
+            (x as f64, y as f64),
+        );
+    }
+
+    fn close(&mut self) {
+        self.path.close_path();
+    }
+}
+
+impl Letterform {
+    /// True if the font has no glyph for the char
+    pub fn is_missing(&self) -> bool {
+        self.advance.is_none()
+    }
+
+    /// A letterform with advance, scaled like path, or None if the font has no glyph
+    pub fn new(path: BezPath, advance: Option<f64>) -> Self {
+        Self {
+            path,
+            advance,
+            index: LazySegmentIndex::default(),
+        }
+    }
+
+    /// The outline, as normalized
+    pub fn path(&self) -> &BezPath {
+        &self.path
+    }
+
+    /// Compare, distinguishing the ways either side may be missing
+    pub fn compare(&self, other: &Self, rules: RulesOfSimilarity) -> Comparison {
+        match (self.is_missing(), other.is_missing()) {
+            (true, true) => Comparison::BothMissing,
+            (true, false) | (false, true) => Comparison::OneMissing,
+            (false, false) => match self.approximately_equal(other, rules) {
+                Ok(()) => Comparison::Match,
+                Err(e) => Comparison::Differ(e),
+            },
+        }
+    }
+
+    pub fn create(
+        font: &FontRef,
+        c: char,
+        uniform_scale: f64,
+        location: LocationRef,
+    ) -> Result<Self, LetterformError> {
+        let gid = font.cmap().map_err(LetterformError::Cmap)?.map_codepoint(c);
+        Self::create_glyph(font, gid, uniform_scale, location)
+    }
+
+    /// Create the letterform of a glyph, missing if gid is None
+    pub fn create_glyph(
+        font: &FontRef,
+        gid: Option<GlyphId>,
+        uniform_scale: f64,
+        location: LocationRef,
+    ) -> Result<Self, LetterformError> {
+        Self::create_glyph_with(&mut GlyphPen::default(), font, gid, uniform_scale, location)
+    }
+
+    /// [`Letterform::create_glyph`], drawing with a pen reused across glyphs to save
+    /// allocations
+    pub fn create_glyph_with(
+        pen: &mut GlyphPen,
+        font: &FontRef,
+        gid: Option<GlyphId>,
+        uniform_scale: f64,
+        location: LocationRef,
+    ) -> Result<Self, LetterformError> {
+        let mut letterform = Self::create_raw(pen, font, gid, uniform_scale, location)?;
+        plant(&mut letterform.path);
+        Ok(letterform)
+    }
+
+    /// Create the letterform of a glyph without moving it to the origin
+    fn create_raw(
+        pen: &mut GlyphPen,
+        font: &FontRef,
+        gid: Option<GlyphId>,
+        uniform_scale: f64,
+        location: LocationRef,
+    ) -> Result<Self, LetterformError> {
+        let transform = Affine::scale_non_uniform(uniform_scale, -uniform_scale);
+        let outlines = font.outline_glyphs();
+
+        let mut path = BezPath::default();
+        let mut advance = None;
+        if let Some(gid) = gid {
+            advance = font
+                .glyph_metrics(Size::unscaled(), location)
+                .advance_width(gid)
+                .map(|adv| adv as f64 * uniform_scale);
+            let glyph = outlines.get(gid).ok_or(LetterformError::NoOutline(gid))?;
+            if let Err(e) = glyph.draw(
+                DrawSettings::unhinted(Size::unscaled(), location),
+                &mut *pen,
+            ) {
+                // don't leave a partial outline behind for the next glyph
+                pen.take(transform);
+                return Err(LetterformError::Draw(gid, e));
+            }
+            path = pen.take(transform);
+        }
+        Ok(Self::new(path, advance))
+    }
+
+    /// Replace overlapping contours with their union so a shape drawn with overlaps and
+    /// the same shape drawn merged compare equal
+    pub fn remove_overlaps(&mut self) {
+        self.remove_overlaps_in_place();
+        plant(&mut self.path);
+        self.index.reset();
+    }
+
+    fn remove_overlaps_in_place(&mut self) {
+        if !self.path.is_empty() {
+            self.path = union_contours(&self.path);
+            self.index.reset();
+        }
+    }
+
+    /// Drop contours whose control box diagonal is less than min_diagonal, returning
+    /// how many were dropped
+    ///
+    /// Meant for slivers and overlap artifacts that differ between otherwise identical glyphs.
+    pub fn drop_tiny_contours(&mut self, min_diagonal: f64) -> usize {
+        let dropped = self.drop_tiny_contours_in_place(min_diagonal);
+        if dropped > 0 {
+            plant(&mut self.path);
+            self.index.reset();
+        }
+        dropped
+    }
+
+    /// Scale uniformly about the origin so the control box is height tall, advance too,
+    /// so the same shape drawn at different sizes compares equal
+    ///
+    /// Letterforms with no height are left alone.
+    pub fn normalize_scale(&mut self, height: f64) {
+        let current = self.path.control_box().height();
+        if current <= 0.0 || current == height {
+            return;
+        }
+        let scale = height / current;
+        self.path.apply_affine(Affine::scale(scale));
+        self.index.reset();
+        self.advance = self.advance.map(|advance| advance * scale);
+    }
+
+    fn drop_tiny_contours_in_place(&mut self, min_diagonal: f64) -> usize {
+        let mut kept = BezPath::new();
+        let mut dropped = 0;
+        for contour in contours(&self.path) {
+            let cbox = contour.control_box();
+            if cbox.width().hypot(cbox.height()) < min_diagonal {
+                dropped += 1;
+                continue;
+            }
+            kept.extend(contour);
+        }
+        if dropped > 0 {
+            self.path = kept;
+            self.index.reset();
+        }
+        dropped
+    }
+}
+
+/// How closely to approximate curves with lines when removing overlaps, in font units
+const FLATTEN_TOLERANCE: f64 = 0.25;
+
+/// Replace path with the nonzero union of its contours, made entirely of lines
+///
+/// Curves are flattened first so the result is an approximation, but consistently so
+/// for every letterform it's applied to.
+fn union_contours(path: &BezPath) -> BezPath {
+    let mut polygons: Vec<Vec<[f64; 2]>> = Vec::new();
+    path.flatten(FLATTEN_TOLERANCE, |el| match el {
+        PathEl::MoveTo(p) => polygons.push(vec![[p.x, p.y]]),
+        PathEl::LineTo(p) => {
+            if let Some(polygon) = polygons.last_mut() {
+                polygon.push([p.x, p.y]);
+            }
+        }
+        // flatten only emits lines
+        _ => (),
+    });
+    let mut union = BezPath::new();
+    for contour in polygons
+        .simplify_shape(FillRule::NonZero, 0.0)
+        .iter()
+        .flatten()
+    {
+        let Some(([x0, y0], rest)) = contour.split_first() else {
+            continue;
+        };
+        union.move_to((*x0, *y0));
+        for [x, y] in rest {
+            union.line_to((*x, *y));
+        }
+        union.close_path();
+    }
+    union
+}
+
+/// Plant the control box at 0,0 so translation doesn't cause mismatches
+fn plant(path: &mut BezPath) {
+    let cbox = path.control_box();
+    let (minx, miny) = (cbox.min_x(), cbox.min_y());
+    if (minx, miny) != (0.0, 0.0) {
+        path.apply_affine(Affine::translate((-minx, -miny)));
+    }
+}
+
+/// Split path into its contours, each starting with a move
+fn contours(path: &BezPath) -> Vec<BezPath> {
+    let mut contours: Vec<BezPath> = Vec::new();
+    for el in path.elements() {
+        match (el, contours.last_mut()) {
+            (PathEl::MoveTo(..), _) | (_, None) => contours.push(BezPath::from_vec(vec![*el])),
+            (_, Some(contour)) => contour.push(*el),
+        }
+    }
+    contours
+}
+
+/// Vertical metrics from `hhea` and `OS/2`, scaled to the same upem as the letterforms
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerticalMetrics {
+    pub ascender: i32,
+    pub descender: i32,
+    pub line_gap: i32,
+    pub cap_height: Option<i32>,
+}
+
+impl VerticalMetrics {
+    /// cap_height is in font units, as from [`resolve_cap_height`]
+    fn new(font: &FontRef, cap_height: Option<f64>, uniform_scale: f64) -> Option<Self> {
+        let scale = |v: f64| (v * uniform_scale).round() as i32;
+        let hhea = font.hhea().ok()?;
+        Some(Self {
+            ascender: scale(hhea.ascender().to_i16() as f64),
+            descender: scale(hhea.descender().to_i16() as f64),
+            line_gap: scale(hhea.line_gap().to_i16() as f64),
+            cap_height: cap_height.map(scale),
+        })
+    }
+
+    /// As written to outlines files: ascender descender line_gap cap_height, - if absent
+    fn to_text(self) -> String {
+        let cap_height = self
+            .cap_height
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        format!(
+            "{} {} {} {cap_height}",
+            self.ascender, self.descender, self.line_gap
+        )
+    }
+
+    fn from_text(value: &str) -> Option<Self> {
+        let values = value
+            .split(' ')
+            .map(|v| v.parse::<i32>().ok())
+            .collect::<Vec<_>>();
+        let [Some(ascender), Some(descender), Some(line_gap), cap_height] = values[..] else {
+            return None;
+        };
+        Some(VerticalMetrics {
+            ascender,
+            descender,
+            line_gap,
+            cap_height,
+        })
+    }
+}
+
+/// Where a cap height came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapHeightSource {
+    /// `OS/2.sCapHeight`
+    Os2,
+    /// The top of the outline of this capital letter
+    Measured(char),
+}
+
+/// Capitals with flat tops, whose height is the cap height, in order of preference
+const CAP_HEIGHT_CHARS: [char; 6] = ['H', 'I', 'E', 'T', 'Z', 'X'];
+
+/// What [`CreateOptions::normalize_cap_height`] scales cap heights to, in ems
+pub const NORMALIZED_CAP_HEIGHT: f64 = 0.7;
+
+/// The cap height of font at location, in font units, and where it came from
+///
+/// Prefers `OS/2`, which may be absent or zero, falling back to measuring a capital letter.
+pub fn resolve_cap_height(font: &FontRef, location: LocationRef) -> Option<(f64, CapHeightSource)> {
+    if let Some(cap_height) = font
+        .os2()
+        .ok()
+        .and_then(|os2| os2.s_cap_height())
+        .filter(|v| *v > 0)
+    {
+        return Some((cap_height as f64, CapHeightSource::Os2));
+    }
+    let cmap = font.charmap();
+    let outlines = font.outline_glyphs();
+    CAP_HEIGHT_CHARS.iter().find_map(|c| {
+        let glyph = outlines.get(cmap.map(*c)?)?;
+        let mut pen = BezPathPen::new();
+        glyph
+            .draw(DrawSettings::unhinted(Size::unscaled(), location), &mut pen)
+            .ok()?;
+        let path = pen.into_inner();
+        if path.is_empty() {
+            return None;
+        }
+        let cap_height = path.control_box().max_y();
+        (cap_height > 0.0).then_some((cap_height, CapHeightSource::Measured(*c)))
+    })
+}
+
+impl Display for VerticalMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ascender {}, descender {}, line gap {}, cap height ",
+            self.ascender, self.descender, self.line_gap
+        )?;
+        match self.cap_height {
+            Some(cap_height) => write!(f, "{cap_height}"),
+            None => write!(f, "unknown"),
+        }
+    }
+}
+
+/// The letterform of each char of each font
+type ByFont = HashMap<PathBuf, HashMap<char, Letterform>>;
+
+/// Every letterform of every font, each stored exactly once
+///
+/// Groups refer back to this by font rather than holding their own copies.
+#[derive(Debug, Default)]
+pub struct Letterforms {
+    /// The upem all letterforms were scaled to
+    pub upem: u16,
+    by_font: ByFont,
+    metrics: HashMap<PathBuf, VerticalMetrics>,
+    /// The upem of each font before scaling, unknown for imported letterforms
+    source_upem: HashMap<PathBuf, u16>,
+    /// Fonts that were given but have no letterforms, and why
+    skipped: BTreeMap<PathBuf, String>,
+    /// The glyph names compared, see [`glyph_name_char`]
+    glyph_names: Vec<String>,
+    /// If set letterforms are in native font coordinates, see [`CreateOptions::raw`]
+    raw: bool,
+    /// If set chars mapped to .notdef are missing, see [`CreateOptions::ignore_notdef`]
+    ignore_notdef: bool,
+    /// If set glyphs are substituted by these features, see
+    /// [`CreateOptions::single_subst_forms`]
+    single_subst: Option<Vec<Tag>>,
+    /// If set cap heights are resolved for metrics, see [`CreateOptions::metrics_cap_height`]
+    metrics_cap_height: bool,
+    /// If set each font is scaled by its cap height, see
+    /// [`CreateOptions::normalize_cap_height`]
+    normalize_cap_height: bool,
+    /// The label of each of [`CreateOptions::more_locations`] and the letterforms of
+    /// each variable font drawn there
+    more_locations: Vec<(String, ByFont)>,
+}
+
+/// A letterform as a line of an outlines file: codepoint, advance or -, svg path
+fn letterform_to_text(c: char, letterform: &Letterform) -> String {
+    let advance = letterform
+        .advance
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| "-".to_string());
+    format!("{:04x} {advance} {}", c as u32, letterform.path.to_svg())
+}
+
+/// Parse a line written by [`letterform_to_text`]
+fn letterform_from_text(line: &str) -> Result<(char, Letterform), &'static str> {
+    let (c, value) = line.split_once(' ').unwrap_or((line, ""));
+    let c = u32::from_str_radix(c, 16)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or("bad codepoint")?;
+    let (advance, path) = value.split_once(' ').unwrap_or((value, ""));
+    let advance = match advance {
+        "-" => None,
+        v => Some(v.parse().map_err(|_| "bad advance")?),
+    };
+    let path = BezPath::from_svg(path).map_err(|_| "bad svg path")?;
+    Ok((c, Letterform::new(path, advance)))
+}
+
+/// The letterforms and metrics of one font, see [`Letterforms::add_font`]
+struct ExtractedFont {
+    letterforms: HashMap<char, Letterform>,
+    metrics: Option<VerticalMetrics>,
+    source_upem: Option<u16>,
+}
+
+impl ExtractedFont {
+    /// Read what [`ExtractedFont::save`] wrote, if it has every one of test_chars
+    ///
+    /// Anything unusable is treated as absent so the caller redraws.
+    fn load(file: &Path, test_chars: &[char]) -> Option<Self> {
+        let text = match cache::read_stamped(file) {
+            Ok(text) => text?,
+            Err(e) => {
+                log::warn!("Unable to read {file:?}, redrawing: {e}");
+                return None;
+            }
+        };
+        let mut extracted = match Self::from_text(&text) {
+            Ok(extracted) => extracted,
+            Err(e) => {
+                log::warn!("Ignoring {file:?}, {e}");
+                return None;
+            }
+        };
+        let mut letterforms = HashMap::new();
+        for c in test_chars {
+            letterforms.insert(*c, extracted.letterforms.remove(c)?);
+        }
+        extracted.letterforms = letterforms;
+        Some(extracted)
+    }
+
+    /// Write to file for [`ExtractedFont::load`] to read on a later run
+    fn save(&self, file: &Path) -> Result<(), io::Error> {
+        cache::write_stamped(file, &self.to_text())
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        if let Some(upem) = self.source_upem {
+            text.push_str(&format!("source_upem {upem}\n"));
+        }
+        if let Some(metrics) = &self.metrics {
+            text.push_str(&format!("metrics {}\n", metrics.to_text()));
+        }
+        let mut letterforms = self.letterforms.iter().collect::<Vec<_>>();
+        letterforms.sort_by_key(|(c, _)| **c);
+        for (c, letterform) in letterforms {
+            text.push_str(&letterform_to_text(*c, letterform));
+            text.push('\n');
+        }
+        text
+    }
+
+    fn from_text(text: &str) -> Result<Self, &'static str> {
+        let mut extracted = ExtractedFont {
+            letterforms: HashMap::new(),
+            metrics: None,
+            source_upem: None,
+        };
+        for line in text.lines() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "source_upem" => {
+                    extracted.source_upem = Some(value.parse().map_err(|_| "bad upem")?)
+                }
+                "metrics" => {
+                    extracted.metrics =
+                        Some(VerticalMetrics::from_text(value).ok_or("bad metrics")?)
+                }
+                _ => {
+                    let (c, letterform) = letterform_from_text(line)?;
+                    extracted.letterforms.insert(c, letterform);
+                }
+            }
+        }
+        Ok(extracted)
+    }
+}
+
+/// The first char used to stand in for a glyph name, see [`glyph_name_char`]
+const FIRST_GLYPH_NAME_CHAR: u32 = 0xF0000;
+
+/// The char standing in for the i'th glyph name compared by name rather than codepoint
+///
+/// Chars from the supplementary private use area are used so glyph names can be handled
+/// exactly like test chars. Glyph names are looked up in `post`, or the CFF charset.
+pub fn glyph_name_char(i: usize) -> char {
+    char::from_u32(FIRST_GLYPH_NAME_CHAR + i as u32).expect("Too many glyph names")
+}
+
+/// The glyph id of each glyph name in post, or failing that in the CFF charset, empty if
+/// neither has names
+fn glyph_ids_by_name(font: &FontRef) -> HashMap<String, GlyphId> {
+    let Ok(maxp) = font.maxp() else {
+        return HashMap::new();
+    };
+    let gids = (0..maxp.num_glyphs()).map(GlyphId::new);
+    let by_name = match font.post() {
+        Ok(post) => gids
+            .filter_map(|gid| Some((post.glyph_name(gid)?.to_string(), gid)))
+            .collect(),
+        Err(_) => HashMap::new(),
+    };
+    if !by_name.is_empty() {
+        return by_name;
+    }
+    let Ok(cff) = font.cff() else {
+        return by_name;
+    };
+    let Some(sids) = cff_charset_offset(&cff).and_then(|offset| {
+        let data = cff.offset_data().as_bytes().get(offset..)?;
+        cff_charset_sids(data, maxp.num_glyphs())
+    }) else {
+        return by_name;
+    };
+    sids.into_iter()
+        .enumerate()
+        .filter_map(|(gid, sid)| {
+            let name = cff.string(StringId::new(sid))?;
+            Some((name.chars().collect(), GlyphId::new(gid as u16)))
+        })
+        .collect()
+}
+
+/// The offset of the custom charset of the first font in cff, if it has one
+///
+/// Offsets 0..=2 are the predefined charsets, which only matter to ancient fonts.
+fn cff_charset_offset(cff: &Cff) -> Option<usize> {
+    let top_dict = cff.top_dicts().get(0).ok()?;
+    dict::entries(top_dict, None)
+        .filter_map(Result::ok)
+        .find_map(|entry| match entry {
+            dict::Entry::Charset(offset) if offset > 2 => Some(offset),
+            _ => None,
+        })
+}
+
+/// The string id naming each glyph, from CFF charset data
+///
+/// See "Charsets" at <https://adobe-type-tools.github.io/font-tech-notes/pdfs/5176.CFF.pdf#page=20>
+fn cff_charset_sids(data: &[u8], num_glyphs: u16) -> Option<Vec<u16>> {
+    let num_glyphs = num_glyphs as usize;
+    let u16_at = |i: usize| Some(u16::from_be_bytes(data.get(i..i + 2)?.try_into().ok()?));
+    // .notdef is implied
+    let mut sids = vec![0];
+    let format = *data.first()?;
+    let mut pos = 1;
+    while sids.len() < num_glyphs {
+        match format {
+            0 => {
+                sids.push(u16_at(pos)?);
+                pos += 2;
+            }
+            1 | 2 => {
+                let first = u16_at(pos)?;
+                let n_left = if format == 1 {
+                    *data.get(pos + 2)? as u16
+                } else {
+                    u16_at(pos + 2)?
+                };
+                pos += if format == 1 { 3 } else { 4 };
+                sids.extend((0..=n_left).map(|i| first.saturating_add(i)));
+            }
+            _ => return None,
+        }
+    }
+    sids.truncate(num_glyphs);
+    Some(sids)
+}
+
+impl Letterforms {
+    pub fn new(upem: u16) -> Self {
+        Self {
+            upem,
+            ..Default::default()
+        }
+    }
+
+    /// Compare the named glyphs, each keyed by [`glyph_name_char`], as well as test chars
+    pub fn with_glyph_names(mut self, glyph_names: &[String]) -> Self {
+        self.glyph_names = glyph_names.to_vec();
+        self
+    }
+
+    /// The glyph name c stands in for, if it's a [`glyph_name_char`]
+    pub fn glyph_name(&self, c: char) -> Option<&str> {
+        let i = (c as u32).checked_sub(FIRST_GLYPH_NAME_CHAR)?;
+        self.glyph_names.get(i as usize).map(|n| n.as_str())
+    }
+
+    /// c, or the glyph name it stands in for, for humans
+    pub fn label(&self, c: char) -> String {
+        match self.glyph_name(c) {
+            Some(name) => format!("/{name}"),
+            None => c.to_string(),
+        }
+    }
+
+    pub fn insert(&mut self, path: PathBuf, c: char, letterform: Letterform) {
+        self.by_font.entry(path).or_default().insert(c, letterform);
+    }
+
+    /// Create and add the letterforms and metrics of font at location, keyed by id
+    ///
+    /// Nothing is added if any letterform can't be created.
+    fn add_font(
+        &mut self,
+        id: PathBuf,
+        font: &FontRef,
+        test_chars: &[char],
+        uniform_scale: f64,
+        location: LocationRef,
+    ) -> Result<(), LetterformError> {
+        let extracted = self.extract(&id, font, test_chars, uniform_scale, location)?;
+        self.add_extracted(id, extracted);
+        Ok(())
+    }
+
+    /// Create the letterforms and metrics of font at location without adding them, so
+    /// fonts can be extracted in parallel
+    fn extract(
+        &self,
+        id: &Path,
+        font: &FontRef,
+        test_chars: &[char],
+        uniform_scale: f64,
+        location: LocationRef,
+    ) -> Result<ExtractedFont, LetterformError> {
+        log::debug!("Creating letterforms for {id:?}");
+        // resolving may mean drawing a capital so only do it if someone will look
+        let cap_height = (self.metrics_cap_height || self.normalize_cap_height)
+            .then(|| resolve_cap_height(font, location))
+            .flatten();
+        match cap_height {
+            Some((cap_height, source)) => {
+                log::debug!("Cap height of {id:?} is {cap_height} from {source:?}")
+            }
+            None if self.normalize_cap_height => log::warn!(
+                "{id:?} has no OS/2 cap height and no capital to measure, falling back to upem-only scaling"
+            ),
+            None => {}
+        }
+        let outline_scale = match (cap_height, font.head()) {
+            (Some((cap_height, _)), Ok(head)) if self.normalize_cap_height => {
+                uniform_scale * NORMALIZED_CAP_HEIGHT * head.units_per_em() as f64 / cap_height
+            }
+            _ => uniform_scale,
+        };
+        let glyph_ids = if self.glyph_names.is_empty() {
+            HashMap::new()
+        } else {
+            glyph_ids_by_name(font)
+        };
+        let cmap = font.cmap().map_err(LetterformError::Cmap)?;
+        let substitutions = self
+            .single_subst
+            .as_ref()
+            .map(|features| SingleSubstitutions::new(font, features))
+            .transpose()
+            .map_err(LetterformError::Gsub)?;
+        let mut pen = GlyphPen::default();
+        let mut created = HashMap::new();
+        for c in test_chars.iter() {
+            let gid = match self.glyph_name(*c) {
+                Some(name) => glyph_ids.get(name).copied(),
+                None => cmap.map_codepoint(*c),
+            };
+            let gid = gid.filter(|gid| !(self.ignore_notdef && *gid == GlyphId::NOTDEF));
+            let gid = match &substitutions {
+                Some(substitutions) => gid.map(|gid| substitutions.apply(gid)),
+                None => gid,
+            };
+            let letterform = if self.raw {
+                Letterform::create_raw(&mut pen, font, gid, outline_scale, location)?
+            } else {
+                Letterform::create_glyph_with(&mut pen, font, gid, outline_scale, location)?
+            };
+            created.insert(*c, letterform);
+        }
+        Ok(ExtractedFont {
+            letterforms: created,
+            metrics: VerticalMetrics::new(
+                font,
+                cap_height.map(|(cap_height, _)| cap_height),
+                uniform_scale,
+            ),
+            source_upem: font.head().ok().map(|head| head.units_per_em()),
+        })
+    }
+
+    fn add_extracted(&mut self, id: PathBuf, extracted: ExtractedFont) {
+        if let Some(metrics) = extracted.metrics {
+            self.metrics.insert(id.clone(), metrics);
+        }
+        if let Some(upem) = extracted.source_upem {
+            self.source_upem.insert(id.clone(), upem);
+        }
+        self.by_font
+            .entry(id)
+            .or_default()
+            .extend(extracted.letterforms);
+    }
+
+    /// Every letterform, at every location, with its font and char
+    fn letterforms_mut(&mut self) -> impl Iterator<Item = (&Path, char, &mut Letterform)> {
+        self.more_locations
+            .iter_mut()
+            .map(|(_, by_font)| by_font)
+            .chain([&mut self.by_font])
+            .flat_map(|by_font| by_font.iter_mut())
+            .flat_map(|(path, letterforms)| {
+                letterforms
+                    .iter_mut()
+                    .map(move |(c, letterform)| (path.as_path(), *c, letterform))
+            })
+    }
+
+    /// Remove overlaps from every letterform, see [`Letterform::remove_overlaps`]
+    pub fn remove_overlaps(&mut self) {
+        let raw = self.raw;
+        for (_, _, letterform) in self.letterforms_mut() {
+            if raw {
+                letterform.remove_overlaps_in_place();
+            } else {
+                letterform.remove_overlaps();
+            }
+        }
+    }
+
+    /// Drop contours smaller than min_diagonal, relative to 1000 upem, from every letterform
+    ///
+    /// See [`Letterform::drop_tiny_contours`].
+    pub fn drop_tiny_contours(&mut self, min_diagonal: f64) {
+        let min_diagonal = min_diagonal * self.upem as f64 / 1000.0;
+        let raw = self.raw;
+        for (path, c, letterform) in self.letterforms_mut() {
+            let dropped = if raw {
+                letterform.drop_tiny_contours_in_place(min_diagonal)
+            } else {
+                letterform.drop_tiny_contours(min_diagonal)
+            };
+            if dropped > 0 {
+                log::trace!("Dropped {dropped} tiny contours from {path:?} '{c}'");
+            }
+        }
+    }
+
+    /// Scale every letterform to be [`Letterforms::upem`] tall, see
+    /// [`Letterform::normalize_scale`]
+    pub fn normalize_scale(&mut self) {
+        let height = self.upem as f64;
+        for (_, _, letterform) in self.letterforms_mut() {
+            letterform.normalize_scale(height);
+        }
+    }
+
+    /// The chars of test_chars present in every font
+    pub fn common_chars(&self, test_chars: &[char]) -> Vec<char> {
+        test_chars
+            .iter()
+            .copied()
+            .filter(|c| {
+                self.by_font
+                    .values()
+                    .all(|l| l.get(c).is_some_and(|l| !l.is_missing()))
+            })
+            .collect()
+    }
+
+    pub fn get(&self, path: &Path, c: char) -> Option<&Letterform> {
+        self.by_font.get(path).and_then(|l| l.get(&c))
+    }
+
+    /// True if letterforms were also drawn at [`CreateOptions::more_locations`]
+    pub fn has_more_locations(&self) -> bool {
+        !self.more_locations.is_empty()
+    }
+
+    /// The label of the first of [`CreateOptions::more_locations`] at which the letterforms
+    /// of c from a and b don't match, None if they match at all of them
+    ///
+    /// Locations either font wasn't drawn at, say because it isn't variable, are skipped.
+    /// Rules must already be scaled to upem.
+    pub fn first_divergence(
+        &self,
+        a: &Path,
+        b: &Path,
+        c: char,
+        rules: RulesOfSimilarity,
+    ) -> Option<&str> {
+        self.more_locations
+            .iter()
+            .find(|(_, by_font)| {
+                let get = |path: &Path| by_font.get(path).and_then(|l| l.get(&c));
+                let (Some(a), Some(b)) = (get(a), get(b)) else {
+                    return false;
+                };
+                !matches!(
+                    a.compare(b, rules),
+                    Comparison::Match | Comparison::BothMissing
+                )
+            })
+            .map(|(label, _)| label.as_str())
+    }
+
+    pub fn metrics(&self, path: &Path) -> Option<&VerticalMetrics> {
+        self.metrics.get(path)
+    }
+
+    pub fn fonts(&self) -> impl Iterator<Item = &Path> {
+        self.by_font.keys().map(|p| p.as_path())
+    }
+
+    pub fn source_upem(&self, path: &Path) -> Option<u16> {
+        self.source_upem.get(path).copied()
+    }
+
+    /// The fonts that were skipped rather than loaded, with the reason
+    pub fn skipped(&self) -> impl Iterator<Item = (&Path, &str)> {
+        self.skipped.iter().map(|(p, r)| (p.as_path(), r.as_str()))
+    }
+
+    /// Write every letterform, already normalized, to a file [`Letterforms::import`] can read
+    ///
+    /// Only letterforms at the first location are written, not those drawn at
+    /// [`CreateOptions::more_locations`].
+    pub fn export(&self, file: &Path) -> Result<(), LetterformsError> {
+        cache::write_stamped(file, &self.to_text())
+            .map_err(|e| LetterformsError::Io(file.to_path_buf(), e))
+    }
+
+    /// Read letterforms written by [`Letterforms::export`]
+    ///
+    /// Returns None if the file was written by a different version and must be recreated.
+    pub fn import(file: &Path) -> Result<Option<Self>, LetterformsError> {
+        let content =
+            cache::read_stamped(file).map_err(|e| LetterformsError::Io(file.to_path_buf(), e))?;
+        content.map(|c| Self::from_text(&c)).transpose()
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!("upem {}\n", self.upem);
+        if self.raw {
+            text.push_str("raw\n");
+        }
+        for name in self.glyph_names.iter() {
+            text.push_str(&format!("glyph_name {name}\n"));
+        }
+        let mut fonts = self.by_font.iter().collect::<Vec<_>>();
+        fonts.sort_by_key(|(p, _)| *p);
+        for (path, letterforms) in fonts {
+            text.push_str(&format!("font {}\n", path.to_string_lossy()));
+            if let Some(m) = self.metrics.get(path) {
+                text.push_str(&format!("metrics {}\n", m.to_text()));
+            }
+            let mut letterforms = letterforms.iter().collect::<Vec<_>>();
+            letterforms.sort_by_key(|(c, _)| **c);
+            for (c, letterform) in letterforms {
+                text.push_str(&letterform_to_text(*c, letterform));
+                text.push('\n');
+            }
+        }
+        text
+    }
+
+    fn from_text(text: &str) -> Result<Self, LetterformsError> {
+        let mut letterforms = Letterforms::default();
+        let mut font: Option<PathBuf> = None;
+        for (i, line) in text.lines().enumerate() {
+            // the stamp is line 1
+            let line_num = i + 2;
+            let invalid = |msg: &str| LetterformsError::InvalidOutlines(line_num, msg.to_string());
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "upem" => {
+                    letterforms.upem = value.parse().map_err(|_| invalid("bad upem"))?;
+                }
+                "raw" => letterforms.raw = true,
+                "glyph_name" => letterforms.glyph_names.push(value.to_string()),
+                "font" => font = Some(PathBuf::from(value)),
+                "metrics" => {
+                    let font = font
+                        .as_ref()
+                        .ok_or_else(|| invalid("metrics before font"))?;
+                    let metrics =
+                        VerticalMetrics::from_text(value).ok_or_else(|| invalid("bad metrics"))?;
+                    letterforms.metrics.insert(font.clone(), metrics);
+                }
+                _ => {
+                    let font = font
+                        .as_ref()
+                        .ok_or_else(|| invalid("outline before font"))?;
+                    let (c, letterform) = letterform_from_text(line).map_err(invalid)?;
+                    letterforms.insert(font.clone(), c, letterform);
+                }
+            }
+        }
+        if letterforms.upem == 0 {
+            return Err(LetterformsError::InvalidOutlines(1, "no upem".to_string()));
+        }
+        Ok(letterforms)
+    }
+}
+
+/// The fonts whose letterform for a character are approximately equal
+pub struct LetterformGroup<'a> {
+    pub members: BTreeSet<&'a Path>,
+}
+
+impl<'a> LetterformGroup<'a> {
+    pub fn new(path: &'a Path) -> Self {
+        Self {
+            members: BTreeSet::from([path]),
+        }
+    }
+
+    /// True if the letterform of c from the font at path matches any member
+    ///
+    /// Identical fingerprints are confirmed and accepted without comparing geometry, and
+    /// members identical to the letterform are tried first so geometry is only compared
+    /// when none is. Each member compared is logged to compared, if given.
+    fn matches(
+        &self,
+        c: char,
+        path: &'a Path,
+        letterforms: &Letterforms,
+        fingerprints: &Fingerprints,
+        rules: RulesOfSimilarity,
+        mut compared: Option<&mut Vec<ComparedPair<'a>>>,
+    ) -> bool {
+        let Some(letterform) = letterforms.get(path, c) else {
+            return false;
+        };
+        let fingerprint = fingerprints.get(&(path, c));
+        let (identical, others): (Vec<_>, Vec<_>) = self.members.iter().partition(|member| {
+            fingerprint.is_some()
+                && fingerprints.get(&(**member, c)) == fingerprint
+                && letterforms.get(member, c).is_some_and(|other| {
+                    other.path == letterform.path && other.advance == letterform.advance
+                })
+        });
+        let identical = identical.into_iter().map(|member| (member, true));
+        let others = others.into_iter().map(|member| (member, false));
+        identical.chain(others).any(|(member, identical)| {
+            let Some(other) = letterforms.get(member, c) else {
+                return false;
+            };
+            if let Some(compared) = compared.as_deref_mut() {
+                compared.push((c, member, path));
+            }
+            let matched = if identical {
+                RESOLVED_BY_HASH.fetch_add(1, Ordering::Relaxed);
+                true
+            } else {
+                RESOLVED_BY_GEOMETRY.fetch_add(1, Ordering::Relaxed);
+                matches!(other.compare(letterform, rules), Comparison::Match)
+            };
+            if !matched {
+                return false;
+            }
+            match letterforms.first_divergence(member, path, c, rules) {
+                Some(label) => {
+                    log::info!("{path:?} '{c}' matches {member:?} but not at {label}");
+                    false
+                }
+                None => true,
+            }
+        })
+    }
+
+    /// True if c is missing from every member, in which case membership says nothing
+    /// about similarity
+    pub fn all_missing(&self, c: char, letterforms: &Letterforms) -> bool {
+        self.letterforms(c, letterforms).all(Letterform::is_missing)
+    }
+
+    /// Add path, returning false if it was already a member
+    pub fn insert(&mut self, path: &'a Path) -> bool {
+        self.members.insert(path)
+    }
+
+    /// The letterforms of the members of this group, which is assumed to be for c
+    pub fn letterforms<'b>(
+        &'b self,
+        c: char,
+        letterforms: &'b Letterforms,
+    ) -> impl Iterator<Item = &'b Letterform> {
+        self.members
+            .iter()
+            .filter_map(move |p| letterforms.get(p, c))
+    }
+}
+
+/// How [`create_letterforms`] extracts and normalizes letterforms
+#[derive(Debug, Clone, Default)]
+pub struct CreateOptions {
+    /// Glyph names to look up test chars that are [`glyph_name_char`]s by
+    pub glyph_names: Vec<String>,
+    /// If the largest upem exceeds this everything is scaled to this instead
+    pub max_upem: Option<u16>,
+    /// Make each named instance of a variable font a separate entry, keyed by
+    /// [`instance_id`], rather than just the default location
+    pub all_instances: bool,
+    /// Where to draw variable fonts, in user space coordinates, e.g. wght 700. Axes a font
+    /// lacks are ignored and those not given are at their default.
+    pub location: Vec<(Tag, f32)>,
+    /// Also draw variable fonts at each of these and only group letterforms that match at
+    /// every one, see [`Letterforms::first_divergence`]. Named instances are only drawn
+    /// where they are.
+    pub more_locations: Vec<Vec<(Tag, f32)>>,
+    /// Don't normalize at all: no scaling to a common upem and no planting at the origin.
+    /// Letterforms are compared in their native coordinates and rules are not scaled.
+    pub raw: bool,
+    /// Treat chars the cmap maps to .notdef as missing. Some fonts show tofu that way
+    /// rather than having no glyph, and every font's tofu box would otherwise match.
+    pub ignore_notdef: bool,
+    /// Compare the form of each test char reached by GSUB single substitutions rather than
+    /// the glyph the cmap maps it to, see [`SingleSubstitutions`]. Joining scripts get their
+    /// isolated forms. This is not shaping.
+    pub single_subst_forms: bool,
+    /// OpenType features whose single substitutions to apply, e.g. smcp or init
+    pub features: Vec<Tag>,
+    /// Resolve the cap height in each font's [`VerticalMetrics`], see
+    /// [`resolve_cap_height`]. It's unknown otherwise, as resolving may mean drawing a
+    /// capital.
+    pub metrics_cap_height: bool,
+    /// Scale each font so its cap height, see [`resolve_cap_height`], is
+    /// [`NORMALIZED_CAP_HEIGHT`] ems rather than only scaling to a common upem. Fonts with
+    /// no cap height are only scaled for upem. Ignored if [`CreateOptions::raw`].
+    pub normalize_cap_height: bool,
+    /// Keep drawn outlines here, keyed by a hash of the font's content, so later runs
+    /// can skip drawing fonts that haven't changed. Outlines unused for
+    /// [`OUTLINE_CACHE_MAX_AGE`] are deleted.
+    pub cache_dir: Option<PathBuf>,
+}
+
+/// How long cached outlines are kept without being used, see [`CreateOptions::cache_dir`]
+pub const OUTLINE_CACHE_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Extract letterforms for test_chars from every font, scaled to the largest upem
+pub fn create_letterforms(
+    test_chars: &[char],
+    raw_fonts: &HashMap<PathBuf, Vec<u8>>,
+    options: &CreateOptions,
+) -> Result<Letterforms, LetterformsError> {
+    let paths = raw_fonts.keys().cloned().collect::<Vec<_>>();
+    create_letterforms_with(
+        test_chars,
+        &paths,
+        |path| Ok(Cow::Borrowed(raw_fonts[path].as_slice())),
+        options,
+    )
+}
+
+/// [`create_letterforms`] for the fonts at paths, read with read when needed rather than
+/// all held in memory
+///
+/// Each font is read twice, once to find the largest upem and again to draw it, and is
+/// dropped as soon as its letterforms are drawn so memory use doesn't grow with the size
+/// of the fonts.
+pub fn create_letterforms_from_files(
+    test_chars: &[char],
+    paths: &[PathBuf],
+    read: impl Fn(&Path) -> Result<Vec<u8>, io::Error> + Sync,
+    options: &CreateOptions,
+) -> Result<Letterforms, LetterformsError> {
+    create_letterforms_with(
+        test_chars,
+        paths,
+        |path| read(path).map(Cow::Owned),
+        options,
+    )
+}
+
+/// A usable face of a font file: its id, index in the [`faces`] of the file, and upem
+type UsableFace = (PathBuf, usize, u16);
+
+/// The faces of the font file at path that can be drawn, and why any others can't
+fn usable_faces(path: &Path, bytes: &[u8]) -> (Vec<UsableFace>, Vec<(PathBuf, String)>) {
+    let mut usable = Vec::new();
+    let mut unusable = Vec::new();
+    for (i, (id, font)) in faces(path, bytes).into_iter().enumerate() {
+        let font = match font {
+            Ok(font) => font,
+            Err(e) => {
+                log::warn!("Skipping {id:?}, unable to load: {e}");
+                unusable.push((id, format!("unable to load: {e}")));
+                continue;
+            }
+        };
+        let upem = match font.head() {
+            Ok(head) => head.units_per_em(),
+            Err(e) => {
+                log::warn!("Skipping {id:?}, unable to read head: {e}");
+                unusable.push((id, format!("unable to read head: {e}")));
+                continue;
+            }
+        };
+        if !VALID_UPEM.contains(&upem) {
+            log::warn!("Skipping {id:?}, upem {upem} is outside {VALID_UPEM:?}");
+            unusable.push((id, format!("upem {upem} is outside {VALID_UPEM:?}")));
+            continue;
+        }
+        usable.push((id, i, upem));
+    }
+    (usable, unusable)
+}
+
+/// Extract letterforms from the fonts at paths, whose bytes are had from bytes, which
+/// may read them afresh each time
+fn create_letterforms_with<'a>(
+    test_chars: &[char],
+    paths: &[PathBuf],
+    bytes: impl Fn(&Path) -> Result<Cow<'a, [u8]>, io::Error> + Sync,
+    options: &CreateOptions,
+) -> Result<Letterforms, LetterformsError> {
+    // Find the usable faces, and their upem, without holding on to any font
+    let scanned = paths
+        .par_iter()
+        .map(|path| {
+            let bytes = bytes(path).map_err(|e| LetterformsError::Io(path.clone(), e))?;
+            Ok((path, usable_faces(path, &bytes)))
+        })
+        .collect::<Result<Vec<_>, LetterformsError>>()?;
+    let mut skipped = BTreeMap::new();
+    let mut fonts = Vec::new();
+    for (path, (usable, unusable)) in scanned {
+        skipped.extend(unusable);
+        if !usable.is_empty() {
+            fonts.push((path, usable));
+        }
+    }
+
+    if fonts.is_empty() {
+        return Err(if paths.is_empty() {
+            LetterformsError::NoFonts
+        } else {
+            LetterformsError::NoUsableFonts(paths.len())
+        });
+    }
+
+    // we will scale to the largest upem, within reason
+    let largest_upem = fonts
+        .iter()
+        .flat_map(|(_, usable)| usable.iter().map(|(_, _, upem)| *upem))
+        .max()
+        .unwrap();
+    let max_upem = match options.max_upem {
+        // rules are relative to 1000 upem so this leaves them unscaled
+        _ if options.raw => 1000,
+        Some(cap) if largest_upem > cap => {
+            log::warn!("Largest upem is {largest_upem}, scaling to --max-upem {cap} instead");
+            cap
+        }
+        _ => largest_upem,
+    };
+
+    let mut letterforms = Letterforms {
+        skipped,
+        raw: options.raw,
+        ignore_notdef: options.ignore_notdef,
+        single_subst: options.single_subst_forms.then(|| options.features.clone()),
+        metrics_cap_height: options.metrics_cap_height,
+        normalize_cap_height: options.normalize_cap_height && !options.raw,
+        more_locations: options
+            .more_locations
+            .iter()
+            .map(|location| (location_label(location), HashMap::new()))
+            .collect(),
+        ..Letterforms::new(max_upem)
+    }
+    .with_glyph_names(&options.glyph_names);
+    let cache_dir = options.cache_dir.as_ref().filter(|dir| {
+        fs::create_dir_all(dir)
+            .inspect_err(|e| log::warn!("Not caching outlines, unable to create {dir:?}: {e}"))
+            .is_ok()
+    });
+    if let Some(dir) = cache_dir {
+        match cache::prune(dir, OUTLINE_CACHE_MAX_AGE) {
+            Ok(pruned) => log::debug!("Pruned {pruned} unused outlines from {dir:?}"),
+            Err(e) => log::warn!("Unable to prune outlines from {dir:?}: {e}"),
+        }
+    }
+    // Drawing outlines dominates so do it in parallel, reading each font again and
+    // dropping it once drawn, then add the results in order
+    let progress = Progress::new("fonts", fonts.len());
+    let mut extracted = fonts
+        .par_iter()
+        .map(|(path, usable)| {
+            let bytes = bytes(path).map_err(|e| LetterformsError::Io(path.to_path_buf(), e))?;
+            let content = content_hash(&bytes);
+            let file_faces = faces(path, &bytes);
+            // Each font, or named instance, to extract: (id, font, scale, location, i) where
+            // i is 0 for the location given, else 1 + the index into more_locations
+            let mut jobs = Vec::new();
+            for (id, face, upem) in usable {
+                let Ok(font) = &file_faces[*face].1 else {
+                    continue;
+                };
+                let uniform_scale = if *upem != max_upem && !options.raw {
+                    max_upem as f64 / *upem as f64
+                } else {
+                    1.0
+                };
+                let instances = if options.all_instances {
+                    named_instances(id, font)
+                } else {
+                    Vec::new()
+                };
+                if instances.is_empty() {
+                    jobs.push((
+                        id.clone(),
+                        font,
+                        uniform_scale,
+                        font.axes().location(&options.location),
+                        0,
+                    ));
+                    if !font.axes().is_empty() {
+                        for (i, location) in options.more_locations.iter().enumerate() {
+                            jobs.push((
+                                id.clone(),
+                                font,
+                                uniform_scale,
+                                font.axes().location(location),
+                                i + 1,
+                            ));
+                        }
+                    }
+                }
+                for (id, location) in instances {
+                    jobs.push((id, font, uniform_scale, location, 0));
+                }
+            }
+            let extracted = jobs
+                .into_par_iter()
+                .map(|(id, font, uniform_scale, location, i)| {
+                    let cache_file = cache_dir.map(|dir| {
+                        let key =
+                            outlines_key(content, path, &id, uniform_scale, &location, options);
+                        dir.join(format!("{key:016x}.outlines"))
+                    });
+                    if let Some(extracted) = cache_file
+                        .as_ref()
+                        .and_then(|f| ExtractedFont::load(f, test_chars))
+                    {
+                        log::debug!("Loaded letterforms for {id:?} from {cache_file:?}");
+                        if let Some(Err(e)) = cache_file.as_deref().map(cache::touch) {
+                            log::warn!("Unable to mark {cache_file:?} used, it may be pruned: {e}");
+                        }
+                        return (id, i, Ok(extracted));
+                    }
+                    let extracted = letterforms.extract(
+                        &id,
+                        font,
+                        test_chars,
+                        uniform_scale,
+                        (&location).into(),
+                    );
+                    if let (Ok(extracted), Some(file)) = (&extracted, cache_file) {
+                        if let Err(e) = extracted.save(&file) {
+                            log::warn!("Unable to cache letterforms for {id:?} in {file:?}: {e}");
+                        }
+                    }
+                    (id, i, extracted)
+                })
+                .collect::<Vec<_>>();
+            progress.tick();
+            Ok(extracted)
+        })
+        .collect::<Result<Vec<_>, LetterformsError>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    extracted.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+    for (id, i, extracted) in extracted {
+        if i > 0 {
+            let (label, by_font) = &mut letterforms.more_locations[i - 1];
+            match extracted {
+                Ok(extracted) => {
+                    by_font.insert(id, extracted.letterforms);
+                }
+                Err(e) => log::warn!("Not comparing {id:?} at {label}, {e}"),
+            }
+            continue;
+        }
+        let extracted = match extracted {
+            Ok(extracted) => extracted,
+            Err(e) => {
+                log::warn!("Skipping {id:?}, {e}");
+                letterforms.skipped.insert(id, e.to_string());
+                continue;
+            }
+        };
+        letterforms.add_extracted(id, extracted);
+    }
+    if letterforms.by_font.is_empty() {
+        return Err(LetterformsError::NoUsableFonts(paths.len()));
+    }
+    Ok(letterforms)
+}
+
+/// The id of a named instance of the font at path, e.g. MyFont[wght].ttf#Bold
+pub fn instance_id(path: &Path, name: &str) -> PathBuf {
+    let mut id = path.as_os_str().to_owned();
+    id.push("#");
+    id.push(name);
+    PathBuf::from(id)
+}
+
+/// The id of a face of the collection at path, e.g. MyFonts.ttc#1
+pub fn face_id(path: &Path, index: u32) -> PathBuf {
+    instance_id(path, &index.to_string())
+}
+
+/// A location for humans, e.g. wght=500,wdth=75
+pub fn location_label(location: &[(Tag, f32)]) -> String {
+    location
+        .iter()
+        .map(|(tag, value)| format!("{tag}={value}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// A hash of the bytes of a font file, stable across builds
+fn content_hash(bytes: &[u8]) -> u64 {
+    cache::StableHasher::default().write(bytes).finish()
+}
+
+/// The key of the outlines [`create_letterforms`] caches for id, drawn from the font file
+/// with content hash content at path
+///
+/// Only the part of id after path, which names the face or instance, is used so renaming
+/// or moving a font doesn't invalidate its outlines but changing its bytes does.
+fn outlines_key(
+    content: u64,
+    path: &Path,
+    id: &Path,
+    uniform_scale: f64,
+    location: &Location,
+    options: &CreateOptions,
+) -> u64 {
+    let id = id.as_os_str().as_encoded_bytes();
+    let within = id.get(path.as_os_str().len()..).unwrap_or(id);
+    let mut hasher = cache::StableHasher::default();
+    hasher
+        .write_u64(content)
+        .write_len_prefixed(within)
+        .write_u64(uniform_scale.to_bits())
+        .write_u64(location.coords().len() as u64);
+    for coord in location.coords() {
+        hasher.write(&coord.to_bits().to_le_bytes());
+    }
+    hasher.write(&[
+        options.raw as u8,
+        options.ignore_notdef as u8,
+        options.single_subst_forms as u8,
+        options.metrics_cap_height as u8,
+        options.normalize_cap_height as u8,
+    ]);
+    hasher.write_u64(options.glyph_names.len() as u64);
+    for name in options.glyph_names.iter() {
+        hasher.write_len_prefixed(name.as_bytes());
+    }
+    hasher.write_u64(options.features.len() as u64);
+    for tag in options.features.iter() {
+        hasher.write(&tag.to_be_bytes());
+    }
+    hasher.finish()
+}
+
+/// The fonts in a font file: the font itself, keyed by path, or each face of a
+/// collection, keyed by [`face_id`]
+pub fn faces<'a>(path: &Path, bytes: &'a [u8]) -> Vec<(PathBuf, Result<FontRef<'a>, ReadError>)> {
+    match FileRef::new(bytes) {
+        Ok(FileRef::Font(font)) => vec![(path.to_path_buf(), Ok(font))],
+        Ok(FileRef::Collection(collection)) => (0..collection.len())
+            .map(|i| (face_id(path, i), collection.get(i)))
+            .collect(),
+        Err(e) => vec![(path.to_path_buf(), Err(e))],
+    }
+}
+
+/// Extract letterforms for test_chars from each named instance of a variable font
+///
+/// Each instance is keyed by [`instance_id`].
+pub fn create_instance_letterforms(
+    test_chars: &[char],
+    path: &Path,
+    bytes: &[u8],
+) -> Result<Letterforms, LetterformsError> {
+    let font = FontRef::new(bytes).map_err(|e| LetterformsError::Load(path.to_path_buf(), e))?;
+    let instances = named_instances(path, &font);
+    if instances.is_empty() {
+        return Err(LetterformsError::NoInstances(path.to_path_buf()));
+    }
+    let upem = font
+        .head()
+        .map_err(|e| LetterformsError::Load(path.to_path_buf(), e))?
+        .units_per_em();
+    let mut letterforms = Letterforms::new(upem);
+    for (id, location) in instances {
+        letterforms
+            .add_font(id.clone(), &font, test_chars, 1.0, (&location).into())
+            .map_err(|e| LetterformsError::Letterform(id, e))?;
+    }
+    Ok(letterforms)
+}
+
+/// Extract letterforms for test_chars from the font at path, scaled to upem so they can be
+/// compared with letterforms from [`create_letterforms`]
+pub fn create_baseline_letterforms(
+    test_chars: &[char],
+    path: &Path,
+    bytes: &[u8],
+    upem: u16,
+) -> Result<Letterforms, LetterformsError> {
+    let font = FontRef::new(bytes).map_err(|e| LetterformsError::Load(path.to_path_buf(), e))?;
+    let font_upem = font
+        .head()
+        .map_err(|e| LetterformsError::Load(path.to_path_buf(), e))?
+        .units_per_em();
+    let mut letterforms = Letterforms::new(upem);
+    letterforms
+        .add_font(
+            path.to_path_buf(),
+            &font,
+            test_chars,
+            upem as f64 / font_upem as f64,
+            LocationRef::default(),
+        )
+        .map_err(|e| LetterformsError::Letterform(path.to_path_buf(), e))?;
+    Ok(letterforms)
+}
+
+/// Remove the groups whose letterforms match those of the baseline font, returning how
+/// many fonts were removed for each char
+///
+/// Some shapes, like a period, are drawn the same almost everywhere so fonts sharing them
+/// says little about duplication.
+pub fn remove_baseline_groups(
+    groups: &mut HashMap<char, Vec<LetterformGroup>>,
+    letterforms: &Letterforms,
+    baseline: &Letterforms,
+    rules: RulesOfSimilarity,
+) -> BTreeMap<char, usize> {
+    let rules = rules.for_upem(letterforms.upem);
+    let mut removed = BTreeMap::new();
+    let Some(baseline_path) = baseline.fonts().next() else {
+        return removed;
+    };
+    for (c, groups) in groups.iter_mut() {
+        let Some(default) = baseline.get(baseline_path, *c) else {
+            continue;
+        };
+        if default.is_missing() {
+            continue;
+        }
+        groups.retain(|group| {
+            let matches_default = group
+                .letterforms(*c, letterforms)
+                .next()
+                .is_some_and(|l| matches!(l.compare(default, rules), Comparison::Match));
+            if matches_default {
+                *removed.entry(*c).or_default() += group.members.len();
+            }
+            !matches_default
+        });
+    }
+    removed
+}
+
+/// The named instances of font, as ([`instance_id`], location)
+fn named_instances(path: &Path, font: &FontRef) -> Vec<(PathBuf, Location)> {
+    font.named_instances()
+        .iter()
+        .enumerate()
+        .map(|(i, instance)| {
+            let name = font
+                .localized_strings(instance.subfamily_name_id())
+                .english_or_first()
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| format!("instance{i}"));
+            (instance_id(path, &name), instance.location())
+        })
+        .collect()
+}
+
+/// The library font most like a candidate font, see [`best_matches`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BestMatch<'a> {
+    pub candidate: &'a Path,
+    /// None if no library font has any letterform in common with candidate
+    pub library_font: Option<&'a Path>,
+    /// The chars whose letterforms match
+    pub chars: BTreeSet<char>,
+}
+
+/// For each candidate font, the library font with the most matching letterforms
+///
+/// Only candidate, library pairs are compared, never two candidates or two library fonts.
+/// Missing from both is not a match. Ties go to the library font that sorts first.
+pub fn best_matches<'a>(
+    rules: RulesOfSimilarity,
+    test_chars: &[char],
+    letterforms: &'a Letterforms,
+    library: &[&'a Path],
+    candidates: &[&'a Path],
+) -> Vec<BestMatch<'a>> {
+    let rules = rules.for_upem(letterforms.upem);
+    let mut library = library.to_vec();
+    library.sort();
+    candidates
+        .iter()
+        .map(|candidate| {
+            let mut best = BestMatch {
+                candidate,
+                library_font: None,
+                chars: BTreeSet::new(),
+            };
+            for library_font in library.iter().filter(|l| *l != candidate) {
+                let chars = test_chars
+                    .iter()
+                    .copied()
+                    .filter(|c| {
+                        let (Some(a), Some(b)) = (
+                            letterforms.get(candidate, *c),
+                            letterforms.get(library_font, *c),
+                        ) else {
+                            return false;
+                        };
+                        matches!(a.compare(b, rules), Comparison::Match)
+                    })
+                    .collect::<BTreeSet<_>>();
+                if chars.len() > best.chars.len() {
+                    best.library_font = Some(library_font);
+                    best.chars = chars;
+                }
+            }
+            best
+        })
+        .collect()
+}
+
+/// How many distinct designs each char has, the number of groups with a letterform
+///
+/// Fonts matching on a char with many designs is stronger evidence of copying than
+/// matching on one everyone draws the same. Requires a full grouping pass first.
+pub fn diversity(
+    groups: &HashMap<char, Vec<LetterformGroup>>,
+    letterforms: &Letterforms,
+) -> HashMap<char, usize> {
+    groups
+        .iter()
+        .map(|(c, groups)| {
+            let designs = groups
+                .iter()
+                .filter(|g| !g.all_missing(*c, letterforms))
+                .count();
+            (*c, designs)
+        })
+        .collect()
+}
+
+/// The fraction of the total weight of test_chars that matched carries, chars without
+/// a weight weigh 1
+pub fn weighted_score(
+    matched: &BTreeSet<char>,
+    test_chars: &[char],
+    weights: &HashMap<char, f64>,
+) -> f64 {
+    let weight = |c: &char| weights.get(c).copied().unwrap_or(1.0);
+    let total = test_chars.iter().map(weight).sum::<f64>();
+    if total == 0.0 {
+        return 0.0;
+    }
+    matched.iter().map(weight).sum::<f64>() / total
+}
+
+/// For each set of fonts sharing a group, the chars they share, see [`share_counts`]
+pub type ShareCounts<'a> = HashMap<BTreeSet<&'a Path>, BTreeSet<char>>;
+
+/// For each group with at least min_group_size members, and never fewer than two, the
+/// chars for which exactly those fonts form a group
+///
+/// Chars missing from every member are not counted, shared absence is not evidence of
+/// duplication.
+pub fn share_counts<'a>(
+    groups: &HashMap<char, Vec<LetterformGroup<'a>>>,
+    letterforms: &Letterforms,
+    min_group_size: usize,
+) -> ShareCounts<'a> {
+    let min_group_size = min_group_size.max(2);
+    let mut share_counts = ShareCounts::default();
+    for (c, groups) in groups.iter() {
+        for group in groups {
+            if group.members.len() < min_group_size || group.all_missing(*c, letterforms) {
+                continue;
+            }
+            let key = group.members.iter().copied().collect::<BTreeSet<&Path>>();
+            share_counts.entry(key).or_default().insert(*c);
+        }
+    }
+    share_counts
+}
+
+/// Sort sets of fonts and the chars they share, as from [`share_counts`], by score
+/// descending and then by fonts so output doesn't depend on hash order
+pub fn rank_clusters(
+    clusters: &mut [(BTreeSet<&Path>, BTreeSet<char>)],
+    score: impl Fn(&BTreeSet<char>) -> f64,
+) {
+    clusters.sort_by(|(a_paths, a_chars), (b_paths, b_chars)| {
+        score(b_chars)
+            .total_cmp(&score(a_chars))
+            .then_with(|| a_paths.cmp(b_paths))
+    });
+}
+
+/// Adjusts the groups made by [`group_letterforms`] before [`share_counts`] aggregates them,
+/// e.g. to merge or drop groups by rules of your own
+///
+/// Groups are keyed by char and refer to fonts by their key in [`Letterforms`]. Implemented
+/// for closures taking the same arguments.
+pub trait GroupPostProcessor {
+    fn process<'a>(
+        &self,
+        groups: &mut HashMap<char, Vec<LetterformGroup<'a>>>,
+        letterforms: &'a Letterforms,
+    );
+}
+
+impl<F> GroupPostProcessor for F
+where
+    F: for<'a> Fn(&mut HashMap<char, Vec<LetterformGroup<'a>>>, &'a Letterforms),
+{
+    fn process<'a>(
+        &self,
+        groups: &mut HashMap<char, Vec<LetterformGroup<'a>>>,
+        letterforms: &'a Letterforms,
+    ) {
+        self(groups, letterforms)
+    }
+}
+
+/// Group letterforms, let each post processor adjust the groups in turn, then count what
+/// each set of fonts shares
+pub fn find_shared<'a>(
+    rules: RulesOfSimilarity,
+    test_chars: &[char],
+    letterforms: &'a Letterforms,
+    post_processors: &[&dyn GroupPostProcessor],
+) -> Result<(HashMap<char, Vec<LetterformGroup<'a>>>, ShareCounts<'a>), LetterformsError> {
+    let mut groups = group_letterforms(rules, test_chars, letterforms)?;
+    for post_processor in post_processors {
+        post_processor.process(&mut groups, letterforms);
+    }
+    let share_counts = share_counts(&groups, letterforms, 2);
+    Ok((groups, share_counts))
+}
+
+/// For each pair of fonts that share a group for at least one char, the chars they share
+///
+/// The first path of each pair sorts before the second. Chars missing from both
+/// are not shared.
+pub fn shared_chars<'a>(
+    groups: &HashMap<char, Vec<LetterformGroup<'a>>>,
+    letterforms: &Letterforms,
+) -> HashMap<(&'a Path, &'a Path), BTreeSet<char>> {
+    let mut shared: HashMap<(&Path, &Path), BTreeSet<char>> = Default::default();
+    for (c, groups) in groups.iter() {
+        for group in groups {
+            if group.all_missing(*c, letterforms) {
+                continue;
+            }
+            let members = group.members.iter().copied().collect::<Vec<_>>();
+            for (i, a) in members.iter().enumerate() {
+                for b in members[i + 1..].iter() {
+                    shared.entry((*a, *b)).or_default().insert(*c);
+                }
+            }
+        }
+    }
+    shared
+}
+
+/// The other font sharing the most chars with a font, see [`nearest_neighbors`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Neighbor<'a> {
+    pub font: &'a Path,
+    pub chars: BTreeSet<char>,
+}
+
+/// For each font sharing at least one char with another, the font it shares the most with
+///
+/// Ties go to the first font in path order. Whether the neighbor is close enough to be a
+/// duplicate is up to the caller.
+pub fn nearest_neighbors<'a>(
+    groups: &HashMap<char, Vec<LetterformGroup<'a>>>,
+    letterforms: &Letterforms,
+) -> BTreeMap<&'a Path, Neighbor<'a>> {
+    let mut nearest: BTreeMap<&Path, Neighbor> = BTreeMap::new();
+    for ((a, b), chars) in shared_chars(groups, letterforms) {
+        for (font, other) in [(a, b), (b, a)] {
+            let closer = match nearest.get(font) {
+                Some(current) => {
+                    (chars.len(), Reverse(other)) > (current.chars.len(), Reverse(current.font))
+                }
+                None => true,
+            };
+            if closer {
+                nearest.insert(
+                    font,
+                    Neighbor {
+                        font: other,
+                        chars: chars.clone(),
+                    },
+                );
+            }
+        }
+    }
+    nearest
+}
+
+/// For each char, a histogram of the separations between the letterforms of every pair of
+/// fonts in the same group
+///
+/// Bin i counts separations in [i * bin_width, (i + 1) * bin_width), except the last bin
+/// which also counts everything larger.
+pub fn separation_histogram(
+    groups: &HashMap<char, Vec<LetterformGroup>>,
+    letterforms: &Letterforms,
+    rules: RulesOfSimilarity,
+    bin_width: f64,
+    num_bins: usize,
+) -> BTreeMap<char, Vec<usize>> {
+    let rules = rules.for_upem(letterforms.upem);
+    let mut histogram = BTreeMap::new();
+    for (c, groups) in groups.iter() {
+        let bins = histogram.entry(*c).or_insert_with(|| vec![0; num_bins]);
+        for group in groups {
+            let members = group.letterforms(*c, letterforms).collect::<Vec<_>>();
+            for (i, a) in members.iter().enumerate() {
+                for b in members[i + 1..].iter() {
+                    for separation in separations(&a.path, &b.path, rules) {
+                        let bin = ((separation / bin_width) as usize).min(num_bins - 1);
+                        bins[bin] += 1;
+                    }
+                }
+            }
+        }
+    }
+    histogram
+}
+
+/// The largest separation between the letterforms of c in any two of fonts, 0 if fewer
+/// than two have it
+///
+/// How loosely the fonts match on c, in units of [`Letterforms::upem`].
+pub fn spread<'a>(
+    fonts: impl IntoIterator<Item = &'a Path>,
+    c: char,
+    letterforms: &Letterforms,
+    rules: RulesOfSimilarity,
+) -> f64 {
+    let rules = rules.for_upem(letterforms.upem);
+    let paths = fonts
+        .into_iter()
+        .filter_map(|font| letterforms.get(font, c))
+        .filter(|l| !l.is_missing())
+        .map(|l| &l.path)
+        .collect::<Vec<_>>();
+    let mut spread = 0.0_f64;
+    for (i, a) in paths.iter().enumerate() {
+        for b in paths.iter().skip(i + 1) {
+            for separation in separations(a, b, rules)
+                .into_iter()
+                .chain(separations(b, a, rules))
+            {
+                spread = spread.max(separation);
+            }
+        }
+    }
+    spread
+}
+
+/// How many comparisons of letterforms were resolved by identical fingerprints
+pub static RESOLVED_BY_HASH: AtomicUsize = AtomicUsize::new(0);
+
+/// How many comparisons of letterforms had to compare geometry
+pub static RESOLVED_BY_GEOMETRY: AtomicUsize = AtomicUsize::new(0);
+
+/// Fingerprint of each (font, char)
+type Fingerprints<'a> = HashMap<(&'a Path, char), u64>;
+
+/// A hash of a letterform's outline and advance, as normalized
+///
+/// Letterforms that are identical after normalization have the same fingerprint.
+fn fingerprint(letterform: &Letterform) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    letterform.advance.map(f64::to_bits).hash(&mut hasher);
+    for el in letterform.path.elements() {
+        let points: &[Point] = match el {
+            PathEl::MoveTo(p) | PathEl::LineTo(p) => &[*p],
+            PathEl::QuadTo(p0, p1) => &[*p0, *p1],
+            PathEl::CurveTo(p0, p1, p2) => &[*p0, *p1, *p2],
+            PathEl::ClosePath => &[],
+        };
+        std::mem::discriminant(el).hash(&mut hasher);
+        for p in points {
+            (p.x.to_bits(), p.y.to_bits()).hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+/// The single char lowercase form of c, if c is uppercase and has one
+fn lowercase_of(c: char) -> Option<char> {
+    if !c.is_uppercase() {
+        return None;
+    }
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) if lower != c => Some(lower),
+        _ => None,
+    }
+}
+
+/// test_chars plus the lowercase form of each uppercase char
+pub fn with_lowercase(test_chars: &[char]) -> Vec<char> {
+    let mut chars = test_chars
+        .iter()
+        .copied()
+        .chain(test_chars.iter().filter_map(|c| lowercase_of(*c)))
+        .collect::<Vec<_>>();
+    chars.sort();
+    chars.dedup();
+    chars
+}
+
+/// Experimental: the (uppercase, lowercase) pairs of the font at path whose lowercase is just
+/// the uppercase scaled down, suggesting faux small caps rather than a designed lowercase
+///
+/// The lowercase letterform is scaled uniformly to the height of the uppercase before comparing.
+pub fn faux_lowercase(
+    letterforms: &Letterforms,
+    path: &Path,
+    test_chars: &[char],
+    rules: RulesOfSimilarity,
+) -> Vec<(char, char)> {
+    let rules = rules.for_upem(letterforms.upem);
+    test_chars
+        .iter()
+        .filter_map(|upper| Some((*upper, lowercase_of(*upper)?)))
+        .filter(|(upper, lower)| {
+            let (Some(upper), Some(lower)) =
+                (letterforms.get(path, *upper), letterforms.get(path, *lower))
+            else {
+                return false;
+            };
+            let (upper_height, lower_height) = (
+                upper.path.control_box().height(),
+                lower.path.control_box().height(),
+            );
+            if upper.is_missing() || lower.is_missing() || lower_height <= 0.0 {
+                return false;
+            }
+            let mut scaled = lower.path.clone();
+            scaled.apply_affine(Affine::scale(upper_height / lower_height));
+            upper.path.approximately_equal(&scaled, rules).is_ok()
+        })
+        .collect()
+}
+
+/// One group merged into another because their letterforms for c match once one is
+/// transformed, e.g. rotated
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergedGroup<T> {
+    pub c: char,
+    /// What was done to the letterform of the merged group to make it match
+    pub transform: T,
+    /// The members of the group that was merged
+    pub merged: BTreeSet<PathBuf>,
+    /// The members of the group it was merged into, before merging
+    pub into: BTreeSet<PathBuf>,
+}
+
+/// A letterform standing in for its group in [`merge_groups_by`]: (char, font, letterform)
+type Representative<'a, 'l> = (char, &'a Path, &'l Letterform);
+
+/// Merge each group into an earlier one if transform finds a way to make the letterform of
+/// the later group match, transform(target, candidate) returning how
+///
+/// Only the first letterform of each group is compared so the cost is bounded by the
+/// number of groups, not fonts. Members of a merged group no longer all match as drawn.
+fn merge_groups_by<'a, 'l, T>(
+    groups: &mut HashMap<char, Vec<LetterformGroup<'a>>>,
+    letterforms: &'l Letterforms,
+    transform: impl Fn(Representative<'a, 'l>, Representative<'a, 'l>) -> Option<T>,
+) -> Vec<MergedGroup<T>> {
+    let mut merged = Vec::new();
+    for (c, groups) in groups.iter_mut() {
+        let c = *c;
+        let representative = |group: &LetterformGroup<'a>| {
+            group.members.iter().find_map(|path| {
+                let letterform = letterforms.get(path, c)?;
+                (!letterform.is_missing()).then_some((c, *path, letterform))
+            })
+        };
+        let mut i = 0;
+        while i < groups.len() {
+            let Some(target) = representative(&groups[i]) else {
+                i += 1;
+                continue;
+            };
+            let mut j = i + 1;
+            while j < groups.len() {
+                let Some(how) =
+                    representative(&groups[j]).and_then(|candidate| transform(target, candidate))
+                else {
+                    j += 1;
+                    continue;
+                };
+                let group = groups.remove(j);
+                merged.push(MergedGroup {
+                    c,
+                    transform: how,
+                    merged: group.members.iter().map(|p| p.to_path_buf()).collect(),
+                    into: groups[i].members.iter().map(|p| p.to_path_buf()).collect(),
+                });
+                groups[i].members.extend(group.members);
+            }
+            i += 1;
+        }
+    }
+    merged.sort_by(|a, b| (a.c, &a.merged).cmp(&(b.c, &b.merged)));
+    merged
+}
+
+/// A group whose letterform matched that of an earlier group as drawn
+pub type Bridged = MergedGroup<()>;
+
+/// Merge groups whose letterforms match, compared either way round, repeating until no
+/// more merge
+///
+/// Grouping is greedy so which groups form depends on the order fonts are seen in and,
+/// if rules aren't symmetric, on which of two letterforms is measured against the other.
+/// This makes the result closer to a clustering, where groups that match are one group
+/// whatever the order, rather than the first matches found.
+///
+/// Returns what was merged, see [`merge_groups_by`].
+pub fn merge_matching_groups(
+    groups: &mut HashMap<char, Vec<LetterformGroup>>,
+    letterforms: &Letterforms,
+    rules: RulesOfSimilarity,
+) -> Vec<Bridged> {
+    let rules = rules.for_upem(letterforms.upem);
+    let mut merged = Vec::new();
+    loop {
+        // a merged group may be represented by a different letterform next time round
+        let more = merge_groups_by(groups, letterforms, |target, candidate| {
+            let (c, target_path, target) = target;
+            let (_, candidate_path, candidate) = candidate;
+            ((matches!(target.compare(candidate, rules), Comparison::Match)
+                || matches!(candidate.compare(target, rules), Comparison::Match))
+                && letterforms
+                    .first_divergence(target_path, candidate_path, c, rules)
+                    .is_none())
+            .then_some(())
+        });
+        if more.is_empty() {
+            break;
+        }
+        merged.extend(more);
+    }
+    merged
+}
+
+/// The rotations, in degrees, tried by [`merge_rotated_groups`]
+const ROTATIONS: [u16; 3] = [90, 180, 270];
+
+/// A group that matched once rotated counterclockwise by this many degrees
+pub type Rotated = MergedGroup<u16>;
+
+/// path rotated counterclockwise by degrees, planted at 0,0 again
+fn rotate(path: &BezPath, degrees: u16) -> BezPath {
+    let mut path = path.clone();
+    path.apply_affine(Affine::rotate((degrees as f64).to_radians()));
+    plant(&mut path);
+    path
+}
+
+/// Merge each group into an earlier one if its letterform matches once rotated by a
+/// multiple of 90 degrees, as copies of symbols sometimes are
+///
+/// Returns what was merged, see [`merge_groups_by`].
+pub fn merge_rotated_groups(
+    groups: &mut HashMap<char, Vec<LetterformGroup>>,
+    letterforms: &Letterforms,
+    rules: RulesOfSimilarity,
+) -> Vec<Rotated> {
+    let rules = rules.for_upem(letterforms.upem);
+    merge_groups_by(groups, letterforms, |(_, _, target), (_, _, candidate)| {
+        ROTATIONS.into_iter().find(|degrees| {
+            let rotated = Letterform::new(rotate(&candidate.path, *degrees), candidate.advance);
+            matches!(target.compare(&rotated, rules), Comparison::Match)
+        })
+    })
+}
+
+/// A group that matched once scaled horizontally by this much, suggesting a condensed or
+/// extended derivative
+pub type Stretched = MergedGroup<f64>;
+
+/// Merge each group into an earlier one if its letterform matches once scaled horizontally
+/// to the same aspect ratio
+///
+/// Returns what was merged, see [`merge_groups_by`].
+pub fn merge_stretched_groups(
+    groups: &mut HashMap<char, Vec<LetterformGroup>>,
+    letterforms: &Letterforms,
+    rules: RulesOfSimilarity,
+) -> Vec<Stretched> {
+    let rules = rules.for_upem(letterforms.upem);
+    merge_groups_by(groups, letterforms, |(_, _, target), (_, _, candidate)| {
+        let (target_box, candidate_box) = (target.path.control_box(), candidate.path.control_box());
+        if target_box.area() == 0.0 || candidate_box.area() == 0.0 {
+            return None;
+        }
+        let x_scale = (target_box.width() / target_box.height())
+            / (candidate_box.width() / candidate_box.height());
+        let mut path = candidate.path.clone();
+        path.apply_affine(Affine::scale_non_uniform(x_scale, 1.0));
+        let stretched = Letterform::new(path, candidate.advance.map(|a| a * x_scale));
+        matches!(target.compare(&stretched, rules), Comparison::Match).then_some(x_scale)
+    })
+}
+
+/// Set to make [`group_letterforms`] stop early, e.g. on Ctrl-C, returning the groups
+/// found so far
+pub static STOP_GROUPING: AtomicBool = AtomicBool::new(false);
+
+/// Group the letterforms for each test char by approximate equality
+///
+/// Each letterform joins the group it matches. If it matches several they are merged into
+/// one, as it bridges them, so a given (font, char) is only ever in one group. Fonts are
+/// processed in sorted order so results are reproducible. Finally groups that match each
+/// other are merged, see [`merge_matching_groups`], so grouping approximates a clustering
+/// rather than depending on which letterforms were compared first.
+///
+/// Fonts missing a char are in none of its groups.
+pub fn group_letterforms<'a>(
+    rules: RulesOfSimilarity,
+    test_chars: &[char],
+    letterforms: &'a Letterforms,
+) -> Result<HashMap<char, Vec<LetterformGroup<'a>>>, LetterformsError> {
+    group_letterforms_logged(rules, test_chars, letterforms, None)
+}
+
+/// A pair of letterforms compared while grouping: the char, a member of a group and the
+/// font whose letterform was compared to it
+pub type ComparedPair<'a> = (char, &'a Path, &'a Path);
+
+/// [`group_letterforms`], logging every pair of letterforms compared to compared, if given
+///
+/// There are O(fonts²) pairs at worst so only ask for them if they're needed.
+pub fn group_letterforms_logged<'a>(
+    rules: RulesOfSimilarity,
+    test_chars: &[char],
+    letterforms: &'a Letterforms,
+    mut compared: Option<&mut Vec<ComparedPair<'a>>>,
+) -> Result<HashMap<char, Vec<LetterformGroup<'a>>>, LetterformsError> {
+    // budget is based on 1000 upem; scale if necessary
+    let rules = rules.for_upem(letterforms.upem);
+    log::info!("The rules are {rules:?}");
+
+    // normalization is complete so identical fingerprints mean identical letterforms
+    let fingerprints: Fingerprints = letterforms
+        .fonts()
+        .flat_map(|path| {
+            test_chars.iter().filter_map(move |c| {
+                letterforms
+                    .get(path, *c)
+                    .map(|l| ((path, *c), fingerprint(l)))
+            })
+        })
+        .collect();
+
+    let mut fonts = letterforms.fonts().collect::<Vec<_>>();
+    fonts.sort();
+    let mut groups: HashMap<char, Vec<LetterformGroup>> = Default::default();
+    for (i, path) in fonts.into_iter().enumerate() {
+        if STOP_GROUPING.load(Ordering::Relaxed) {
+            log::warn!("Stopped grouping after {i} fonts");
+            break;
+        }
+        for c in test_chars.iter() {
+            let groups = groups.entry(*c).or_default();
+            // Shared absence is not evidence of duplication so missing chars join no group
+            if letterforms.get(path, *c).is_none_or(Letterform::is_missing) {
+                continue;
+            }
+            let matching = groups
+                .iter()
+                .enumerate()
+                .filter(|(_, g)| {
+                    g.matches(
+                        *c,
+                        path,
+                        letterforms,
+                        &fingerprints,
+                        rules,
+                        compared.as_deref_mut(),
+                    )
+                })
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>();
+            let Some((first, others)) = matching.split_first() else {
+                groups.push(LetterformGroup::new(path));
+                continue;
+            };
+            if !others.is_empty() {
+                log::info!(
+                    "{path:?} '{c}' matches {} groups, merging them",
+                    others.len() + 1
+                );
+            }
+            // Later indices first so removal doesn't shift those yet to be merged
+            for i in others.iter().rev() {
+                let merged = groups.remove(*i);
+                groups[*first].members.extend(merged.members);
+            }
+            if !groups[*first].insert(path) {
+                return Err(LetterformsError::MultipleGroups(path.to_path_buf(), *c));
+            }
+        }
+    }
+    for bridged in merge_matching_groups(&mut groups, letterforms, rules) {
+        log::info!(
+            "'{}' {:?} matches {:?}, merging them",
+            bridged.c,
+            bridged.merged,
+            bridged.into
+        );
+    }
+    check_membership(&groups)?;
+    Ok(groups)
+}
+
+/// Confirm no (font, char) is in more than one group
+fn check_membership(groups: &HashMap<char, Vec<LetterformGroup>>) -> Result<(), LetterformsError> {
+    for (c, groups) in groups.iter() {
+        let mut seen = HashSet::new();
+        for path in groups.iter().flat_map(|g| g.members.iter()) {
+            if !seen.insert(path) {
+                return Err(LetterformsError::MultipleGroups(path.to_path_buf(), *c));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, BTreeSet, HashMap},
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use kurbo::{Affine, BezPath, Circle, Rect, Shape, Size};
+    use skrifa::{instance::LocationRef, raw::types::Tag, FontRef, MetadataProvider};
+
+    use crate::{
+        about_the_same::{AboutTheSame, ApproximatelyEqualError, RulesOfSimilarity},
+        letterform::{
+            best_matches, cff_charset_sids, check_membership, create_baseline_letterforms,
+            create_instance_letterforms, create_letterforms, create_letterforms_from_files,
+            diversity, face_id, faux_lowercase, find_shared, fingerprint, glyph_name_char,
+            group_letterforms, group_letterforms_logged, instance_id, merge_matching_groups,
+            merge_rotated_groups, merge_stretched_groups, nearest_neighbors, rank_clusters,
+            remove_baseline_groups, resolve_cap_height, rotate, share_counts, shared_chars, spread,
+            weighted_score, with_lowercase, BestMatch, CapHeightSource, Comparison, CreateOptions,
+            GlyphPen, Letterform, LetterformError, LetterformGroup, Letterforms, LetterformsError,
+            ShareCounts, VerticalMetrics,
+        },
+        test_fonts::{
+            build_collection, build_font, build_font_with_forms, build_font_with_notdef,
+            build_font_without_head, build_named_font, build_variable_font,
+            build_variable_font_with_middle, RULES,
+        },
+    };
+
+    #[test]
+    fn text_round_trip() {
+        let mut letterforms = Letterforms::new(2048).with_glyph_names(&["a.alt".to_string()]);
+        let circle = Circle::new((100.0, 100.0), 33.3).to_path(0.1);
+        let o = Letterform::new(circle.clone(), Some(120.5));
+        let space = Letterform::new(BezPath::new(), None);
+        letterforms.insert(PathBuf::from("a.ttf"), 'o', o.clone());
+        letterforms.insert(PathBuf::from("a.ttf"), ' ', space);
+        letterforms.insert(PathBuf::from("b c.ttf"), 'o', o);
+        letterforms.metrics.insert(
+            PathBuf::from("b c.ttf"),
+            VerticalMetrics {
+                ascender: 1900,
+                descender: -500,
+                line_gap: 0,
+                cap_height: None,
+            },
+        );
+
+        let restored = Letterforms::from_text(&letterforms.to_text()).unwrap();
+        assert_eq!(2048, restored.upem);
+        let o = restored.get(Path::new("b c.ttf"), 'o').unwrap();
+        assert_eq!((&circle, Some(120.5)), (&o.path, o.advance));
+        let space = restored.get(Path::new("a.ttf"), ' ').unwrap();
+        assert_eq!((&BezPath::new(), None), (&space.path, space.advance));
+        assert_eq!(
+            letterforms.metrics(Path::new("b c.ttf")),
+            restored.metrics(Path::new("b c.ttf"))
+        );
+        assert_eq!(Some("a.alt"), restored.glyph_name(glyph_name_char(0)));
+    }
+
+    #[test]
+    fn prefilter_advance() {
+        let bar = Rect::new(0.0, 0.0, 4.0, 12.0).to_path(0.1);
+        let narrow = Letterform::new(bar.clone(), Some(100.0));
+        let wide = Letterform::new(bar, Some(200.0));
+        let mut rules = RULES;
+        narrow.approximately_equal(&wide, rules).unwrap();
+        rules.advance_tolerance = Some(50.0);
+        assert!(matches!(
+            narrow.approximately_equal(&wide, rules),
+            Err(ApproximatelyEqualError::AdvanceMismatch(..))
+        ));
+    }
+
+    #[test]
+    fn skips_fonts_with_absurd_upem() {
+        let glyphs = [('l', Rect::new(0.0, 0.0, 4.0, 12.0).to_path(0.1))];
+        let raw_fonts = HashMap::from([
+            (PathBuf::from("tiny.ttf"), build_font(8, &glyphs)),
+            (PathBuf::from("fine.ttf"), build_font(1000, &glyphs)),
+        ]);
+        let letterforms =
+            create_letterforms(&['l'], &raw_fonts, &CreateOptions::default()).unwrap();
+        assert_eq!(1000, letterforms.upem);
+        assert_eq!(
+            vec![Path::new("fine.ttf")],
+            letterforms.fonts().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![Path::new("tiny.ttf")],
+            letterforms.skipped().map(|(p, _)| p).collect::<Vec<_>>()
+        );
+        assert_eq!(Some(1000), letterforms.source_upem(Path::new("fine.ttf")));
+    }
+
+    #[test]
+    fn skips_fonts_without_head() {
+        let glyphs = [('l', Rect::new(0.0, 0.0, 4.0, 12.0).to_path(0.1))];
+        let mut raw_fonts =
+            HashMap::from([(PathBuf::from("headless.ttf"), build_font_without_head())]);
+        assert!(matches!(
+            create_letterforms(&['l'], &raw_fonts, &CreateOptions::default()),
+            Err(LetterformsError::NoUsableFonts(1))
+        ));
+
+        raw_fonts.insert(PathBuf::from("fine.ttf"), build_font(1000, &glyphs));
+        let letterforms =
+            create_letterforms(&['l'], &raw_fonts, &CreateOptions::default()).unwrap();
+        assert_eq!(
+            vec![Path::new("fine.ttf")],
+            letterforms.fonts().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![Path::new("headless.ttf")],
+            letterforms.skipped().map(|(p, _)| p).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn compare_distinguishes_missing() {
+        let present = |w| Letterform::new(Rect::new(0.0, 0.0, w, 100.0).to_path(0.1), Some(500.0));
+        let missing = Letterform::new(BezPath::new(), None);
+        let results = [
+            present(10.0).compare(&present(10.0), RULES),
+            present(10.0).compare(&present(90.0), RULES),
+            present(10.0).compare(&missing, RULES),
+            missing.compare(&present(10.0), RULES),
+            missing.compare(&missing, RULES),
+        ];
+        assert!(
+            matches!(
+                results,
+                [
+                    Comparison::Match,
+                    Comparison::Differ(..),
+                    Comparison::OneMissing,
+                    Comparison::OneMissing,
+                    Comparison::BothMissing,
+                ]
+            ),
+            "{results:?}"
+        );
+    }
+
+    #[test]
+    fn measures_cap_height_without_os2() {
+        let h = Rect::new(0.0, 0.0, 500.0, 700.0).to_path(0.1);
+        let x = Rect::new(0.0, 0.0, 400.0, 500.0).to_path(0.1);
+        let with_h = build_font(1000, &[('H', h), ('x', x.clone())]);
+        let without_caps = build_font(1000, &[('x', x)]);
+        assert_eq!(
+            Some((700.0, CapHeightSource::Measured('H'))),
+            resolve_cap_height(&FontRef::new(&with_h).unwrap(), LocationRef::default())
+        );
+        assert_eq!(
+            None,
+            resolve_cap_height(
+                &FontRef::new(&without_caps).unwrap(),
+                LocationRef::default()
+            )
+        );
+    }
+
+    #[test]
+    fn normalizes_cap_height() {
+        let glyphs = |scale: f64| {
+            [
+                ('H', Rect::new(0.0, 0.0, 500.0, 700.0)),
+                ('o', Rect::new(0.0, 0.0, 400.0, 500.0)),
+            ]
+            .map(|(c, rect)| (c, Affine::scale(scale) * rect.to_path(0.1)))
+        };
+        let raw_fonts = HashMap::from([
+            (PathBuf::from("big.ttf"), build_font(1000, &glyphs(1.0))),
+            (PathBuf::from("small.ttf"), build_font(1000, &glyphs(0.5))),
+        ]);
+        let o_heights = |normalize_cap_height| {
+            let letterforms = create_letterforms(
+                &['H', 'o'],
+                &raw_fonts,
+                &CreateOptions {
+                    normalize_cap_height,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            ["big.ttf", "small.ttf"].map(|font| {
+                let o = letterforms.get(Path::new(font), 'o').unwrap();
+                o.path.control_box().height().round()
+            })
+        };
+        assert_eq!([500.0, 250.0], o_heights(false));
+        assert_eq!([500.0, 500.0], o_heights(true));
+    }
+
+    #[test]
+    fn drops_tiny_contours() {
+        let mut path = Rect::new(0.0, 0.0, 100.0, 100.0).to_path(0.1);
+        // a sliver hanging off the bottom left
+        path.extend(Rect::new(-3.0, -3.0, 0.0, 0.0).to_path(0.1));
+        let mut letterform = Letterform::new(path, Some(500.0));
+        assert_eq!(0, letterform.drop_tiny_contours(1.0));
+        assert_eq!(1, letterform.drop_tiny_contours(5.0));
+        assert_eq!(
+            Rect::new(0.0, 0.0, 100.0, 100.0),
+            letterform.path.control_box()
+        );
+    }
+
+    #[test]
+    fn remove_overlaps() {
+        // a T drawn as an overlapping bar and stem and as a single merged contour
+        let mut overlapping = Rect::new(0.0, 400.0, 400.0, 500.0).to_path(0.1);
+        overlapping.extend(Rect::new(150.0, 0.0, 250.0, 450.0).to_path(0.1));
+        let mut merged = BezPath::new();
+        merged.move_to((150.0, 0.0));
+        merged.line_to((250.0, 0.0));
+        merged.line_to((250.0, 400.0));
+        merged.line_to((400.0, 400.0));
+        merged.line_to((400.0, 500.0));
+        merged.line_to((0.0, 500.0));
+        merged.line_to((0.0, 400.0));
+        merged.line_to((150.0, 400.0));
+        merged.close_path();
+        let letterform = |path| Letterform::new(path, Some(400.0));
+        let mut overlapping = letterform(overlapping);
+        let mut merged = letterform(merged);
+        assert!(overlapping.approximately_equal(&merged, RULES).is_err());
+
+        overlapping.remove_overlaps();
+        merged.remove_overlaps();
+        overlapping.approximately_equal(&merged, RULES).unwrap();
+        merged.approximately_equal(&overlapping, RULES).unwrap();
+    }
+
+    #[test]
+    fn fingerprints() {
+        let letterform =
+            |x| Letterform::new(Rect::new(x, 0.0, 100.0, 100.0).to_path(0.1), Some(500.0));
+        let mut respaced = letterform(0.0);
+        respaced.advance = Some(600.0);
+        assert_eq!(fingerprint(&letterform(0.0)), fingerprint(&letterform(0.0)));
+        assert_ne!(fingerprint(&letterform(0.0)), fingerprint(&letterform(1.0)));
+        assert_ne!(fingerprint(&letterform(0.0)), fingerprint(&respaced));
+    }
+
+    #[test]
+    fn groups_faces_of_a_collection() {
+        let bar = Rect::new(0.0, 0.0, 40.0, 120.0).to_path(0.1);
+        let dot = Rect::new(0.0, 0.0, 40.0, 40.0).to_path(0.1);
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("fam.ttc"),
+                build_collection(&[
+                    build_font(1000, &[('l', bar.clone())]),
+                    build_font(1000, &[('l', dot)]),
+                ]),
+            ),
+            (PathBuf::from("bar.ttf"), build_font(1000, &[('l', bar)])),
+        ]);
+        let letterforms =
+            create_letterforms(&['l'], &raw_fonts, &CreateOptions::default()).unwrap();
+        let first = face_id(Path::new("fam.ttc"), 0);
+        let second = face_id(Path::new("fam.ttc"), 1);
+        assert_eq!(
+            vec![Path::new("bar.ttf"), &first, &second],
+            letterforms
+                .fonts()
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+
+        let groups = group_letterforms(RULES, &['l'], &letterforms).unwrap();
+        let mut members = groups[&'l']
+            .iter()
+            .map(|g| g.members.iter().copied().collect::<BTreeSet<_>>())
+            .collect::<Vec<_>>();
+        members.sort();
+        assert_eq!(
+            vec![
+                BTreeSet::from([Path::new("bar.ttf"), &first]),
+                BTreeSet::from([second.as_path()]),
+            ],
+            members
+        );
+    }
+
+    #[test]
+    fn common_chars() {
+        let bar = Rect::new(0.0, 0.0, 40.0, 120.0).to_path(0.1);
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("il.ttf"),
+                build_font(1000, &[('i', bar.clone()), ('l', bar.clone())]),
+            ),
+            (
+                PathBuf::from("lj.ttf"),
+                build_font(1000, &[('l', bar.clone()), ('j', bar)]),
+            ),
+        ]);
+        let letterforms =
+            create_letterforms(&['i', 'j', 'l'], &raw_fonts, &CreateOptions::default()).unwrap();
+        assert_eq!(vec!['l'], letterforms.common_chars(&['i', 'j', 'l']));
+    }
+
+    #[test]
+    fn faux_lowercase_is_scaled_uppercase() {
+        let rect = |w, h| Rect::new(0.0, 0.0, w, h).to_path(0.1);
+        let raw_fonts = HashMap::from([(
+            PathBuf::from("faux.ttf"),
+            build_font(
+                1000,
+                &[
+                    ('I', rect(100.0, 700.0)),
+                    ('i', rect(50.0, 350.0)),
+                    ('L', rect(300.0, 700.0)),
+                    ('l', rect(100.0, 700.0)),
+                ],
+            ),
+        )]);
+        let test_chars = with_lowercase(&['I', 'L']);
+        assert_eq!(vec!['I', 'L', 'i', 'l'], test_chars);
+        let letterforms =
+            create_letterforms(&test_chars, &raw_fonts, &CreateOptions::default()).unwrap();
+        assert_eq!(
+            vec![('I', 'i')],
+            faux_lowercase(&letterforms, Path::new("faux.ttf"), &test_chars, RULES)
+        );
+    }
+
+    #[test]
+    fn max_upem_caps_scaling() {
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("1000.ttf"),
+                build_font(
+                    1000,
+                    &[('l', Rect::new(0.0, 0.0, 40.0, 120.0).to_path(0.1))],
+                ),
+            ),
+            (
+                PathBuf::from("4000.ttf"),
+                build_font(
+                    4000,
+                    &[('l', Rect::new(0.0, 0.0, 160.0, 480.0).to_path(0.1))],
+                ),
+            ),
+        ]);
+        let letterforms = create_letterforms(
+            &['l'],
+            &raw_fonts,
+            &CreateOptions {
+                max_upem: Some(2000),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(2000, letterforms.upem);
+        let sizes = ["1000.ttf", "4000.ttf"]
+            .into_iter()
+            .map(|p| {
+                letterforms
+                    .get(Path::new(p), 'l')
+                    .unwrap()
+                    .path
+                    .bounding_box()
+                    .size()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec![Size::new(80.0, 240.0); 2], sizes);
+    }
+
+    #[test]
+    fn removes_groups_matching_baseline() {
+        let period = |size| Rect::new(0.0, 0.0, size, size).to_path(0.1);
+        let a = Rect::new(0.0, 0.0, 300.0, 500.0).to_path(0.1);
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_font(1000, &[('.', period(100.0)), ('a', a.clone())]),
+            ),
+            (
+                PathBuf::from("b.ttf"),
+                build_font(1000, &[('.', period(100.0)), ('a', a)]),
+            ),
+        ]);
+        let test_chars = ['.', 'a'];
+        let letterforms =
+            create_letterforms(&test_chars, &raw_fonts, &CreateOptions::default()).unwrap();
+        // at twice the upem, so only the same once scaled
+        let baseline = create_baseline_letterforms(
+            &test_chars,
+            Path::new("baseline.ttf"),
+            &build_font(
+                2000,
+                &[
+                    ('.', period(200.0)),
+                    ('a', Rect::new(0.0, 0.0, 10.0, 10.0).to_path(0.1)),
+                ],
+            ),
+            letterforms.upem,
+        )
+        .unwrap();
+        let mut groups = group_letterforms(RULES, &test_chars, &letterforms).unwrap();
+
+        let removed = remove_baseline_groups(&mut groups, &letterforms, &baseline, RULES);
+        assert_eq!(BTreeMap::from([('.', 2)]), removed);
+        assert!(groups[&'.'].is_empty());
+        assert_eq!(1, groups[&'a'].len());
+    }
+
+    #[test]
+    fn compares_by_glyph_name() {
+        let alt = Rect::new(0.0, 0.0, 300.0, 500.0).to_path(0.1);
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_named_font(
+                    1000,
+                    &[
+                        ('a', Rect::new(0.0, 0.0, 50.0, 50.0).to_path(0.1)),
+                        ('b', alt.clone()),
+                    ],
+                    &["a", "a.alt"],
+                ),
+            ),
+            // the same alternate, encoded differently
+            (
+                PathBuf::from("b.ttf"),
+                build_named_font(1000, &[('z', alt)], &["a.alt"]),
+            ),
+        ]);
+        let glyph_names = ["a.alt".to_string(), "nope".to_string()];
+        let test_chars = [glyph_name_char(0), glyph_name_char(1)];
+        let letterforms = create_letterforms(
+            &test_chars,
+            &raw_fonts,
+            &CreateOptions {
+                glyph_names: glyph_names.to_vec(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(Some("a.alt"), letterforms.glyph_name(test_chars[0]));
+        assert_eq!("/nope", letterforms.label(test_chars[1]));
+        assert_eq!("a", letterforms.label('a'));
+
+        let groups = group_letterforms(RULES, &test_chars, &letterforms).unwrap();
+        assert_eq!(1, groups[&test_chars[0]].len());
+        assert!(!groups[&test_chars[0]][0].all_missing(test_chars[0], &letterforms));
+        assert!(groups[&test_chars[1]]
+            .iter()
+            .all(|g| g.all_missing(test_chars[1], &letterforms)));
+    }
+
+    #[test]
+    fn reads_cff_charsets() {
+        // format 0, a sid per glyph
+        assert_eq!(
+            Some(vec![0, 391, 66]),
+            cff_charset_sids(&[0, 1, 135, 0, 66], 3)
+        );
+        // format 1, runs of sids; the run is cut short by the glyph count
+        assert_eq!(
+            Some(vec![0, 66, 67, 68, 391]),
+            cff_charset_sids(&[1, 0, 66, 2, 1, 135, 9], 5)
+        );
+        // format 2, runs with 16 bit lengths
+        assert_eq!(
+            Some(vec![0, 391, 392]),
+            cff_charset_sids(&[2, 1, 135, 0, 1], 3)
+        );
+        assert_eq!(None, cff_charset_sids(&[0, 1, 135], 3));
+        assert_eq!(None, cff_charset_sids(&[3], 3));
+    }
+
+    #[test]
+    fn merges_rotated_groups() {
+        // an L, so every rotation is distinct
+        let ell = |w: f64, h: f64| {
+            let mut path = Rect::new(0.0, 0.0, w / 4.0, h).to_path(0.1);
+            path.extend(Rect::new(w / 4.0, 0.0, w, h / 4.0).to_path(0.1));
+            path
+        };
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_font(1000, &[('x', ell(200.0, 400.0))]),
+            ),
+            (
+                PathBuf::from("b.ttf"),
+                build_font(1000, &[('x', rotate(&ell(200.0, 400.0), 90))]),
+            ),
+            (
+                PathBuf::from("c.ttf"),
+                build_font(1000, &[('x', ell(400.0, 400.0))]),
+            ),
+        ]);
+        let letterforms =
+            create_letterforms(&['x'], &raw_fonts, &CreateOptions::default()).unwrap();
+        let mut groups = group_letterforms(RULES, &['x'], &letterforms).unwrap();
+        assert_eq!(3, groups[&'x'].len());
+
+        let merged = merge_rotated_groups(&mut groups, &letterforms, RULES);
+        assert_eq!(2, groups[&'x'].len());
+        assert_eq!(1, merged.len(), "{merged:?}");
+        let fonts = [&merged[0].merged, &merged[0].into]
+            .into_iter()
+            .flatten()
+            .map(|p| p.to_str().unwrap())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(BTreeSet::from(["a.ttf", "b.ttf"]), fonts);
+        assert!([90, 270].contains(&merged[0].transform), "{merged:?}");
+    }
+
+    #[test]
+    fn logs_compared_pairs() {
+        let bar = Rect::new(0.0, 0.0, 40.0, 120.0).to_path(0.1);
+        let dot = Rect::new(0.0, 0.0, 40.0, 40.0).to_path(0.1);
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_font(1000, &[('l', bar.clone())]),
+            ),
+            (PathBuf::from("b.ttf"), build_font(1000, &[('l', dot)])),
+            (PathBuf::from("c.ttf"), build_font(1000, &[('l', bar)])),
+        ]);
+        let letterforms =
+            create_letterforms(&['l'], &raw_fonts, &CreateOptions::default()).unwrap();
+        let mut compared = Vec::new();
+        let groups =
+            group_letterforms_logged(RULES, &['l'], &letterforms, Some(&mut compared)).unwrap();
+        assert_eq!(2, groups[&'l'].len());
+        let (a, b, c) = (Path::new("a.ttf"), Path::new("b.ttf"), Path::new("c.ttf"));
+        // b is compared to a, c to both a and b
+        assert_eq!(
+            BTreeSet::from([('l', a, b), ('l', a, c), ('l', b, c)]),
+            compared.into_iter().collect::<BTreeSet<_>>()
+        );
+    }
+
+    #[test]
+    fn tries_identical_members_first() {
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_font(
+                    1000,
+                    &[('l', Rect::new(0.0, 0.0, 100.5, 600.0).to_path(0.1))],
+                ),
+            ),
+            (
+                PathBuf::from("b.ttf"),
+                build_font(
+                    1000,
+                    &[('l', Rect::new(0.0, 0.0, 100.0, 600.0).to_path(0.1))],
+                ),
+            ),
+            (
+                PathBuf::from("c.ttf"),
+                build_font(
+                    1000,
+                    &[('l', Rect::new(0.0, 0.0, 100.0, 600.0).to_path(0.1))],
+                ),
+            ),
+        ]);
+        let letterforms =
+            create_letterforms(&['l'], &raw_fonts, &CreateOptions::default()).unwrap();
+        let mut compared = Vec::new();
+        let groups =
+            group_letterforms_logged(RULES, &['l'], &letterforms, Some(&mut compared)).unwrap();
+        assert_eq!(1, groups[&'l'].len());
+        let (a, b, c) = (Path::new("a.ttf"), Path::new("b.ttf"), Path::new("c.ttf"));
+        // c is identical to b so it's never compared to a
+        assert_eq!(vec![('l', a, b), ('l', b, c)], compared);
+    }
+
+    #[test]
+    fn merges_groups_that_match_the_other_way_round() {
+        let bar = Rect::new(0.0, 0.0, 40.0, 120.0).to_path(0.1);
+        let mut dotted = bar.clone();
+        // below the bar, so planting at 0,0 puts the bar in the same place in both
+        dotted.extend(Rect::new(0.0, -120.0, 40.0, -80.0).to_path(0.1));
+        let raw_fonts = HashMap::from([
+            (PathBuf::from("a.ttf"), build_font(1000, &[('i', dotted)])),
+            (PathBuf::from("b.ttf"), build_font(1000, &[('i', bar)])),
+        ]);
+        let letterforms =
+            create_letterforms(&['i'], &raw_fonts, &CreateOptions::default()).unwrap();
+        let one_way = RulesOfSimilarity {
+            symmetric: false,
+            ..RULES
+        };
+        let (a, b) = (Path::new("a.ttf"), Path::new("b.ttf"));
+        // the bar lies on the dotted i but not the other way round, so b doesn't join a
+        let (dotted, bar) = (
+            letterforms.get(a, 'i').unwrap(),
+            letterforms.get(b, 'i').unwrap(),
+        );
+        assert!(matches!(
+            dotted.compare(bar, one_way),
+            Comparison::Differ(..)
+        ));
+        assert!(matches!(bar.compare(dotted, one_way), Comparison::Match));
+
+        let mut groups =
+            HashMap::from([('i', vec![LetterformGroup::new(a), LetterformGroup::new(b)])]);
+        let merged = merge_matching_groups(&mut groups, &letterforms, one_way);
+        assert_eq!(1, merged.len(), "{merged:?}");
+        assert_eq!(
+            vec![BTreeSet::from([a, b])],
+            groups[&'i']
+                .iter()
+                .map(|g| g.members.clone())
+                .collect::<Vec<_>>()
+        );
+
+        let groups = group_letterforms(one_way, &['i'], &letterforms).unwrap();
+        assert_eq!(1, groups[&'i'].len());
+    }
+
+    #[test]
+    fn post_processors_adjust_groups_before_counting() {
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_font(
+                    1000,
+                    &[('l', Rect::new(0.0, 0.0, 100.0, 600.0).to_path(0.1))],
+                ),
+            ),
+            (
+                PathBuf::from("b.ttf"),
+                build_font(
+                    1000,
+                    &[('l', Rect::new(0.0, 0.0, 300.0, 600.0).to_path(0.1))],
+                ),
+            ),
+        ]);
+        let letterforms =
+            create_letterforms(&['l'], &raw_fonts, &CreateOptions::default()).unwrap();
+        let (_, shared) = find_shared(RULES, &['l'], &letterforms, &[]).unwrap();
+        assert!(shared.is_empty());
+
+        // Decide by external rules that all the l's are the same after all
+        let merge = |groups: &mut HashMap<char, Vec<LetterformGroup>>, _: &Letterforms| {
+            for groups in groups.values_mut() {
+                let mut merged = groups.remove(0);
+                for group in groups.drain(..) {
+                    merged.members.extend(group.members);
+                }
+                groups.push(merged);
+            }
+        };
+        let (groups, shared) = find_shared(RULES, &['l'], &letterforms, &[&merge]).unwrap();
+        assert_eq!(1, groups[&'l'].len());
+        assert_eq!(
+            ShareCounts::from([(
+                BTreeSet::from([Path::new("a.ttf"), Path::new("b.ttf")]),
+                BTreeSet::from(['l'])
+            )]),
+            shared
+        );
+    }
+
+    #[test]
+    fn merges_stretched_groups() {
+        // an L again, so stretching is visible
+        let ell = |w: f64, h: f64| {
+            let mut path = Rect::new(0.0, 0.0, w / 4.0, h).to_path(0.1);
+            path.extend(Rect::new(w / 4.0, 0.0, w, h / 4.0).to_path(0.1));
+            path
+        };
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("regular.ttf"),
+                build_font(1000, &[('x', ell(200.0, 400.0))]),
+            ),
+            (
+                PathBuf::from("condensed.ttf"),
+                build_font(1000, &[('x', ell(100.0, 400.0))]),
+            ),
+            (
+                PathBuf::from("other.ttf"),
+                build_font(
+                    1000,
+                    &[('x', Rect::new(0.0, 0.0, 200.0, 400.0).to_path(0.1))],
+                ),
+            ),
+        ]);
+        let letterforms =
+            create_letterforms(&['x'], &raw_fonts, &CreateOptions::default()).unwrap();
+        let mut groups = group_letterforms(RULES, &['x'], &letterforms).unwrap();
+        assert_eq!(3, groups[&'x'].len());
+
+        let merged = merge_stretched_groups(&mut groups, &letterforms, RULES);
+        assert_eq!(2, groups[&'x'].len());
+        assert_eq!(1, merged.len(), "{merged:?}");
+        let fonts = [&merged[0].merged, &merged[0].into]
+            .into_iter()
+            .flatten()
+            .map(|p| p.to_str().unwrap())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(BTreeSet::from(["condensed.ttf", "regular.ttf"]), fonts);
+        let x_scale = merged[0].transform.max(1.0 / merged[0].transform);
+        assert!((x_scale - 2.0).abs() < 1e-9, "{merged:?}");
+    }
+
+    #[test]
+    fn best_matches_only_compare_across_sets() {
+        let tall = Rect::new(0.0, 0.0, 100.0, 600.0).to_path(0.1);
+        let wide = Rect::new(0.0, 0.0, 600.0, 100.0).to_path(0.1);
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("lib/a.ttf"),
+                build_font(1000, &[('l', tall.clone()), ('-', wide.clone())]),
+            ),
+            (
+                PathBuf::from("lib/b.ttf"),
+                build_font(1000, &[('l', tall.clone())]),
+            ),
+            (
+                PathBuf::from("new/copy.ttf"),
+                build_font(1000, &[('l', tall), ('-', wide.clone())]),
+            ),
+            // matches only the other new font
+            (
+                PathBuf::from("new/original.ttf"),
+                build_font(1000, &[('l', wide.clone()), ('-', wide.clone())]),
+            ),
+            (
+                PathBuf::from("new/original-copy.ttf"),
+                build_font(
+                    1000,
+                    &[
+                        ('l', wide),
+                        ('-', Rect::new(0.0, 0.0, 300.0, 50.0).to_path(0.1)),
+                    ],
+                ),
+            ),
+        ]);
+        let test_chars = ['-', 'l'];
+        let letterforms =
+            create_letterforms(&test_chars, &raw_fonts, &CreateOptions::default()).unwrap();
+        let library = [Path::new("lib/a.ttf"), Path::new("lib/b.ttf")];
+        let candidates = [Path::new("new/copy.ttf"), Path::new("new/original.ttf")];
+
+        let best = best_matches(RULES, &test_chars, &letterforms, &library, &candidates);
+        assert_eq!(
+            vec![
+                BestMatch {
+                    candidate: Path::new("new/copy.ttf"),
+                    library_font: Some(Path::new("lib/a.ttf")),
+                    chars: BTreeSet::from(['-', 'l']),
+                },
+                BestMatch {
+                    candidate: Path::new("new/original.ttf"),
+                    library_font: Some(Path::new("lib/a.ttf")),
+                    chars: BTreeSet::from(['-']),
+                }
+            ],
+            best
+        );
+    }
+
+    #[test]
+    fn raw_skips_normalization() {
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_font(
+                    1000,
+                    &[('l', Rect::new(0.0, 0.0, 100.0, 600.0).to_path(0.1))],
+                ),
+            ),
+            (
+                PathBuf::from("moved.ttf"),
+                // vertically, as glyf outlines are shifted horizontally to match hmtx lsb
+                build_font(
+                    1000,
+                    &[('l', Rect::new(0.0, 50.0, 100.0, 650.0).to_path(0.1))],
+                ),
+            ),
+            (
+                PathBuf::from("2000.ttf"),
+                build_font(
+                    2000,
+                    &[('l', Rect::new(0.0, 0.0, 200.0, 1200.0).to_path(0.1))],
+                ),
+            ),
+        ]);
+        let num_groups = |raw| {
+            let options = CreateOptions {
+                raw,
+                ..Default::default()
+            };
+            let letterforms = create_letterforms(&['l'], &raw_fonts, &options).unwrap();
+            group_letterforms(RULES, &['l'], &letterforms).unwrap()[&'l'].len()
+        };
+        assert_eq!(1, num_groups(false));
+        assert_eq!(3, num_groups(true));
+    }
+
+    #[test]
+    fn groups_in_sorted_font_order() {
+        let bar = |w| Rect::new(0.0, 0.0, w, 600.0).to_path(0.1);
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("c.ttf"),
+                build_font(1000, &[('l', bar(100.0))]),
+            ),
+            (
+                PathBuf::from("a.ttf"),
+                build_font(1000, &[('l', bar(300.0))]),
+            ),
+            (
+                PathBuf::from("b.ttf"),
+                build_font(1000, &[('l', bar(200.0))]),
+            ),
+        ]);
+        let letterforms =
+            create_letterforms(&['l'], &raw_fonts, &CreateOptions::default()).unwrap();
+        let groups = group_letterforms(RULES, &['l'], &letterforms).unwrap();
+        assert_eq!(
+            vec!["a.ttf", "b.ttf", "c.ttf"],
+            groups[&'l']
+                .iter()
+                .map(|g| g.members.iter().next().unwrap().to_str().unwrap())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn merges_groups_bridged_by_a_letterform() {
+        // a ~ c and c ~ b but a !~ b; c is grouped last so joins, and merges, both groups
+        let bar = |w| Rect::new(0.0, 0.0, w, 600.0).to_path(0.1);
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_font(1000, &[('l', bar(100.0))]),
+            ),
+            (
+                PathBuf::from("b.ttf"),
+                build_font(1000, &[('l', bar(104.0))]),
+            ),
+            (
+                PathBuf::from("c.ttf"),
+                build_font(1000, &[('l', bar(102.0))]),
+            ),
+        ]);
+        let letterforms =
+            create_letterforms(&['l'], &raw_fonts, &CreateOptions::default()).unwrap();
+        let a = letterforms.get(Path::new("a.ttf"), 'l').unwrap();
+        let b = letterforms.get(Path::new("b.ttf"), 'l').unwrap();
+        assert!(a.approximately_equal(b, RULES).is_err());
+
+        let groups = group_letterforms(RULES, &['l'], &letterforms).unwrap();
+        assert_eq!(1, groups[&'l'].len());
+        assert_eq!(3, groups[&'l'][0].members.len());
+    }
+
+    #[test]
+    fn shared_absence_is_not_a_match() {
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_font(
+                    1000,
+                    &[('a', Rect::new(0.0, 0.0, 100.0, 600.0).to_path(0.1))],
+                ),
+            ),
+            (
+                PathBuf::from("b.ttf"),
+                build_font(
+                    1000,
+                    &[('a', Rect::new(0.0, 0.0, 400.0, 300.0).to_path(0.1))],
+                ),
+            ),
+        ]);
+        let test_chars = ['a', '€'];
+        let letterforms =
+            create_letterforms(&test_chars, &raw_fonts, &CreateOptions::default()).unwrap();
+        let groups = group_letterforms(RULES, &test_chars, &letterforms).unwrap();
+        assert!(groups[&'€'].is_empty());
+        assert_eq!(2, groups[&'a'].len());
+        assert!(share_counts(&groups, &letterforms, 2).is_empty());
+    }
+
+    #[test]
+    fn normalizes_scale() {
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_font(
+                    1000,
+                    &[('l', Rect::new(0.0, 0.0, 100.0, 600.0).to_path(0.1))],
+                ),
+            ),
+            (
+                PathBuf::from("b.ttf"),
+                build_font(
+                    1000,
+                    &[('l', Rect::new(0.0, 0.0, 80.0, 480.0).to_path(0.1))],
+                ),
+            ),
+        ]);
+        let mut letterforms =
+            create_letterforms(&['l'], &raw_fonts, &CreateOptions::default()).unwrap();
+        let num_groups = |letterforms: &Letterforms| {
+            group_letterforms(RULES, &['l'], letterforms).unwrap()[&'l'].len()
+        };
+        assert_eq!(2, num_groups(&letterforms));
+        letterforms.normalize_scale();
+        assert_eq!(1, num_groups(&letterforms));
+        assert_eq!(
+            1000.0,
+            letterforms
+                .get(Path::new("a.ttf"), 'l')
+                .unwrap()
+                .path
+                .control_box()
+                .height()
+        );
+    }
+
+    #[test]
+    fn skips_groups_below_min_size() {
+        let bar = Rect::new(0.0, 0.0, 100.0, 600.0).to_path(0.1);
+        let raw_fonts = ["a.ttf", "b.ttf", "c.ttf"]
+            .into_iter()
+            .map(|f| (PathBuf::from(f), build_font(1000, &[('l', bar.clone())])))
+            .collect::<HashMap<_, _>>();
+        let letterforms =
+            create_letterforms(&['l'], &raw_fonts, &CreateOptions::default()).unwrap();
+        let groups = group_letterforms(RULES, &['l'], &letterforms).unwrap();
+        assert_eq!(1, share_counts(&groups, &letterforms, 3).len());
+        assert!(share_counts(&groups, &letterforms, 4).is_empty());
+    }
+
+    #[test]
+    fn ranks_clusters_deterministically() {
+        // pairs of fonts share 2, 2 and 1 of l and o, everything else is unique
+        let shared = [2, 2, 1];
+        let raw_fonts = ["a.ttf", "b.ttf", "c.ttf", "d.ttf", "e.ttf", "f.ttf"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, file)| {
+                let pair = i / 2;
+                let glyphs = ['l', 'o']
+                    .into_iter()
+                    .enumerate()
+                    .map(|(j, c)| {
+                        let mut width = 50.0 + 40.0 * pair as f64;
+                        if j >= shared[pair] {
+                            width += 200.0 + 17.0 * i as f64;
+                        }
+                        let height = 300.0 + 100.0 * j as f64;
+                        (c, Rect::new(0.0, 0.0, width, height).to_path(0.1))
+                    })
+                    .collect::<Vec<_>>();
+                (PathBuf::from(file), build_font(1000, &glyphs))
+            })
+            .collect::<HashMap<_, _>>();
+        let report = || {
+            let letterforms =
+                create_letterforms(&['l', 'o'], &raw_fonts, &CreateOptions::default()).unwrap();
+            let groups = group_letterforms(RULES, &['l', 'o'], &letterforms).unwrap();
+            let mut clusters = share_counts(&groups, &letterforms, 2)
+                .into_iter()
+                .collect::<Vec<_>>();
+            rank_clusters(&mut clusters, |matched| matched.len() as f64);
+            format!("{clusters:?}")
+        };
+        let first = report();
+        assert_eq!(
+            r#"[({"a.ttf", "b.ttf"}, {'l', 'o'}), ({"c.ttf", "d.ttf"}, {'l', 'o'}), ({"e.ttf", "f.ttf"}, {'l'})]"#,
+            first
+        );
+        assert_eq!(first, report());
+    }
+
+    #[test]
+    fn truncated_font_is_an_error() {
+        let mut bytes = build_font(
+            1000,
+            &[('a', Rect::new(0.0, 0.0, 100.0, 600.0).to_path(0.1))],
+        );
+        let glyf = FontRef::new(&bytes)
+            .unwrap()
+            .table_directory
+            .table_records()
+            .iter()
+            .find(|r| r.tag() == Tag::new(b"glyf"))
+            .unwrap()
+            .offset() as usize;
+        bytes.truncate(glyf + 2);
+
+        let font = FontRef::new(&bytes).unwrap();
+        assert!(matches!(
+            Letterform::create(&font, 'a', 1.0, LocationRef::default()),
+            Err(LetterformError::NoOutline(..))
+        ));
+        let raw_fonts = HashMap::from([(PathBuf::from("truncated.ttf"), bytes)]);
+        assert!(matches!(
+            create_letterforms(&['a'], &raw_fonts, &CreateOptions::default()),
+            Err(LetterformsError::NoUsableFonts(1))
+        ));
+    }
+
+    #[test]
+    fn weights_by_diversity() {
+        let bar = |w| Rect::new(0.0, 0.0, w, 600.0).to_path(0.1);
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_font(1000, &[('l', bar(100.0)), ('a', bar(200.0))]),
+            ),
+            (
+                PathBuf::from("b.ttf"),
+                build_font(1000, &[('l', bar(100.0)), ('a', bar(300.0))]),
+            ),
+            (
+                PathBuf::from("c.ttf"),
+                build_font(1000, &[('l', bar(100.0)), ('a', bar(400.0))]),
+            ),
+        ]);
+        let test_chars = ['a', 'l', 'x'];
+        let letterforms =
+            create_letterforms(&test_chars, &raw_fonts, &CreateOptions::default()).unwrap();
+        let groups = group_letterforms(RULES, &test_chars, &letterforms).unwrap();
+        let weights = diversity(&groups, &letterforms);
+        assert_eq!(HashMap::from([('a', 3), ('l', 1), ('x', 0)]), weights);
+        let weights = weights
+            .into_iter()
+            .map(|(c, w)| (c, w as f64))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(
+            0.25,
+            weighted_score(&BTreeSet::from(['l']), &test_chars, &weights)
+        );
+    }
+
+    #[test]
+    fn weights_by_char() {
+        // Matching the letters but not the punctuation is 2/4 unweighted
+        let test_chars = ['a', 'b', '.', ','];
+        let matched = BTreeSet::from(['a', 'b']);
+        let weights = HashMap::from([('.', 0.2), (',', 0.2)]);
+        let score = weighted_score(&matched, &test_chars, &weights);
+        assert!(score >= 0.8, "{score}");
+        // and punctuation alone falls well short
+        let score = weighted_score(&BTreeSet::from(['.', ',']), &test_chars, &weights);
+        assert!(score < 0.2, "{score}");
+    }
+
+    #[test]
+    fn finds_nearest_neighbors() {
+        let bar = |w| Rect::new(0.0, 0.0, w, 600.0).to_path(0.1);
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_font(
+                    1000,
+                    &[('a', bar(100.0)), ('b', bar(100.0)), ('c', bar(100.0))],
+                ),
+            ),
+            (
+                PathBuf::from("b.ttf"),
+                build_font(
+                    1000,
+                    &[('a', bar(100.0)), ('b', bar(100.0)), ('c', bar(200.0))],
+                ),
+            ),
+            (
+                PathBuf::from("c.ttf"),
+                build_font(
+                    1000,
+                    &[('a', bar(100.0)), ('b', bar(300.0)), ('c', bar(300.0))],
+                ),
+            ),
+        ]);
+        let test_chars = ['a', 'b', 'c'];
+        let letterforms =
+            create_letterforms(&test_chars, &raw_fonts, &CreateOptions::default()).unwrap();
+        let groups = group_letterforms(RULES, &test_chars, &letterforms).unwrap();
+        let nearest = nearest_neighbors(&groups, &letterforms)
+            .into_iter()
+            .map(|(font, n)| (font, (n.font, n.chars)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (
+                    Path::new("a.ttf"),
+                    (Path::new("b.ttf"), BTreeSet::from(['a', 'b']))
+                ),
+                (
+                    Path::new("b.ttf"),
+                    (Path::new("a.ttf"), BTreeSet::from(['a', 'b']))
+                ),
+                (
+                    Path::new("c.ttf"),
+                    (Path::new("a.ttf"), BTreeSet::from(['a']))
+                ),
+            ],
+            nearest
+        );
+    }
+
+    #[test]
+    fn reused_pen_matches_fresh_pen() {
+        let font = build_font(
+            1000,
+            &[
+                ('a', Rect::new(0.0, 0.0, 100.0, 600.0).to_path(0.1)),
+                ('b', Rect::new(50.0, 0.0, 400.0, 300.0).to_path(0.1)),
+            ],
+        );
+        let font = FontRef::new(&font).unwrap();
+        let cmap = font.charmap();
+        let mut pen = GlyphPen::default();
+        for c in ['a', 'x', 'b', 'a'] {
+            let gid = cmap.map(c);
+            let fresh = Letterform::create_glyph(&font, gid, 1.0, LocationRef::default()).unwrap();
+            let reused =
+                Letterform::create_glyph_with(&mut pen, &font, gid, 1.0, LocationRef::default())
+                    .unwrap();
+            assert_eq!(
+                (fresh.path.elements(), fresh.advance),
+                (reused.path.elements(), reused.advance),
+                "{c}"
+            );
+        }
+    }
+
+    #[test]
+    fn measures_spread() {
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_font(
+                    1000,
+                    &[('a', Rect::new(0.0, 0.0, 100.0, 600.0).to_path(0.1))],
+                ),
+            ),
+            (
+                PathBuf::from("b.ttf"),
+                build_font(
+                    1000,
+                    &[('a', Rect::new(0.0, 0.0, 100.0, 602.0).to_path(0.1))],
+                ),
+            ),
+            (PathBuf::from("c.ttf"), build_font(1000, &[])),
+        ]);
+        let letterforms =
+            create_letterforms(&['a'], &raw_fonts, &CreateOptions::default()).unwrap();
+        let a = Path::new("a.ttf");
+        let b = Path::new("b.ttf");
+        let c = Path::new("c.ttf");
+        assert_eq!(0.0, spread([a, c], 'a', &letterforms, RULES));
+        let spread = spread([a, b, c], 'a', &letterforms, RULES);
+        assert!((spread - 2.0).abs() < 0.01, "{spread}");
+    }
+
+    #[test]
+    fn all_instances_are_separate_fonts() {
+        let bar = |w| Rect::new(0.0, 0.0, w, 600.0).to_path(0.1);
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("Var[wght].ttf"),
+                build_variable_font(
+                    1000,
+                    &[('l', bar(50.0), bar(250.0))],
+                    &[("Thin", 100.0), ("Black", 900.0)],
+                ),
+            ),
+            (
+                PathBuf::from("Static.ttf"),
+                build_font(1000, &[('l', bar(50.0))]),
+            ),
+        ]);
+        let fonts = |all_instances| {
+            let letterforms = create_letterforms(
+                &['l'],
+                &raw_fonts,
+                &CreateOptions {
+                    all_instances,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let mut fonts = letterforms
+                .fonts()
+                .map(|p| p.to_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+            fonts.sort();
+            fonts
+        };
+        assert_eq!(vec!["Static.ttf", "Var[wght].ttf"], fonts(false));
+        assert_eq!(
+            vec!["Static.ttf", "Var[wght].ttf#Black", "Var[wght].ttf#Thin"],
+            fonts(true)
+        );
+    }
+
+    #[test]
+    fn draws_at_location() {
+        let bar = |w| Rect::new(0.0, 0.0, w, 600.0).to_path(0.1);
+        let raw_fonts = HashMap::from([(
+            PathBuf::from("Var[wght].ttf"),
+            build_variable_font(1000, &[('l', bar(50.0), bar(250.0))], &[]),
+        )]);
+        let width = |location| {
+            let letterforms = create_letterforms(
+                &['l'],
+                &raw_fonts,
+                &CreateOptions {
+                    location,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            letterforms
+                .get(Path::new("Var[wght].ttf"), 'l')
+                .unwrap()
+                .path
+                .bounding_box()
+                .width()
+                .round()
+        };
+        assert_eq!(50.0, width(Vec::new()));
+        assert_eq!(150.0, width(vec![(Tag::new(b"wght"), 500.0)]));
+        assert_eq!(250.0, width(vec![(Tag::new(b"wght"), 900.0)]));
+        // axes the font lacks don't matter
+        assert_eq!(50.0, width(vec![(Tag::new(b"wdth"), 75.0)]));
+    }
+
+    #[test]
+    fn ignores_notdef() {
+        let tofu = Rect::new(0.0, 0.0, 400.0, 600.0).to_path(0.1);
+        let bar = Rect::new(0.0, 0.0, 40.0, 120.0).to_path(0.1);
+        let dot = Rect::new(0.0, 0.0, 40.0, 40.0).to_path(0.1);
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_font_with_notdef(1000, &tofu, &[('l', bar)], &['∞']),
+            ),
+            (
+                PathBuf::from("b.ttf"),
+                build_font_with_notdef(1000, &tofu, &[('l', dot)], &['∞']),
+            ),
+        ]);
+        let num_groups = |ignore_notdef| {
+            let options = CreateOptions {
+                ignore_notdef,
+                ..Default::default()
+            };
+            let letterforms = create_letterforms(&['l', '∞'], &raw_fonts, &options).unwrap();
+            let groups = group_letterforms(RULES, &['l', '∞'], &letterforms).unwrap();
+            groups.get(&'∞').map(Vec::len).unwrap_or_default()
+        };
+        assert_eq!((1, 0), (num_groups(false), num_groups(true)));
+    }
+
+    #[test]
+    fn substitutes_forms_of_test_chars() {
+        let rect = |w: f64, h: f64| Rect::new(0.0, 0.0, w, h).to_path(0.1);
+        let beh = '\u{0628}';
+        // the same isolated form, everything else differs
+        let raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_font_with_forms(
+                    1000,
+                    &[(beh, rect(400.0, 100.0))],
+                    &[("isol", rect(500.0, 200.0)), ("init", rect(100.0, 100.0))],
+                ),
+            ),
+            (
+                PathBuf::from("b.ttf"),
+                build_font_with_forms(
+                    1000,
+                    &[(beh, rect(100.0, 400.0))],
+                    &[("isol", rect(500.0, 200.0)), ("init", rect(100.0, 300.0))],
+                ),
+            ),
+        ]);
+        let num_groups = |single_subst_forms, features: &[&[u8; 4]]| {
+            let options = CreateOptions {
+                single_subst_forms,
+                features: features.iter().map(|f| Tag::new(f)).collect(),
+                ..Default::default()
+            };
+            let letterforms = create_letterforms(&[beh], &raw_fonts, &options).unwrap();
+            group_letterforms(RULES, &[beh], &letterforms).unwrap()[&beh].len()
+        };
+        assert_eq!(
+            (2, 1, 2),
+            (
+                num_groups(false, &[]),
+                num_groups(true, &[]),
+                num_groups(true, &[b"init"])
+            )
+        );
+    }
+
+    #[test]
+    fn reads_fonts_as_needed() {
+        let dir = tempfile::tempdir().unwrap();
+        let raw_fonts = HashMap::from([
+            (
+                dir.path().join("a.ttf"),
+                build_font(
+                    1000,
+                    &[('l', Rect::new(0.0, 0.0, 40.0, 120.0).to_path(0.1))],
+                ),
+            ),
+            (
+                dir.path().join("b.ttf"),
+                build_font(
+                    2048,
+                    &[('l', Rect::new(0.0, 0.0, 80.0, 240.0).to_path(0.1))],
+                ),
+            ),
+        ]);
+        for (path, bytes) in raw_fonts.iter() {
+            fs::write(path, bytes).unwrap();
+        }
+        let paths = raw_fonts.keys().cloned().collect::<Vec<_>>();
+        let options = CreateOptions::default();
+
+        let held = create_letterforms(&['l'], &raw_fonts, &options).unwrap();
+        let read =
+            create_letterforms_from_files(&['l'], &paths, |p| fs::read(p), &options).unwrap();
+        assert_eq!(held.to_text(), read.to_text());
+
+        let missing = [dir.path().join("missing.ttf")];
+        assert!(matches!(
+            create_letterforms_from_files(&['l'], &missing, |p| fs::read(p), &options),
+            Err(LetterformsError::Io(..))
+        ));
+    }
+
+    #[test]
+    fn reuses_cached_outlines() {
+        let bar = Rect::new(0.0, 0.0, 40.0, 120.0).to_path(0.1);
+        let dot = Rect::new(0.0, 0.0, 40.0, 40.0).to_path(0.1);
+        let mut raw_fonts = HashMap::from([
+            (
+                PathBuf::from("a.ttf"),
+                build_font(1000, &[('l', bar.clone())]),
+            ),
+            (PathBuf::from("b.ttf"), build_font(1000, &[('l', bar)])),
+            (
+                PathBuf::from("c.ttf"),
+                build_font(1000, &[('l', dot.clone())]),
+            ),
+        ]);
+        let cache_dir = tempfile::tempdir().unwrap();
+        let options = CreateOptions {
+            cache_dir: Some(cache_dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let groups = |letterforms: &Letterforms| {
+            let groups = group_letterforms(RULES, &['l'], letterforms).unwrap();
+            let mut members = groups[&'l']
+                .iter()
+                .map(|g| {
+                    g.members
+                        .iter()
+                        .map(|p| p.to_path_buf())
+                        .collect::<BTreeSet<_>>()
+                })
+                .collect::<Vec<_>>();
+            members.sort();
+            members
+        };
+
+        let drawn = create_letterforms(&['l'], &raw_fonts, &options).unwrap();
+        let cached = create_letterforms(&['l'], &raw_fonts, &options).unwrap();
+        assert_eq!(groups(&drawn), groups(&cached));
+        assert_eq!(drawn.to_text(), cached.to_text());
+
+        // Swap the cached outlines of a.ttf and c.ttf; if we didn't draw they move groups
+        let files = fs::read_dir(cache_dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(2, files.len(), "a.ttf and b.ttf have the same content");
+        let contents = files
+            .iter()
+            .map(|f| fs::read_to_string(f).unwrap())
+            .collect::<Vec<_>>();
+        fs::write(&files[0], &contents[1]).unwrap();
+        fs::write(&files[1], &contents[0]).unwrap();
+        let swapped = create_letterforms(&['l'], &raw_fonts, &options).unwrap();
+        let svg = |letterforms: &Letterforms, font: &str| {
+            letterforms.get(Path::new(font), 'l').unwrap().path.to_svg()
+        };
+        assert_eq!(svg(&drawn, "a.ttf"), svg(&swapped, "c.ttf"));
+
+        // Changing the font invalidates its cached outlines
+        let mut changed_bytes = build_font(1000, &[('l', dot)]);
+        changed_bytes.push(0);
+        raw_fonts.insert(PathBuf::from("c.ttf"), changed_bytes);
+        let changed = create_letterforms(&['l'], &raw_fonts, &options).unwrap();
+        assert_eq!(svg(&drawn, "c.ttf"), svg(&changed, "c.ttf"));
+    }
+
+    #[test]
+    fn requires_a_match_at_every_location() {
+        let bar = |w| Rect::new(0.0, 0.0, w, 600.0).to_path(0.1);
+        let (a, b) = (Path::new("a[wght].ttf"), Path::new("b[wght].ttf"));
+        let raw_fonts = HashMap::from([
+            (
+                a.to_path_buf(),
+                build_variable_font(1000, &[('l', bar(50.0), bar(250.0))], &[]),
+            ),
+            // the same at either end but the middle was redrawn
+            (
+                b.to_path_buf(),
+                build_variable_font_with_middle(
+                    1000,
+                    &[('l', bar(50.0), Some(bar(350.0)), bar(250.0))],
+                    &[],
+                ),
+            ),
+        ]);
+        let wght = |v| vec![(Tag::new(b"wght"), v)];
+        let group = |more_locations| {
+            let letterforms = create_letterforms(
+                &['l'],
+                &raw_fonts,
+                &CreateOptions {
+                    location: wght(100.0),
+                    more_locations,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let groups = group_letterforms(RULES, &['l'], &letterforms).unwrap();
+            (
+                groups[&'l'].len(),
+                letterforms
+                    .first_divergence(a, b, 'l', RULES)
+                    .map(str::to_string),
+            )
+        };
+        assert_eq!((1, None), group(vec![wght(900.0)]));
+        assert_eq!(
+            (2, Some("wght=500".to_string())),
+            group(vec![wght(500.0), wght(900.0)])
+        );
+    }
+
+    #[test]
+    fn instances_of_a_font() {
+        let bar = |w| Rect::new(0.0, 0.0, w, 600.0).to_path(0.1);
+        // glyf wants quadratics, a diamond will do
+        let mut o = BezPath::new();
+        o.move_to((300.0, 0.0));
+        o.line_to((600.0, 300.0));
+        o.line_to((300.0, 600.0));
+        o.line_to((0.0, 300.0));
+        o.close_path();
+        let font = build_variable_font(
+            1000,
+            &[('l', bar(50.0), bar(250.0)), ('o', o.clone(), o)],
+            &[("Thin", 100.0), ("Thin Too", 104.0), ("Black", 900.0)],
+        );
+        let path = Path::new("Fam[wght].ttf");
+        let letterforms = create_instance_letterforms(&['l', 'o'], path, &font).unwrap();
+        let groups = group_letterforms(RULES, &['l', 'o'], &letterforms).unwrap();
+
+        let thin = instance_id(path, "Thin");
+        let thin_too = instance_id(path, "Thin Too");
+        let black = instance_id(path, "Black");
+        let mut shared = shared_chars(&groups, &letterforms)
+            .into_iter()
+            .map(|((a, b), chars)| {
+                (
+                    a.to_str().unwrap().to_string(),
+                    b.to_str().unwrap().to_string(),
+                    chars.into_iter().collect::<String>(),
+                )
+            })
+            .collect::<Vec<_>>();
+        shared.sort();
+        let name = |p: &PathBuf| p.to_str().unwrap().to_string();
+        assert_eq!(
+            vec![
+                (name(&black), name(&thin), "o".to_string()),
+                (name(&black), name(&thin_too), "o".to_string()),
+                (name(&thin), name(&thin_too), "lo".to_string()),
+            ],
+            shared
+        );
+    }
+
+    #[test]
+    fn font_in_multiple_groups() {
+        let a = Path::new("a.ttf");
+        let b = Path::new("b.ttf");
+        let mut groups =
+            HashMap::from([('a', vec![LetterformGroup::new(a), LetterformGroup::new(b)])]);
+        assert!(check_membership(&groups).is_ok());
+
+        groups.get_mut(&'a').unwrap()[1].insert(a);
+        assert!(matches!(
+            check_membership(&groups),
+            Err(LetterformsError::MultipleGroups(p, 'a')) if p == a
+        ));
+    }
+}
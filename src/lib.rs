@@ -1,2 +1,14 @@
 pub mod about_the_same;
 pub mod args;
+pub mod cache;
+pub mod charset;
+pub mod compare;
+pub mod letterform;
+pub mod metadata;
+pub mod progress;
+pub mod report;
+pub mod single_subst;
+pub mod woff;
+
+#[cfg(test)]
+mod test_fonts;
@@ -2,113 +2,128 @@ use std::{
     collections::{BTreeSet, HashMap, HashSet},
     fs, io,
     path::{self, Path, PathBuf},
+    process::ExitCode,
+    sync::{atomic::Ordering, Mutex, OnceLock},
+    time::{Duration, Instant},
 };
 
 use clap::Parser;
-use kurbo::{Affine, BezPath, PathEl, Shape};
-use skrifa::{instance::Size, raw::TableProvider, FontRef, MetadataProvider};
-use write_fonts::pens::BezPathPen;
+use kurbo::{BezPath, PathEl, Shape};
+use rayon::prelude::*;
 
 use find_dups::{
-    about_the_same::{AboutTheSame, ApproximatelyEqualError, RulesOfSimilarity},
-    args::Args,
+    about_the_same::{num_comparisons, num_nearest, RulesOfSimilarity},
+    args::{fonts_under, Args, Bucket, MissingExemplar, OutputFormat},
+    charset::group_by_charset,
+    compare::score,
+    letterform::{
+        best_matches, create_baseline_letterforms, create_instance_letterforms, create_letterforms,
+        create_letterforms_from_files, diversity, faux_lowercase, group_letterforms,
+        group_letterforms_logged, merge_rotated_groups, merge_stretched_groups, nearest_neighbors,
+        rank_clusters, remove_baseline_groups, separation_histogram, share_counts, shared_chars,
+        spread, weighted_score, with_lowercase, ComparedPair, Comparison, LetterformGroup,
+        Letterforms, RESOLVED_BY_GEOMETRY, RESOLVED_BY_HASH, STOP_GROUPING,
+    },
+    metadata,
+    report::{Cluster, FamilyWithoutExemplar, NearestNeighbor, Report},
+    woff::decode_font_bytes,
 };
 
-fn svg_circle(x: f64, y: f64, r: f64) -> String {
-    format!("<circle fill=\"darkblue\" opacity=\"0.25\" cx=\"{x}\" cy=\"{y}\" r=\"{r}\" />\n")
-}
+/// Where the report goes, stdout unless --output names a file
+static OUTPUT: OnceLock<Mutex<Box<dyn io::Write + Send>>> = OnceLock::new();
 
-fn init_logging() {
-    use std::io::Write;
-    env_logger::builder()
-        .format(|buf, record| {
-            let ts = buf.timestamp_micros();
-            writeln!(
-                buf,
-                "[{ts} {} {} {}] {}",
-                // we manually assign all threads a name
-                std::thread::current().name().unwrap_or("unknown"),
-                record.target(),
-                buf.default_level_style(record.level())
-                    .value(record.level()),
-                record.args()
-            )
-        })
-        .init();
+/// Like println! but to --output
+macro_rules! outln {
+    () => {
+        outln!("")
+    };
+    ($($arg:tt)*) => {{
+        use std::io::Write;
+        let mut out = OUTPUT
+            .get()
+            .expect("Output must be opened first")
+            .lock()
+            .unwrap();
+        writeln!(out, $($arg)*).expect("Unable to write report");
+    }};
 }
 
-fn load_fonts<'a>(paths: HashSet<PathBuf>) -> Result<HashMap<PathBuf, Vec<u8>>, io::Error> {
-    paths
-        .into_iter()
-        .map(|p| {
-            let contents = fs::read(&p)?;
-            Ok((p, contents))
-        })
-        .collect::<Result<_, _>>()
+/// Open the destination for the report, - for stdout
+fn open_output(path: &Path) -> Result<Box<dyn io::Write + Send>, io::Error> {
+    if path == Path::new("-") {
+        return Ok(Box::new(io::stdout()));
+    }
+    Ok(Box::new(fs::File::create(path)?))
 }
 
-struct LetterformGroup<'a> {
-    letterforms: HashMap<&'a Path, Letterform>,
+fn svg_circle(x: f64, y: f64, r: f64) -> String {
+    format!("<circle fill=\"darkblue\" opacity=\"0.25\" cx=\"{x}\" cy=\"{y}\" r=\"{r}\" />\n")
 }
 
-impl<'a> LetterformGroup<'a> {
-    fn new(path: &'a Path, letterform: Letterform) -> Self {
-        Self {
-            letterforms: HashMap::from([(path, letterform)]),
+/// Log errors, or as RUST_LOG says, or at level if given and RUST_LOG isn't set, plus
+/// progress if requested
+fn logger(level: Option<log::LevelFilter>, progress: bool) -> env_logger::Builder {
+    use std::io::Write;
+    let mut builder = env_logger::builder();
+    if let Some(level) = level {
+        if std::env::var_os("RUST_LOG").is_none() {
+            builder.filter_level(level);
         }
     }
-
-    fn matches(&self, letterform: &Letterform, rules: RulesOfSimilarity) -> bool {
-        self.letterforms
-            .values()
-            .any(|l| l.approximately_equal(letterform, rules).is_ok())
-    }
-
-    fn insert(&mut self, path: &'a Path, letterform: Letterform) -> Option<Letterform> {
-        self.letterforms.insert(path, letterform)
+    if progress {
+        builder.filter_module("find_dups::progress", log::LevelFilter::Info);
     }
+    builder.format(|buf, record| {
+        let ts = buf.timestamp_micros();
+        writeln!(
+            buf,
+            "[{ts} {} {} {}] {}",
+            // we manually assign all threads a name
+            std::thread::current().name().unwrap_or("unknown"),
+            record.target(),
+            buf.default_level_style(record.level())
+                .value(record.level()),
+            record.args()
+        )
+    });
+    builder
 }
 
-#[derive(Debug, Clone)]
-struct Letterform(BezPath);
-
-impl AboutTheSame for Letterform {
-    fn approximately_equal(
-        &self,
-        other: &Self,
-        rules: RulesOfSimilarity,
-    ) -> Result<(), ApproximatelyEqualError> {
-        self.0.approximately_equal(&other.0, rules)
-    }
+/// Size the global thread pool, naming threads for the log
+fn init_threads(threads: Option<usize>) {
+    rayon::ThreadPoolBuilder::new()
+        // 0 lets rayon pick, one per CPU
+        .num_threads(threads.unwrap_or(0))
+        .thread_name(|i| format!("worker-{i}"))
+        .build_global()
+        .expect("Unable to create thread pool");
 }
 
-impl Letterform {
-    fn create(font: &FontRef, c: char, uniform_scale: f64) -> Self {
-        let transform = Affine::scale_non_uniform(uniform_scale, -uniform_scale);
-        let cmap = font.cmap().unwrap();
-        let outlines = font.outline_glyphs();
-
-        let mut path = BezPath::default();
-        if let Some(gid) = cmap.map_codepoint(c) {
-            let glyph = outlines.get(gid).unwrap();
-            let mut pen = BezPathPen::new();
-            glyph.draw(Size::unscaled(), &mut pen).unwrap();
-            path = pen.into_inner();
-            path.apply_affine(transform);
-
-            // plant the control box at 0,0 so translation doesn't cause mismatches
-            let cbox = path.control_box();
-            let (minx, miny) = (cbox.min_x(), cbox.min_y());
-            if (minx, miny) != (0.0, 0.0) {
-                path.apply_affine(Affine::translate((-minx, -miny)));
-            }
+/// On the first Ctrl-C stop comparing and report what we have, on the second give up
+fn handle_interrupts() {
+    ctrlc::set_handler(|| {
+        if STOP_GROUPING.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
         }
-        Self(path)
-    }
+        eprintln!("Interrupted, reporting partial results. Interrupt again to abort.");
+    })
+    .expect("Unable to install Ctrl-C handler");
 }
 
-fn letterforms<'a>(groups: &'a [LetterformGroup]) -> impl Iterator<Item = &'a Letterform> {
-    groups.iter().flat_map(|g| g.letterforms.values())
+/// The sfnt bytes of a font file, decompressing WOFF and WOFF2
+fn read_font(path: &Path) -> Result<Vec<u8>, io::Error> {
+    decode_font_bytes(fs::read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Read, and decompress, every font, spreading the work over the rayon pool
+fn load_fonts(paths: HashSet<PathBuf>) -> Result<HashMap<PathBuf, Vec<u8>>, io::Error> {
+    paths
+        .into_par_iter()
+        .map(|p| {
+            let contents = read_font(&p)?;
+            Ok((p, contents))
+        })
+        .collect::<Result<_, _>>()
 }
 
 fn path_safe_c(c: char) -> String {
@@ -119,34 +134,53 @@ fn path_safe_c(c: char) -> String {
     }
 }
 
-fn dump_glyphs(working_dir: &Path, all_letterforms: &HashMap<char, Vec<LetterformGroup>>) {
-    for (c, group) in all_letterforms.iter() {
-        let viewbox = letterforms(group)
-            .map(|l| l.0.bounding_box())
-            .reduce(|acc, e| acc.union(e))
+/// An svg of paths drawn over one another, each with a start marker, optionally filled
+fn overlay_svg(paths: &[(&BezPath, Option<String>)]) -> String {
+    let viewbox = paths
+        .iter()
+        .map(|(p, _)| p.bounding_box())
+        .reduce(|acc, e| acc.union(e))
+        .unwrap_or_default();
+    let marker_radius = viewbox.width() * 0.02;
+    let margin = 0.1 * viewbox.width().max(viewbox.height());
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+        viewbox.min_x() - margin,
+        viewbox.min_y() - margin,
+        viewbox.width() + 2.0 * margin,
+        viewbox.height() + 2.0 * margin,
+    );
+    for (path, fill) in paths {
+        // actual path
+        let fill = fill
+            .as_ref()
+            .map(|f| format!(" fill=\"{f}\""))
             .unwrap_or_default();
-        let marker_radius = viewbox.width() * 0.02;
-        let margin = 0.1 * viewbox.width().max(viewbox.height());
-
-        let mut svg = format!(
-            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
-            viewbox.min_x() - margin,
-            viewbox.min_y() - margin,
-            viewbox.width() + 2.0 * margin,
-            viewbox.height() + 2.0 * margin,
-        );
-        for path in letterforms(group).map(|l| &l.0) {
-            // actual path
-            svg.push_str(format!("<path opacity=\"0.25\" d=\"{}\" />\n", path.to_svg()).as_str());
-        }
-        for path in letterforms(group).map(|l| &l.0) {
-            // start marker
-            if let Some(PathEl::MoveTo(p)) = path.elements().first() {
-                svg.push_str(svg_circle(p.x, p.y, marker_radius).as_str());
-            }
+        svg.push_str(format!("<path opacity=\"0.25\"{fill} d=\"{}\" />\n", path.to_svg()).as_str());
+    }
+    for (path, _) in paths {
+        // start marker
+        if let Some(PathEl::MoveTo(p)) = path.elements().first() {
+            svg.push_str(svg_circle(p.x, p.y, marker_radius).as_str());
         }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
 
-        svg.push_str("</svg>\n");
+fn dump_glyphs(
+    working_dir: &Path,
+    letterforms: &Letterforms,
+    all_groups: &HashMap<char, Vec<LetterformGroup>>,
+) {
+    for (c, group) in all_groups.iter() {
+        let group_letterforms = || group.iter().flat_map(|g| g.letterforms(*c, letterforms));
+        let svg = overlay_svg(
+            &group_letterforms()
+                .map(|l| (l.path(), None))
+                .collect::<Vec<_>>(),
+        );
         let suffix = if group.len() > 1 { "-inconsistent" } else { "" };
         let c = path_safe_c(*c);
         let dest = working_dir.join(format!("glyph_{c}{suffix}.svg"));
@@ -154,18 +188,121 @@ fn dump_glyphs(working_dir: &Path, all_letterforms: &HashMap<char, Vec<Letterfor
     }
 }
 
-fn dump_groups(working_dir: &Path, all_letterforms: &HashMap<char, Vec<LetterformGroup>>) {
-    for (c, groups) in all_letterforms.iter() {
+/// Escape text for inclusion in html
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A color for the i'th font, spread around the color wheel so neighbors differ
+fn font_color(i: usize) -> String {
+    format!("hsl({:.0}, 70%, 45%)", (i as f64 * 137.508) % 360.0)
+}
+
+/// Write index.html, a single page showing every group of every char
+fn dump_html(
+    working_dir: &Path,
+    letterforms: &Letterforms,
+    all_groups: &HashMap<char, Vec<LetterformGroup>>,
+) {
+    let mut fonts = letterforms.fonts().collect::<Vec<_>>();
+    fonts.sort();
+    let colors = fonts
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (*f, font_color(i)))
+        .collect::<HashMap<_, _>>();
+    let mut chars = all_groups.keys().copied().collect::<Vec<_>>();
+    chars.sort();
+
+    let mut index = String::from("<ul>\n");
+    let mut sections = String::new();
+    for c in chars {
+        let groups = &all_groups[&c];
+        let id = format!("u{:04x}", c as u32);
+        let label = html_escape(&letterforms.label(c));
+        let flag = if groups.len() > 1 {
+            format!(
+                " <strong class=\"inconsistent\">inconsistent, {} groups</strong>",
+                groups.len()
+            )
+        } else {
+            String::new()
+        };
+        index.push_str(&format!("<li><a href=\"#{id}\">'{label}'</a>{flag}</li>\n"));
+
+        sections.push_str(&format!(
+            "<section id=\"{id}\">\n<h2>'{label}'{flag}</h2>\n"
+        ));
         for (i, group) in groups.iter().enumerate() {
-            let mut paths = group
-                .letterforms
-                .keys()
-                .map(|p| p.to_str().unwrap())
+            let members = group.members.iter().copied().collect::<Vec<_>>();
+            let paths = members
+                .iter()
+                .filter_map(|p| {
+                    letterforms
+                        .get(p, c)
+                        .map(|l| (l.path(), Some(colors[p].clone())))
+                })
                 .collect::<Vec<_>>();
+            sections.push_str(&format!(
+                "<div class=\"group\" id=\"{id}.{i}\">\n<h3>Group {i}, {} fonts</h3>\n{}<ul>\n",
+                members.len(),
+                overlay_svg(&paths)
+            ));
+            for member in members {
+                sections.push_str(&format!(
+                    "<li><span class=\"swatch\" style=\"background: {}\"></span>{}</li>\n",
+                    colors[member],
+                    html_escape(&member.to_string_lossy())
+                ));
+            }
+            sections.push_str("</ul>\n</div>\n");
+        }
+        sections.push_str("</section>\n");
+    }
+    index.push_str("</ul>\n");
+
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>find_dups</title>
+<style>
+.group {{ display: inline-block; vertical-align: top; margin: 1em; }}
+.group svg {{ width: 200px; height: 200px; transform: scaleY(-1); border: 1px solid #ccc; }}
+.group ul {{ list-style: none; padding: 0; font-size: small; }}
+.swatch {{ display: inline-block; width: 1em; height: 1em; margin-right: 0.5em; vertical-align: middle; }}
+.inconsistent {{ color: white; background: firebrick; padding: 0 0.5em; }}
+</style>
+</head>
+<body>
+{index}{sections}</body>
+</html>
+"#
+    );
+    let dest = working_dir.join("index.html");
+    fs::write(&dest, html).unwrap_or_else(|e| panic!("Unable to write {dest:?}: {e}"));
+}
+
+fn dump_groups(working_dir: &Path, all_groups: &HashMap<char, Vec<LetterformGroup>>) {
+    for (c, groups) in all_groups.iter() {
+        for (i, group) in groups.iter().enumerate() {
+            let mut paths = group.members.iter().collect::<Vec<_>>();
             paths.sort();
             let mut content = format!("{} files with matching {c}\n", paths.len());
             for path in paths {
-                content.push_str(path);
+                content.push_str(&path.to_string_lossy());
                 content.push('\n');
             }
             let c = path_safe_c(*c);
@@ -175,21 +312,477 @@ fn dump_groups(working_dir: &Path, all_letterforms: &HashMap<char, Vec<Letterfor
     }
 }
 
-fn log_groups(test_chars: &[char], letterforms: &HashMap<char, Vec<LetterformGroup>>) {
+/// Write a minimal svg of the normalized letterform of one member of each group
+fn dump_representatives(
+    working_dir: &Path,
+    letterforms: &Letterforms,
+    all_groups: &HashMap<char, Vec<LetterformGroup>>,
+) {
+    for (c, groups) in all_groups.iter() {
+        for (i, group) in groups.iter().enumerate() {
+            let members = group.members.iter().collect::<Vec<_>>();
+            let Some(letterform) = members
+                .into_iter()
+                .filter_map(|p| letterforms.get(p, *c))
+                .find(|l| !l.is_missing())
+            else {
+                continue;
+            };
+            let bbox = letterform.path().bounding_box();
+            let svg = format!(
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n<path d=\"{}\" />\n</svg>\n",
+                bbox.min_x(),
+                bbox.min_y(),
+                bbox.width(),
+                bbox.height(),
+                letterform.path().to_svg()
+            );
+            let c = path_safe_c(*c);
+            let dest = working_dir.join(format!("representative_{c}.{i}.svg"));
+            fs::write(&dest, svg).unwrap_or_else(|e| panic!("Unable to write {dest:?}: {e}"));
+        }
+    }
+}
+
+/// Write a specimen of the shared characters of the first font in each cluster, in a row
+fn dump_clusters(
+    working_dir: &Path,
+    letterforms: &Letterforms,
+    clusters: &[(BTreeSet<&Path>, BTreeSet<char>)],
+) {
+    for (i, (paths, matched)) in clusters.iter().enumerate() {
+        let Some(representative) = paths.first() else {
+            continue;
+        };
+        let shapes = matched
+            .iter()
+            .filter_map(|c| letterforms.get(representative, *c))
+            .map(|l| (l.path(), l.path().bounding_box()))
+            .collect::<Vec<_>>();
+        let height = shapes.iter().map(|(_, b)| b.height()).fold(0.0, f64::max);
+        let gap = 0.1 * height;
+
+        let mut x = 0.0;
+        let mut content = String::new();
+        for (path, bbox) in shapes {
+            // letterforms are planted at the origin; bottom-align them
+            let y = height - bbox.height();
+            content.push_str(&format!(
+                "<path transform=\"translate({x} {y})\" d=\"{}\" />\n",
+                path.to_svg()
+            ));
+            x += bbox.width() + gap;
+        }
+        let svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n<!-- {} -->\n{content}</svg>\n",
+            -gap,
+            -gap,
+            x + gap,
+            height + 2.0 * gap,
+            representative.display(),
+        );
+        let dest = working_dir.join(format!("cluster_{i}.svg"));
+        fs::write(&dest, svg).unwrap_or_else(|e| panic!("Unable to write {dest:?}: {e}"));
+    }
+}
+
+/// Backslash escape tabs, newlines and backslashes in a tab separated field
+fn tsv_escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Write each pair of fonts with at least limit shared chars as a row of a tsv edge list
+fn dump_edges(
+    dest: &Path,
+    letterforms: &Letterforms,
+    groups: &HashMap<char, Vec<LetterformGroup>>,
+    num_test_chars: usize,
+    limit: usize,
+) {
+    let mut edges = shared_chars(groups, letterforms)
+        .into_iter()
+        .filter(|(_, matched)| matched.len() >= limit)
+        .map(|((a, b), matched)| (a, b, matched.len()))
+        .collect::<Vec<_>>();
+    edges.sort();
+    let mut content = String::from("# font_a\tfont_b\tmatched\tscore\n");
+    for (a, b, matched) in edges {
+        content.push_str(&format!(
+            "{}\t{}\t{matched}\t{:.4}\n",
+            tsv_escape(&a.to_string_lossy()),
+            tsv_escape(&b.to_string_lossy()),
+            matched as f64 / num_test_chars as f64
+        ));
+    }
+    fs::write(dest, content).unwrap_or_else(|e| panic!("Unable to write {dest:?}: {e}"));
+}
+
+/// Print a line per reported group, plus whatever detail was requested
+fn print_clusters(
+    args: &Args,
+    clusters: Vec<(BTreeSet<&Path>, BTreeSet<char>)>,
+    test_chars: &[char],
+    letterforms: &Letterforms,
+    weights: Option<&HashMap<char, f64>>,
+    buckets: &[Bucket],
+    normalized: &[Normalized],
+) {
+    outln!("\nGroup, Score");
+    for (paths, matched) in clusters {
+        let mut line = format!("{paths:?}, {}/{}", matched.len(), test_chars.len());
+        if let Some(weights) = weights {
+            line.push_str(&format!(
+                ", weighted {:.1}%",
+                weighted_score(&matched, test_chars, weights) * 100.0
+            ));
+        }
+        let waived = waived(test_chars, &matched, args.allow_diff);
+        if !waived.is_empty() {
+            let labels = waived
+                .iter()
+                .map(|c| letterforms.label(*c))
+                .collect::<Vec<_>>();
+            line.push_str(&format!(", waived {labels:?}"));
+        }
+        for bucket in buckets.iter() {
+            line.push_str(&format!(
+                ", {} {}/{}",
+                bucket.name,
+                bucket_score(bucket, &matched),
+                bucket.chars.len()
+            ));
+        }
+        if args.flag_cross_designer {
+            let designers = paths
+                .iter()
+                .filter_map(|p| metadata::designer(p))
+                .collect::<BTreeSet<_>>();
+            line.push_str(&format!(", designers {designers:?}"));
+            if designers.len() > 1 {
+                line.push_str(", cross-designer");
+            }
+        }
+        if args.compare_metrics {
+            let metrics = paths
+                .iter()
+                .map(|p| (p, letterforms.metrics(p)))
+                .collect::<Vec<_>>();
+            if metrics.windows(2).all(|w| w[0].1 == w[1].1) {
+                line.push_str(", metrics match");
+            } else {
+                line.push_str(", metrics differ");
+            }
+            outln!("{line}");
+            for (path, metrics) in metrics {
+                match metrics {
+                    Some(metrics) => outln!("  {path:?}: {metrics}"),
+                    None => outln!("  {path:?}: no metrics"),
+                }
+            }
+        } else {
+            outln!("{line}");
+        }
+        if args.explain_group {
+            outln!(
+                "{}",
+                explain_cluster(
+                    &paths,
+                    &matched,
+                    &waived,
+                    test_chars,
+                    letterforms,
+                    normalized,
+                    args.rules()
+                )
+            );
+        }
+    }
+}
+
+/// Print the n pairs of fonts sharing the most letterforms, however few that is
+fn print_top_pairs(
+    n: usize,
+    letterforms: &Letterforms,
+    groups: &HashMap<char, Vec<LetterformGroup>>,
+    num_test_chars: usize,
+) {
+    let mut pairs = shared_chars(groups, letterforms)
+        .into_iter()
+        .map(|((a, b), matched)| (matched.len(), a, b))
+        .collect::<Vec<_>>();
+    pairs.sort_by(|x, y| y.0.cmp(&x.0).then_with(|| (x.1, x.2).cmp(&(y.1, y.2))));
+    outln!("\nTop {n} most similar pairs");
+    outln!("Font, Font, Matched, Score");
+    for (matched, a, b) in pairs.into_iter().take(n) {
+        outln!(
+            "{a:?}, {b:?}, {matched}/{num_test_chars}, {:.1}%",
+            100.0 * matched as f64 / num_test_chars as f64
+        );
+    }
+}
+
+/// Print the nearest neighbor of every font, fonts sharing nothing with any other included
+fn print_nearest_neighbors(letterforms: &Letterforms, report: &Report, num_test_chars: usize) {
+    let mut fonts = letterforms.fonts().collect::<Vec<_>>();
+    fonts.sort();
+    outln!("\nNearest neighbor of each font");
+    outln!("Font, Nearest, Matched, Score, Duplicate");
+    for font in fonts {
+        match report.nearest_neighbor.get(font.to_string_lossy().as_ref()) {
+            Some(n) => outln!(
+                "{font:?}, {:?}, {}/{num_test_chars}, {:.1}%, {}",
+                n.file,
+                n.matched_chars,
+                n.score_pct,
+                n.duplicate
+            ),
+            None => outln!("{font:?}, none, 0/{num_test_chars}, 0.0%, false"),
+        }
+    }
+}
+
+/// Bin width for --separation-histogram, relative to 1000 upem
+const HISTOGRAM_BIN_WIDTH: f64 = 0.5;
+
+/// Write a histogram of the separations within groups as codepoint,from,to,count
+fn dump_separation_histogram(
+    dest: &Path,
+    rules: RulesOfSimilarity,
+    letterforms: &Letterforms,
+    groups: &HashMap<char, Vec<LetterformGroup>>,
+) {
+    let bin_width = HISTOGRAM_BIN_WIDTH * letterforms.upem as f64 / 1000.0;
+    let num_bins = (rules.error / HISTOGRAM_BIN_WIDTH).ceil() as usize + 1;
+    let histogram = separation_histogram(groups, letterforms, rules, bin_width, num_bins);
+    let mut content = String::from("codepoint,from,to,count\n");
+    for (c, bins) in histogram {
+        for (i, count) in bins.into_iter().enumerate() {
+            let from = i as f64 * bin_width;
+            let to = if i == num_bins - 1 {
+                "inf".to_string()
+            } else {
+                format!("{}", from + bin_width)
+            };
+            content.push_str(&format!("U+{:04X},{from},{to},{count}\n", c as u32));
+        }
+    }
+    fs::write(dest, content).unwrap_or_else(|e| panic!("Unable to write {dest:?}: {e}"));
+}
+
+/// Quote a CSV field if it contains a comma, quote or newline
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Write one row per font saying how much of test_chars it covers and whether it was
+/// reported as a duplicate
+fn dump_coverage(
+    dest: &Path,
+    letterforms: &Letterforms,
+    test_chars: &[char],
+    clusters: &[(BTreeSet<&Path>, BTreeSet<char>)],
+    families_without_exemplar: &[MissingExemplar],
+) {
+    let clustered = clusters
+        .iter()
+        .flat_map(|(paths, _)| paths.iter().copied())
+        .collect::<HashSet<_>>();
+    let mut rows = letterforms
+        .fonts()
+        .map(|path| {
+            let present = test_chars
+                .iter()
+                .filter(|c| letterforms.get(path, **c).is_some_and(|l| !l.is_missing()))
+                .count();
+            let missing = test_chars.len() - present;
+            let reason = if missing > 0 { "not in cmap" } else { "" };
+            let upem = letterforms
+                .source_upem(path)
+                .map(|u| u.to_string())
+                .unwrap_or_default();
+            (
+                path.to_path_buf(),
+                upem,
+                present,
+                missing,
+                reason.to_string(),
+                clustered.contains(path),
+            )
+        })
+        .collect::<Vec<_>>();
+    let skipped = letterforms
+        .skipped()
+        .map(|(path, reason)| (path.to_path_buf(), reason.to_string()))
+        .chain(families_without_exemplar.iter().flat_map(|family| {
+            family
+                .files
+                .iter()
+                .map(|f| (f.clone(), "no exemplar for family".to_string()))
+        }));
+    for (path, reason) in skipped {
+        rows.push((path, String::new(), 0, test_chars.len(), reason, false));
+    }
+    rows.sort();
+
+    let mut content = String::from("path,upem,present,skipped,skip_reason,in_cluster\n");
+    for (path, upem, present, skipped, reason, in_cluster) in rows {
+        content.push_str(&format!(
+            "{},{upem},{present},{skipped},{},{in_cluster}\n",
+            csv_escape(&path.to_string_lossy()),
+            csv_escape(&reason)
+        ));
+    }
+    fs::write(dest, content).unwrap_or_else(|e| panic!("Unable to write {dest:?}: {e}"));
+}
+
+/// Write the separations of every pair of letterforms compared while grouping as CSV
+fn dump_scores(
+    dest: &Path,
+    letterforms: &Letterforms,
+    compared: &[ComparedPair],
+    rules: RulesOfSimilarity,
+) {
+    let rules = rules.for_upem(letterforms.upem);
+    let rows = compared
+        .par_iter()
+        .filter_map(|(c, a, b)| {
+            let (letterform_a, letterform_b) = (letterforms.get(a, *c)?, letterforms.get(b, *c)?);
+            let (max_separation, budget_used) = score(letterform_a, letterform_b, rules)?;
+            let (passed, drift) = match letterform_a.compare(letterform_b, rules) {
+                Comparison::Match => (true, None),
+                Comparison::Differ(e) => (false, e.drift()),
+                _ => (false, None),
+            };
+            Some(format!(
+                "{},{},{},{max_separation:.3},{budget_used:.3},{passed},{}\n",
+                csv_escape(&letterforms.label(*c)),
+                csv_escape(&a.to_string_lossy()),
+                csv_escape(&b.to_string_lossy()),
+                csv_escape(&drift.map(|d| d.to_string()).unwrap_or_default()),
+            ))
+        })
+        .collect::<Vec<_>>();
+    let mut content = String::from("char,font_a,font_b,max_separation,budget_used,passed,drift\n");
+    content.extend(rows);
+    fs::write(dest, content).unwrap_or_else(|e| panic!("Unable to write {dest:?}: {e}"));
+}
+
+/// Up to allow_diff of the test chars that didn't match, in order
+fn waived(test_chars: &[char], matched: &BTreeSet<char>, allow_diff: usize) -> BTreeSet<char> {
+    test_chars
+        .iter()
+        .filter(|c| !matched.contains(c))
+        .take(allow_diff)
+        .copied()
+        .collect()
+}
+
+/// A letterform that only matched the rest of its group once transformed, and how
+struct Normalized<'a> {
+    c: char,
+    fonts: &'a BTreeSet<PathBuf>,
+    how: String,
+}
+
+/// Why paths were reported as a group, for --explain-group
+fn explain_cluster(
+    paths: &BTreeSet<&Path>,
+    matched: &BTreeSet<char>,
+    waived: &BTreeSet<char>,
+    test_chars: &[char],
+    letterforms: &Letterforms,
+    normalized: &[Normalized],
+    rules: RulesOfSimilarity,
+) -> String {
+    let labels = |chars: &BTreeSet<char>| {
+        chars
+            .iter()
+            .map(|c| format!("'{}'", letterforms.label(*c)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let normalized = normalized
+        .iter()
+        .filter(|n| matched.contains(&n.c) && n.fonts.iter().any(|f| paths.contains(f.as_path())))
+        .collect::<Vec<_>>();
+
+    let mut explanation = format!(
+        "  Matched {}/{} glyphs ({:.1}%): {}.",
+        matched.len(),
+        test_chars.len(),
+        100.0 * matched.len() as f64 / test_chars.len() as f64,
+        labels(matched)
+    );
+    let mut spreads = matched
+        .iter()
+        .filter(|c| !normalized.iter().any(|n| n.c == **c))
+        .map(|c| (spread(paths.iter().copied(), *c, letterforms, rules), *c))
+        .collect::<Vec<_>>();
+    spreads.sort_by(|a, b| a.0.total_cmp(&b.0));
+    if let (Some(tightest), Some(loosest)) = (spreads.first(), spreads.last()) {
+        explanation.push_str(&format!(
+            " Tightest '{}', at most {:.2} units apart, loosest '{}', at most {:.2} units apart, at {} upem.",
+            letterforms.label(tightest.1),
+            tightest.0,
+            letterforms.label(loosest.1),
+            loosest.0,
+            letterforms.upem
+        ));
+    }
+    let source_upem = paths
+        .iter()
+        .filter_map(|p| letterforms.source_upem(p))
+        .collect::<BTreeSet<_>>();
+    if source_upem.len() > 1 {
+        explanation.push_str(&format!(
+            " Outlines were scaled from upems {source_upem:?} to compare."
+        ));
+    }
+    for n in normalized {
+        explanation.push_str(&format!(
+            " '{}' of {:?} only matched once {}.",
+            letterforms.label(n.c),
+            n.fonts,
+            n.how
+        ));
+    }
+    if !waived.is_empty() {
+        explanation.push_str(&format!(" Mismatches waived: {}.", labels(waived)));
+    }
+    explanation
+}
+
+fn bucket_score(bucket: &Bucket, matched: &BTreeSet<char>) -> usize {
+    bucket.chars.iter().filter(|c| matched.contains(c)).count()
+}
+
+fn log_groups(test_chars: &[char], groups: &HashMap<char, Vec<LetterformGroup>>) {
     if !log::log_enabled!(log::Level::Debug) {
         return;
     }
     for c in test_chars.iter() {
-        let groups = letterforms
-            .get(c)
-            .expect("All test chars should be defined");
+        let groups = groups.get(c).expect("All test chars should be defined");
         log::debug!("{} groups for '{c}'", groups.len());
         for (i, group) in groups.iter().enumerate() {
             log::debug!(
                 "  {i}: {:?}",
                 group
-                    .letterforms
-                    .keys()
+                    .members
+                    .iter()
                     .map(|p| p.to_string_lossy())
                     .collect::<Vec<_>>()
             );
@@ -197,10 +790,111 @@ fn log_groups(test_chars: &[char], letterforms: &HashMap<char, Vec<LetterformGro
     }
 }
 
-fn dump_stuff(args: &Args, letterforms: &HashMap<char, Vec<LetterformGroup>>) {
+fn print_designs(
+    test_chars: &[char],
+    letterforms: &Letterforms,
+    groups: &HashMap<char, Vec<LetterformGroup>>,
+) {
+    outln!("Distinct designs per character\n");
+    for c in test_chars.iter() {
+        let Some(groups) = groups.get(c) else {
+            continue;
+        };
+        let mut designs = groups
+            .iter()
+            .map(|g| {
+                let mut paths = g.members.iter().copied().collect::<Vec<_>>();
+                paths.sort();
+                paths
+            })
+            .collect::<Vec<_>>();
+        designs.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        outln!("'{}': {} designs", letterforms.label(*c), designs.len());
+        for paths in designs {
+            outln!("  {} fonts: {paths:?}", paths.len());
+        }
+    }
+    outln!();
+}
+
+/// For each char, print the groups that match at the first --instance location but were
+/// kept apart because they diverge at a later one, and where
+fn print_divergences(
+    test_chars: &[char],
+    letterforms: &Letterforms,
+    groups: &HashMap<char, Vec<LetterformGroup>>,
+    rules: RulesOfSimilarity,
+) {
+    let rules = rules.for_upem(letterforms.upem);
+    let mut divergences = Vec::new();
+    for c in test_chars.iter() {
+        let Some(groups) = groups.get(c) else {
+            continue;
+        };
+        let representatives = groups
+            .iter()
+            .filter_map(|g| g.members.iter().copied().min())
+            .collect::<Vec<_>>();
+        for (i, a) in representatives.iter().enumerate() {
+            for b in representatives[i + 1..].iter() {
+                let (Some(letterform_a), Some(letterform_b)) =
+                    (letterforms.get(a, *c), letterforms.get(b, *c))
+                else {
+                    continue;
+                };
+                if !matches!(letterform_a.compare(letterform_b, rules), Comparison::Match) {
+                    continue;
+                }
+                if let Some(label) = letterforms.first_divergence(a, b, *c, rules) {
+                    divergences.push((letterforms.label(*c), *a.min(b), *a.max(b), label));
+                }
+            }
+        }
+    }
+    if divergences.is_empty() {
+        return;
+    }
+    outln!("Matching at the first --instance location but diverging later\n");
+    for (c, a, b, label) in divergences {
+        outln!("'{c}' {a:?} and {b:?} first differ at {label}");
+    }
+    outln!();
+}
+
+/// The family of a font or instance, taken to be the directory it's in
+fn family(path: &Path) -> &Path {
+    path.parent().unwrap_or(path)
+}
+
+/// Roll clusters of fonts and instances up to the families they belong to, reporting
+/// the best score for each set of families
+fn print_families(clusters: &[(BTreeSet<&Path>, BTreeSet<char>)], num_test_chars: usize) {
+    let mut by_family: HashMap<BTreeSet<&Path>, usize> = Default::default();
+    for (paths, matched) in clusters {
+        let families = paths.iter().map(|p| family(p)).collect::<BTreeSet<_>>();
+        // Instances of one family resembling each other is expected
+        if families.len() < 2 {
+            continue;
+        }
+        let best = by_family.entry(families).or_default();
+        *best = (*best).max(matched.len());
+    }
+    let mut by_family = by_family.into_iter().collect::<Vec<_>>();
+    by_family.sort();
+    outln!("\nFamilies, Best score");
+    for (families, best) in by_family {
+        outln!("{families:?}, {best}/{num_test_chars}");
+    }
+}
+
+fn dump_stuff(
+    args: &Args,
+    letterforms: &Letterforms,
+    groups: &HashMap<char, Vec<LetterformGroup>>,
+) {
     let working_dir = Path::new(&args.working_dir);
     if working_dir.is_dir() {
-        for del_pat in ["*.svg", "*.txt"] {
+        for del_pat in ["*.svg", "*.txt", "*.html"] {
             for file in
                 glob::glob(working_dir.join(del_pat).to_str().expect("Oh no")).expect("To glob")
             {
@@ -212,118 +906,671 @@ fn dump_stuff(args: &Args, letterforms: &HashMap<char, Vec<LetterformGroup>>) {
         fs::create_dir(working_dir).unwrap();
     }
     if args.dump_glyphs {
-        dump_glyphs(working_dir, letterforms);
+        dump_glyphs(working_dir, letterforms, groups);
+    }
+    if args.dump_html {
+        dump_html(working_dir, letterforms, groups);
     }
     if args.dump_groups {
-        dump_groups(working_dir, letterforms);
+        dump_groups(working_dir, groups);
+    }
+    if args.dump_representatives {
+        dump_representatives(working_dir, letterforms, groups);
     }
 }
 
-fn create_grouped_letterforms<'a>(
-    rules: RulesOfSimilarity,
-    test_chars: &[char],
-    raw_fonts: &'a HashMap<PathBuf, Vec<u8>>,
-) -> Result<HashMap<char, Vec<LetterformGroup<'a>>>, ()> {
-    let fonts: HashMap<_, _> = raw_fonts
-        .iter()
-        .map(|(path, bytes)| {
-            (
-                path,
-                FontRef::new(bytes).unwrap_or_else(|e| panic!("Unable to load {path:?}: {e}")),
-            )
-        })
-        .collect();
+/// Report how similar each pair of named instances of a single font is
+fn compare_instances(args: &Args, font_file: &Path, test_chars: &[char]) {
+    let raw_font =
+        read_font(font_file).unwrap_or_else(|e| panic!("Unable to load {font_file:?}: {e}"));
+    let letterforms = create_instance_letterforms(test_chars, font_file, &raw_font)
+        .unwrap_or_else(|e| panic!("Unable to create letterforms: {e}"));
+    let groups = group_letterforms(args.rules(), test_chars, &letterforms)
+        .unwrap_or_else(|e| panic!("Unable to group letterforms: {e}"));
+    log_groups(test_chars, &groups);
+    dump_stuff(args, &letterforms, &groups);
 
-    if fonts.is_empty() {
-        log::error!("Not much to do with no fonts specified");
-        return Err(());
-    }
-
-    // we will scale to the largest upem
-    let max_upem = fonts
-        .values()
-        .map(|f| f.head().unwrap().units_per_em())
-        .max()
-        .unwrap();
-
-    // budget is based on 1000 upem; scale if necessary
-    let rules = rules.for_upem(max_upem);
-    log::info!("The rules are {rules:?}");
-    let mut glyphs: HashMap<char, Vec<BezPath>> = Default::default();
-
-    // Really we should shape the test string but we don't have a safe shaper.
-    // This should suffice for copied Latin which is our primarily use case.
-    let mut letterforms: HashMap<char, Vec<LetterformGroup>> = Default::default();
-    for (path, font) in fonts.iter() {
-        let upem = font.head().unwrap().units_per_em();
-        let uniform_scale = if upem != max_upem {
-            max_upem as f64 / upem as f64
-        } else {
-            1.0
-        };
-        log::debug!("Creating letterforms for {path:?}");
-        for c in test_chars.iter() {
-            let letterform = Letterform::create(font, *c, uniform_scale);
-
-            glyphs.entry(*c).or_default().push(letterform.0.clone());
-
-            let groups = letterforms.entry(*c).or_default();
-            let mut grouped = false;
-            for group in groups.iter_mut() {
-                if group.matches(&letterform, rules) {
-                    if group.insert(path, letterform.clone()).is_some() {
-                        panic!("Multiple definitions for {path:?} '{c}");
-                    }
-                    grouped = true;
-                }
-            }
-            if !grouped {
-                groups.push(LetterformGroup::new(path, letterform));
-            }
+    let shared = shared_chars(&groups, &letterforms);
+    let mut instances = letterforms.fonts().collect::<Vec<_>>();
+    instances.sort();
+    let limit = (test_chars.len() as f64 * args.match_pct / 100.0).ceil() as usize;
+    outln!(
+        "Instances of {font_file:?}, pairs with at least {limit}/{} matching glyphs are marked same",
+        test_chars.len()
+    );
+    outln!("\nInstance, Instance, Score, Pct");
+    for (i, a) in instances.iter().enumerate() {
+        for b in instances[i + 1..].iter() {
+            let matched = shared.get(&(*a, *b)).map(|m| m.len()).unwrap_or_default();
+            let pct = 100.0 * matched as f64 / test_chars.len() as f64;
+            let same = if matched >= limit { ", same" } else { "" };
+            outln!(
+                "{a:?}, {b:?}, {matched}/{}, {pct:.1}%{same}",
+                test_chars.len()
+            );
         }
     }
-    Ok(letterforms)
 }
 
-fn main() {
-    let args = Args::parse();
-    init_logging();
+/// Experimental: report fonts whose lowercase is scaled down uppercase
+fn report_faux_lowercase(args: &Args, test_chars: &[char]) {
+    let test_chars = with_lowercase(test_chars);
+    let raw_fonts =
+        load_fonts(args.font_files().files).unwrap_or_else(|e| panic!("Unable to load fonts {e}"));
+    let letterforms = create_letterforms(&test_chars, &raw_fonts, &args.create_options())
+        .unwrap_or_else(|e| panic!("Unable to create letterforms: {e}"));
+    let mut fonts = letterforms.fonts().collect::<Vec<_>>();
+    fonts.sort();
+    outln!("EXPERIMENTAL: lowercase that matches uppercase scaled to the same height\n");
+    outln!("Font, Pairs");
+    for font in fonts {
+        let pairs = faux_lowercase(&letterforms, font, &test_chars, args.rules());
+        if pairs.is_empty() {
+            continue;
+        }
+        let pairs = pairs
+            .iter()
+            .map(|(upper, lower)| format!("{upper}{lower}"))
+            .collect::<Vec<_>>();
+        outln!("{font:?}, {}", pairs.join(" "));
+    }
+}
+
+/// Report the font in dir most like each input font
+fn report_against(args: &Args, dir: &Path, test_chars: &[char]) {
+    let library_files = fonts_under(dir);
+    let mut files = args.font_files().files;
+    files.extend(library_files);
+    let files = files.into_iter().collect::<Vec<_>>();
+    let mut letterforms =
+        create_letterforms_from_files(test_chars, &files, read_font, &args.create_options())
+            .unwrap_or_else(|e| panic!("Unable to create letterforms: {e}"));
+    if args.remove_overlaps {
+        letterforms.remove_overlaps();
+    }
+    if let Some(min_diagonal) = args.ignore_tiny_contours {
+        letterforms.drop_tiny_contours(min_diagonal);
+    }
+    if args.normalize_scale {
+        letterforms.normalize_scale();
+    }
 
-    let test_chars = args.test_chars();
+    // fonts, and instances of fonts, from dir are keyed by paths within it
+    let (mut library, mut candidates): (Vec<&Path>, Vec<&Path>) =
+        letterforms.fonts().partition(|p| p.starts_with(dir));
+    library.sort();
+    candidates.sort();
+    let best = best_matches(
+        args.rules(),
+        test_chars,
+        &letterforms,
+        &library,
+        &candidates,
+    );
+    outln!(
+        "Best match for each of {} fonts among {} fonts in {dir:?}\n",
+        candidates.len(),
+        library.len()
+    );
+    outln!("Font, Best match, Score");
+    for best in best {
+        match best.library_font {
+            Some(library_font) => outln!(
+                "{:?}, {library_font:?}, {}/{}",
+                best.candidate,
+                best.chars.len(),
+                test_chars.len()
+            ),
+            None => outln!("{:?}, -, 0/{}", best.candidate, test_chars.len()),
+        }
+    }
+}
+
+/// Report sets of fonts that cover the same test chars, without comparing outlines
+fn report_charsets(args: &Args, test_chars: &[char]) {
     let raw_fonts =
-        load_fonts(args.font_files()).unwrap_or_else(|e| panic!("Unable to load fonts {e}"));
+        load_fonts(args.font_files().files).unwrap_or_else(|e| panic!("Unable to load fonts {e}"));
+    let groups = group_by_charset(test_chars, &raw_fonts)
+        .unwrap_or_else(|e| panic!("Unable to read charsets: {e}"));
+    outln!("Fonts covering exactly the same test characters\n");
+    outln!("Fonts, Covered");
+    for group in groups {
+        outln!(
+            "{:?}, {}/{}",
+            group.fonts,
+            group.chars.len(),
+            test_chars.len()
+        );
+    }
+}
 
-    let letterforms = create_grouped_letterforms(args.rules(), &test_chars, &raw_fonts).unwrap();
+/// Time spent in each phase of a run
+#[derive(Default)]
+struct Timings {
+    load: Duration,
+    compare: Duration,
+    dump: Duration,
+}
 
-    log_groups(&test_chars, &letterforms);
-    dump_stuff(&args, &letterforms);
+impl Timings {
+    fn summary(&self, num_fonts: usize) -> String {
+        let total = self.load + self.compare + self.dump;
+        format!(
+            "\nTiming: load {:.2}s, compare {:.2}s, dump {:.2}s, total {:.2}s, {:.1} fonts/s",
+            self.load.as_secs_f64(),
+            self.compare.as_secs_f64(),
+            self.dump.as_secs_f64(),
+            total.as_secs_f64(),
+            num_fonts as f64 / total.as_secs_f64().max(f64::EPSILON)
+        )
+    }
+}
 
-    // Did we find sets of fonts that share glyphs?
-    let mut share_counts: HashMap<BTreeSet<&Path>, usize> = Default::default();
-    for groups in letterforms.values() {
-        for group in groups {
-            // It's really much more interesting when the group has multiple things in it
-            if group.letterforms.len() < 2 {
-                continue;
+/// Letterforms of the --baseline font, normalized the same way as those being compared
+fn baseline_letterforms(args: &Args, file: &Path, test_chars: &[char], upem: u16) -> Letterforms {
+    let bytes = read_font(file).unwrap_or_else(|e| panic!("Unable to read {file:?}: {e}"));
+    let mut baseline = create_baseline_letterforms(test_chars, file, &bytes, upem)
+        .unwrap_or_else(|e| panic!("Unable to create baseline letterforms: {e}"));
+    if args.remove_overlaps {
+        baseline.remove_overlaps();
+    }
+    if let Some(min_diagonal) = args.ignore_tiny_contours {
+        baseline.drop_tiny_contours(min_diagonal);
+    }
+    if args.normalize_scale {
+        baseline.normalize_scale();
+    }
+    baseline
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    logger(args.log_level(), args.progress).init();
+    init_threads(args.threads);
+    handle_interrupts();
+    let output = open_output(&args.output)
+        .unwrap_or_else(|e| panic!("Unable to open {:?}: {e}", args.output));
+    assert!(
+        OUTPUT.set(Mutex::new(output)).is_ok(),
+        "Output is only opened once"
+    );
+
+    if args.json_schema {
+        outln!(
+            "{}",
+            serde_json::to_string_pretty(&Report::json_schema()).expect("Schemas are serializable")
+        );
+        return ExitCode::SUCCESS;
+    }
+
+    if args.raw {
+        log::warn!(
+            "--raw compares outlines in font units without normalization, thresholds now depend on upem"
+        );
+    }
+    let features = args.features();
+    if !features.is_empty() && !args.single_subst_forms {
+        log::warn!(
+            "--features {features:?} only apply with --single-subst-forms, comparing default glyphs"
+        );
+    }
+    let mut test_chars = args.test_chars();
+    if args.charset_only {
+        report_charsets(&args, &test_chars);
+        return ExitCode::SUCCESS;
+    }
+    if args.experimental_faux_lowercase {
+        report_faux_lowercase(&args, &test_chars);
+        return ExitCode::SUCCESS;
+    }
+    if let Some(dir) = &args.against {
+        report_against(&args, dir, &test_chars);
+        return ExitCode::SUCCESS;
+    }
+    if let Some(font_file) = &args.self_instances {
+        compare_instances(&args, font_file, &test_chars);
+        return ExitCode::SUCCESS;
+    }
+    let imported = args.import_outlines.as_ref().and_then(|file| {
+        Letterforms::import(Path::new(file))
+            .unwrap_or_else(|e| panic!("Unable to import outlines: {e}"))
+    });
+    let mut timings = Timings::default();
+    let start = Instant::now();
+    let mut families_without_exemplar = Vec::new();
+    let mut letterforms = match imported {
+        Some(letterforms) => letterforms,
+        None => {
+            let font_files = args.font_files();
+            if args.strict_exemplar && !font_files.families_without_exemplar.is_empty() {
+                // not logged so --quiet still says why we failed
+                eprintln!("Unable to identify an exemplar for:");
+                for family in font_files.families_without_exemplar.iter() {
+                    eprintln!("  {:?}: {:?}", family.dir, family.files);
+                }
+                return ExitCode::FAILURE;
             }
-            let key = group
-                .letterforms
-                .keys()
-                .copied()
-                .collect::<BTreeSet<&Path>>();
-            let v = share_counts.entry(key).or_default();
-            *v += 1;
+            families_without_exemplar = font_files.families_without_exemplar;
+            // fonts are read as they're drawn, not held in memory
+            let files = font_files.files.into_iter().collect::<Vec<_>>();
+            create_letterforms_from_files(&test_chars, &files, read_font, &args.create_options())
+                .unwrap_or_else(|e| panic!("Unable to create letterforms: {e}"))
+        }
+    };
+    if let Some(file) = &args.export_outlines {
+        letterforms
+            .export(Path::new(file))
+            .unwrap_or_else(|e| panic!("Unable to export outlines: {e}"));
+    }
+    if args.remove_overlaps {
+        letterforms.remove_overlaps();
+    }
+    if let Some(min_diagonal) = args.ignore_tiny_contours {
+        letterforms.drop_tiny_contours(min_diagonal);
+    }
+    if args.normalize_scale {
+        letterforms.normalize_scale();
+    }
+    timings.load = start.elapsed();
+
+    if args.intersect_charset {
+        test_chars = letterforms.common_chars(&test_chars);
+        if test_chars.is_empty() {
+            log::warn!("No test characters are present in every font, nothing to compare");
+            return ExitCode::SUCCESS;
         }
+        log::info!(
+            "Comparing {} characters common to all fonts",
+            test_chars.len()
+        );
+    }
+
+    let start = Instant::now();
+    let mut compared = Vec::new();
+    let mut groups = group_letterforms_logged(
+        args.rules(),
+        &test_chars,
+        &letterforms,
+        args.dump_scores.is_some().then_some(&mut compared),
+    )
+    .unwrap_or_else(|e| panic!("Unable to group letterforms: {e}"));
+    let removed_by_baseline = args.baseline.as_ref().map(|file| {
+        let baseline = baseline_letterforms(&args, file, &test_chars, letterforms.upem);
+        remove_baseline_groups(&mut groups, &letterforms, &baseline, args.rules())
+    });
+    let rotated = if args.detect_rotation {
+        merge_rotated_groups(&mut groups, &letterforms, args.rules())
+    } else {
+        Vec::new()
+    };
+    let stretched = if args.normalize_aspect {
+        merge_stretched_groups(&mut groups, &letterforms, args.rules())
+    } else {
+        Vec::new()
+    };
+    timings.compare = start.elapsed();
+
+    log_groups(&test_chars, &groups);
+    let start = Instant::now();
+    dump_stuff(&args, &letterforms, &groups);
+    if let Some(dest) = &args.dump_scores {
+        dump_scores(dest, &letterforms, &compared, args.rules());
     }
+    timings.dump += start.elapsed();
 
+    if args.designs {
+        print_designs(&test_chars, &letterforms, &groups);
+    }
+    if letterforms.has_more_locations() && args.format == OutputFormat::Text {
+        print_divergences(&test_chars, &letterforms, &groups, args.rules());
+    }
+
+    // Did we find sets of fonts that share glyphs?
+    let share_counts = share_counts(&groups, &letterforms, args.min_group_size);
+
+    if STOP_GROUPING.load(Ordering::Relaxed) {
+        outln!("Interrupted, results are partial\n");
+    }
+    let buckets = args.buckets(&test_chars);
+    let weights = (args.weight_by_diversity || args.char_weights.is_some()).then(|| {
+        let diversity = args
+            .weight_by_diversity
+            .then(|| diversity(&groups, &letterforms));
+        test_chars
+            .iter()
+            .map(|c| {
+                let designs = diversity
+                    .as_ref()
+                    .map_or(1.0, |d| d.get(c).copied().unwrap_or_default() as f64);
+                let weight = args.char_weights.as_ref().map_or(1.0, |w| w.get(*c));
+                (*c, designs * weight)
+            })
+            .collect::<HashMap<_, _>>()
+    });
     let limit = (test_chars.len() as f64 * args.match_pct / 100.0).ceil() as usize;
-    println!(
-        "Showing groups where at least {limit}/{} glyphs match\n\nGroup, Score",
-        test_chars.len()
+    // just the JSON on stdout for --format json
+    if args.format == OutputFormat::Text {
+        outln!(
+            "Showing groups where at least {limit}/{} glyphs match",
+            test_chars.len()
+        );
+        if weights.is_some() {
+            let by = match (args.weight_by_diversity, args.char_weights.is_some()) {
+                (true, true) => "the number of designs of each glyph and --char-weights",
+                (true, false) => "the number of designs of each glyph",
+                _ => "--char-weights",
+            };
+            outln!(
+                "weighted by {by}, at least {}% of the weight must match",
+                args.match_pct
+            );
+        }
+        if args.min_group_size > 2 {
+            outln!("in groups of at least {} fonts", args.min_group_size);
+        }
+        if args.require_all_buckets && !buckets.is_empty() {
+            outln!("and every bucket has at least {}% matching", args.match_pct);
+        }
+        if let (Some(file), Some(removed)) = (&args.baseline, &removed_by_baseline) {
+            outln!("Not counting groups that match the baseline {file:?}:");
+            for (c, num_fonts) in removed {
+                outln!("  '{}' ({num_fonts} fonts)", letterforms.label(*c));
+            }
+        }
+        if !rotated.is_empty() {
+            outln!("Letterforms that match once rotated counterclockwise:");
+            for r in rotated.iter() {
+                outln!(
+                    "  '{}' {:?} rotated {} degrees matches {:?}",
+                    letterforms.label(r.c),
+                    r.merged,
+                    r.transform,
+                    r.into
+                );
+            }
+        }
+        if !stretched.is_empty() {
+            outln!(
+                "Letterforms that match once scaled horizontally, condensed or extended copies:"
+            );
+            for s in stretched.iter() {
+                outln!(
+                    "  '{}' {:?} scaled {:.3}x matches {:?}",
+                    letterforms.label(s.c),
+                    s.merged,
+                    s.transform,
+                    s.into
+                );
+            }
+        }
+        if !families_without_exemplar.is_empty() {
+            outln!(
+                "{} families were skipped for lack of an exemplar:",
+                families_without_exemplar.len()
+            );
+            for family in families_without_exemplar.iter() {
+                outln!("  {:?}: {:?}", family.dir, family.files);
+            }
+        }
+        if args.allow_diff > 0 {
+            outln!("ignoring up to {} mismatched glyphs", args.allow_diff);
+        }
+    }
+    let enough = |matched: &BTreeSet<char>| {
+        let waived = waived(&test_chars, matched, args.allow_diff);
+        let scored = test_chars
+            .iter()
+            .filter(|c| !waived.contains(c))
+            .copied()
+            .collect::<Vec<_>>();
+        match &weights {
+            Some(weights) => weighted_score(matched, &scored, weights) * 100.0 >= args.match_pct,
+            None => matched.len() as f64 >= (scored.len() as f64 * args.match_pct / 100.0).ceil(),
+        }
+    };
+    let mut clusters = share_counts
+        .into_iter()
+        .filter(|(_, matched)| {
+            enough(matched)
+                && (!args.require_all_buckets
+                    || buckets
+                        .iter()
+                        .all(|b| bucket_score(b, matched) >= b.limit(args.match_pct)))
+        })
+        .collect::<Vec<_>>();
+    rank_clusters(&mut clusters, |matched| match &weights {
+        Some(weights) => weighted_score(matched, &test_chars, weights),
+        None => matched.len() as f64,
+    });
+    let start = Instant::now();
+    if let Some(dest) = &args.separation_histogram {
+        dump_separation_histogram(dest, args.rules(), &letterforms, &groups);
+    }
+    if let Some(dest) = &args.dump_edges {
+        dump_edges(dest, &letterforms, &groups, test_chars.len(), limit);
+    }
+    if let Some(dest) = &args.coverage_csv {
+        dump_coverage(
+            dest,
+            &letterforms,
+            &test_chars,
+            &clusters,
+            &families_without_exemplar,
+        );
+    }
+    if args.dump_clusters {
+        dump_clusters(Path::new(&args.working_dir), &letterforms, &clusters);
+    }
+    timings.dump += start.elapsed();
+
+    if args.all_instances {
+        print_families(&clusters, test_chars.len());
+    }
+
+    let nearest = args
+        .nearest_neighbors
+        .then(|| nearest_neighbors(&groups, &letterforms));
+    let mut report = Report::new(
+        clusters
+            .iter()
+            .map(|(paths, matched)| {
+                let mut cluster = Cluster::new(
+                    paths.iter().map(|p| p.to_string_lossy().to_string()),
+                    matched.len(),
+                    test_chars.len(),
+                );
+                cluster.waived_chars = waived(&test_chars, matched, args.allow_diff)
+                    .into_iter()
+                    .map(|c| letterforms.label(c))
+                    .collect();
+                cluster
+            })
+            .collect(),
     );
-    for (paths, score) in share_counts {
-        if score >= limit {
-            println!("{paths:?}, {score}/{}", test_chars.len());
+    if let Some(nearest) = &nearest {
+        report.nearest_neighbor = nearest
+            .iter()
+            .map(|(font, n)| {
+                (
+                    font.to_string_lossy().to_string(),
+                    NearestNeighbor::new(
+                        n.font.to_string_lossy().to_string(),
+                        n.chars.len(),
+                        test_chars.len(),
+                        enough(&n.chars),
+                    ),
+                )
+            })
+            .collect();
+    }
+    report.families_without_exemplar = families_without_exemplar
+        .iter()
+        .map(|family| FamilyWithoutExemplar {
+            dir: family.dir.to_string_lossy().to_string(),
+            files: family
+                .files
+                .iter()
+                .map(|f| f.to_string_lossy().to_string())
+                .collect(),
+        })
+        .collect();
+    if let Some(file) = &args.json_report {
+        report
+            .write(file)
+            .unwrap_or_else(|e| panic!("Unable to write report: {e}"));
+    }
+
+    let normalized = rotated
+        .iter()
+        .map(|r| Normalized {
+            c: r.c,
+            fonts: &r.merged,
+            how: format!("rotated {} degrees", r.transform),
+        })
+        .chain(stretched.iter().map(|s| Normalized {
+            c: s.c,
+            fonts: &s.merged,
+            how: format!("scaled {:.3}x horizontally", s.transform),
+        }))
+        .collect::<Vec<_>>();
+
+    if args.format == OutputFormat::Json {
+        outln!("{}", report.clusters_json());
+        // keep stdout parseable
+        for family in report.families_without_exemplar.iter() {
+            eprintln!(
+                "No exemplar for {}, skipped: {:?}",
+                family.dir, family.files
+            );
         }
+    } else {
+        print_clusters(
+            &args,
+            clusters,
+            &test_chars,
+            &letterforms,
+            weights.as_ref(),
+            &buckets,
+            &normalized,
+        );
+    }
+
+    if let Some(n) = args.top {
+        print_top_pairs(n, &letterforms, &groups, test_chars.len());
+    }
+
+    if args.nearest_neighbors {
+        print_nearest_neighbors(&letterforms, &report, test_chars.len());
+    }
+
+    if let Some(file) = &args.diff {
+        let previous =
+            Report::load(file).unwrap_or_else(|e| panic!("Unable to load previous report: {e}"));
+        outln!("\nDiff against {file:?}");
+        outln!(
+            "{}",
+            serde_json::to_string_pretty(&report.diff(&previous)).expect("Diffs are serializable")
+        );
+    }
+
+    let mut stats = format!(
+        "{}\nComparisons: {} resolved by fingerprint, {} by geometry, {} paths compared",
+        timings.summary(letterforms.fonts().count()),
+        RESOLVED_BY_HASH.load(Ordering::Relaxed),
+        RESOLVED_BY_GEOMETRY.load(Ordering::Relaxed),
+        num_comparisons(),
+    );
+    if let Some(num_nearest) = num_nearest() {
+        stats.push_str(&format!(", {num_nearest} nearest segment searches"));
+    }
+    // keep stdout parseable
+    if args.format == OutputFormat::Json {
+        eprintln!("{stats}");
+    } else {
+        outln!("{stats}");
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, io::Write};
+
+    use clap::Parser;
+    use find_dups::args::Args;
+    use log::{Level, Log, Metadata};
+
+    use crate::{csv_escape, html_escape, logger, open_output, tsv_escape, waived};
+
+    #[test]
+    fn verbosity() {
+        let enabled = |args: &[&str], target: &str, level: Level| {
+            let args = Args::parse_from(["find_dups"].iter().chain(args.iter()));
+            logger(args.log_level(), args.progress)
+                .build()
+                .enabled(&Metadata::builder().level(level).target(target).build())
+        };
+        // log_groups only logs at debug
+        assert!(!enabled(&["-v"], "find_dups", Level::Debug));
+        assert!(enabled(&["-vv"], "find_dups", Level::Debug));
+        assert!(!enabled(&["--quiet"], "find_dups", Level::Error));
+        assert!(enabled(
+            &["--quiet", "--progress"],
+            "find_dups::progress",
+            Level::Info
+        ));
+    }
+
+    #[test]
+    fn writes_output_to_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("report.txt");
+        writeln!(open_output(&file).unwrap(), "Group, Score").unwrap();
+        assert_eq!("Group, Score\n", std::fs::read_to_string(&file).unwrap());
+    }
+
+    #[test]
+    fn escapes_tsv_fields() {
+        assert_eq!(
+            vec!["plain.ttf", "a\\tb.ttf", "a\\nb\\\\c.ttf"],
+            vec![
+                tsv_escape("plain.ttf"),
+                tsv_escape("a\tb.ttf"),
+                tsv_escape("a\nb\\c.ttf")
+            ]
+        );
+    }
+
+    #[test]
+    fn escapes_csv_fields() {
+        assert_eq!(
+            vec!["plain.ttf", "\"a,b.ttf\"", "\"a\"\"b\"\".ttf\""],
+            vec![
+                csv_escape("plain.ttf"),
+                csv_escape("a,b.ttf"),
+                csv_escape("a\"b\".ttf")
+            ]
+        );
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!("plain.ttf", html_escape("plain.ttf"));
+        assert_eq!(
+            "&lt;b&gt; &amp; &quot;q&quot; &#39;s&#39;",
+            html_escape("<b> & \"q\" 's'")
+        );
+    }
+
+    #[test]
+    fn waives_first_mismatches() {
+        let test_chars = ['a', 'b', 'c', 'd'];
+        let matched = BTreeSet::from(['b']);
+        assert_eq!(BTreeSet::new(), waived(&test_chars, &matched, 0));
+        assert_eq!(BTreeSet::from(['a', 'c']), waived(&test_chars, &matched, 2));
+        assert_eq!(
+            BTreeSet::from(['a', 'c', 'd']),
+            waived(&test_chars, &matched, 5)
+        );
     }
 }
@@ -0,0 +1,45 @@
+//! Just enough reading of Google Fonts METADATA.pb to annotate results
+
+use std::{fs, path::Path};
+
+/// The designer declared in the METADATA.pb alongside font_file, if any
+pub fn designer(font_file: &Path) -> Option<String> {
+    let metadata_file = font_file.parent()?.join("METADATA.pb");
+    let content = fs::read_to_string(metadata_file).ok()?;
+    parse_designer(&content)
+}
+
+/// The top level designer field of a METADATA.pb
+///
+/// METADATA.pb is a text format protobuf; top level fields start at column 0.
+fn parse_designer(content: &str) -> Option<String> {
+    content.lines().find_map(|line| {
+        let value = line.strip_prefix("designer:")?.trim();
+        let value = value.strip_prefix('"')?.strip_suffix('"')?;
+        (!value.is_empty()).then(|| value.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metadata::parse_designer;
+
+    #[test]
+    fn parse_designers() {
+        let metadata = r#"name: "Fam"
+designer: "Some Body, Else"
+license: "OFL"
+fonts {
+  designer: "Not Me"
+}
+"#;
+        assert_eq!(
+            vec![Some("Some Body, Else".to_string()), None, None],
+            vec![
+                parse_designer(metadata),
+                parse_designer("name: \"Fam\"\n"),
+                parse_designer("designer: \"\"\n"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,61 @@
+//! Periodic progress logging for long runs
+//!
+//! Logged at info under this module so `--progress` can enable just these lines without
+//! the rest of the info logging.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How many times over a run to report progress
+const REPORTS_PER_RUN: usize = 20;
+
+/// Counts items of work done, from any thread, logging every so often
+#[derive(Debug)]
+pub struct Progress {
+    what: &'static str,
+    total: usize,
+    every: usize,
+    done: AtomicUsize,
+}
+
+impl Progress {
+    /// Track total items described by what, e.g. "fonts"
+    pub fn new(what: &'static str, total: usize) -> Self {
+        Self {
+            what,
+            total,
+            every: (total / REPORTS_PER_RUN).max(1),
+            done: AtomicUsize::new(0),
+        }
+    }
+
+    /// Count one more item done, returning how many are done
+    pub fn tick(&self) -> usize {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if done.is_multiple_of(self.every) || done == self.total {
+            log::info!("processed {done}/{} {}", self.total, self.what);
+        }
+        done
+    }
+
+    pub fn done(&self) -> usize {
+        self.done.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rayon::prelude::*;
+
+    use crate::progress::Progress;
+
+    #[test]
+    fn counts_every_item() {
+        let progress = Progress::new("fonts", 1000);
+        let last = (0..1000)
+            .into_par_iter()
+            .map(|_| progress.tick())
+            .max()
+            .unwrap();
+        assert_eq!((1000, 1000), (last, progress.done()));
+    }
+}
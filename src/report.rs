@@ -0,0 +1,324 @@
+//! Machine readable results, for tracking duplicates across runs
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The version of the report format, bumped whenever a change would break existing readers
+///
+/// Reports written before the version was recorded are version 1.
+pub const SCHEMA_VERSION: u32 = 1;
+
+fn first_schema_version() -> u32 {
+    1
+}
+
+#[derive(Error, Debug)]
+pub enum ReportError {
+    #[error("Unable to access {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+    #[error("Invalid report {0:?}: {1}")]
+    Json(PathBuf, serde_json::Error),
+    #[error("{0:?} is schema version {1}, only versions up to {SCHEMA_VERSION} are understood")]
+    UnsupportedVersion(PathBuf, u32),
+}
+
+/// A set of files that share enough letterforms to be reported
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct Cluster {
+    /// Sorted
+    pub files: Vec<String>,
+    pub matched_chars: usize,
+    pub total_chars: usize,
+    pub score_pct: f64,
+    /// Mismatched chars ignored when scoring, see --allow-diff
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub waived_chars: Vec<String>,
+}
+
+impl Cluster {
+    pub fn new(
+        files: impl IntoIterator<Item = String>,
+        matched_chars: usize,
+        total_chars: usize,
+    ) -> Self {
+        let mut files = files.into_iter().collect::<Vec<_>>();
+        files.sort();
+        Self {
+            files,
+            matched_chars,
+            total_chars,
+            score_pct: score_pct(matched_chars, total_chars),
+            waived_chars: Vec::new(),
+        }
+    }
+}
+
+fn score_pct(matched_chars: usize, total_chars: usize) -> f64 {
+    if total_chars > 0 {
+        100.0 * matched_chars as f64 / total_chars as f64
+    } else {
+        0.0
+    }
+}
+
+/// The other file most similar to a file, whether or not they are similar enough to report
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct NearestNeighbor {
+    pub file: String,
+    pub matched_chars: usize,
+    pub total_chars: usize,
+    pub score_pct: f64,
+    /// Whether the pair meets the threshold for duplicates
+    pub duplicate: bool,
+}
+
+impl NearestNeighbor {
+    pub fn new(file: String, matched_chars: usize, total_chars: usize, duplicate: bool) -> Self {
+        Self {
+            file,
+            matched_chars,
+            total_chars,
+            score_pct: score_pct(matched_chars, total_chars),
+            duplicate,
+        }
+    }
+}
+
+/// A family skipped because none of its fonts could be picked to represent it
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct FamilyWithoutExemplar {
+    pub dir: String,
+    /// The font files that were present
+    pub files: Vec<String>,
+}
+
+/// Every reported cluster, sorted by files
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct Report {
+    /// The version of the report format, see [`SCHEMA_VERSION`]
+    #[serde(default = "first_schema_version")]
+    pub schema_version: u32,
+    pub clusters: Vec<Cluster>,
+    /// For each file, the other file most like it, if requested with --nearest-neighbors
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub nearest_neighbor: BTreeMap<String, NearestNeighbor>,
+    /// Families in --google-fonts that weren't compared for lack of an exemplar
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub families_without_exemplar: Vec<FamilyWithoutExemplar>,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+/// A cluster reported by two runs with different scores
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct ChangedCluster {
+    pub files: Vec<String>,
+    pub before: Cluster,
+    pub after: Cluster,
+}
+
+/// How the clusters of a run differ from those of a previous run
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+pub struct ReportDiff {
+    pub added: Vec<Cluster>,
+    pub removed: Vec<Cluster>,
+    pub changed: Vec<ChangedCluster>,
+}
+
+impl ReportDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Report {
+    pub fn new(mut clusters: Vec<Cluster>) -> Self {
+        clusters.sort_by(|a, b| a.files.cmp(&b.files));
+        Self {
+            schema_version: SCHEMA_VERSION,
+            clusters,
+            nearest_neighbor: BTreeMap::new(),
+            families_without_exemplar: Vec::new(),
+        }
+    }
+
+    /// The JSON Schema reports, as written by [`Report::write`], conform to
+    pub fn json_schema() -> RootSchema {
+        schema_for!(Report)
+    }
+
+    /// Just the clusters, as a JSON array, for --format json
+    pub fn clusters_json(&self) -> String {
+        serde_json::to_string_pretty(&self.clusters).expect("Clusters are serializable")
+    }
+
+    pub fn load(file: &Path) -> Result<Self, ReportError> {
+        let content =
+            fs::read_to_string(file).map_err(|e| ReportError::Io(file.to_path_buf(), e))?;
+        let report: Report =
+            serde_json::from_str(&content).map_err(|e| ReportError::Json(file.to_path_buf(), e))?;
+        if report.schema_version > SCHEMA_VERSION {
+            return Err(ReportError::UnsupportedVersion(
+                file.to_path_buf(),
+                report.schema_version,
+            ));
+        }
+        Ok(report)
+    }
+
+    pub fn write(&self, file: &Path) -> Result<(), ReportError> {
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| ReportError::Json(file.to_path_buf(), e))?;
+        fs::write(file, content).map_err(|e| ReportError::Io(file.to_path_buf(), e))
+    }
+
+    /// What changed since previous, clusters being identified by their files
+    pub fn diff(&self, previous: &Report) -> ReportDiff {
+        let before = previous
+            .clusters
+            .iter()
+            .map(|c| (&c.files, c))
+            .collect::<HashMap<_, _>>();
+        let after = self
+            .clusters
+            .iter()
+            .map(|c| (&c.files, c))
+            .collect::<HashMap<_, _>>();
+
+        let mut diff = ReportDiff::default();
+        for cluster in self.clusters.iter() {
+            match before.get(&cluster.files) {
+                None => diff.added.push(cluster.clone()),
+                Some(prev) if *prev != cluster => diff.changed.push(ChangedCluster {
+                    files: cluster.files.clone(),
+                    before: (*prev).clone(),
+                    after: cluster.clone(),
+                }),
+                Some(..) => (),
+            }
+        }
+        diff.removed = previous
+            .clusters
+            .iter()
+            .filter(|c| !after.contains_key(&c.files))
+            .cloned()
+            .collect();
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::report::{Cluster, FamilyWithoutExemplar, Report, ReportError, SCHEMA_VERSION};
+
+    fn cluster(files: &[&str], matched_chars: usize) -> Cluster {
+        Cluster::new(files.iter().map(|f| f.to_string()), matched_chars, 10)
+    }
+
+    #[test]
+    fn diff_reports() {
+        let previous = Report::new(vec![
+            cluster(&["a.ttf", "b.ttf"], 9),
+            cluster(&["c.ttf", "d.ttf"], 8),
+            cluster(&["e.ttf", "f.ttf"], 10),
+        ]);
+        let current = Report::new(vec![
+            cluster(&["f.ttf", "e.ttf"], 10),
+            cluster(&["b.ttf", "a.ttf"], 10),
+            cluster(&["g.ttf", "h.ttf"], 8),
+        ]);
+        let diff = current.diff(&previous);
+        assert_eq!(vec![cluster(&["g.ttf", "h.ttf"], 8)], diff.added);
+        assert_eq!(vec![cluster(&["c.ttf", "d.ttf"], 8)], diff.removed);
+        assert_eq!(
+            vec![(9, 10)],
+            diff.changed
+                .iter()
+                .map(|c| (c.before.matched_chars, c.after.matched_chars))
+                .collect::<Vec<_>>()
+        );
+        assert!(current.diff(&current).is_empty());
+    }
+
+    #[test]
+    fn clusters_json_round_trips() {
+        let report = Report::new(vec![
+            cluster(&["d.ttf", "c.ttf"], 9),
+            cluster(&["b.ttf", "a.ttf", "e.ttf"], 10),
+        ]);
+        let json = report.clusters_json();
+        let clusters: Vec<Cluster> = serde_json::from_str(&json).unwrap();
+        assert_eq!(report.clusters, clusters);
+        assert_eq!(
+            vec![vec!["a.ttf", "b.ttf", "e.ttf"], vec!["c.ttf", "d.ttf"]],
+            clusters.iter().map(|c| c.files.clone()).collect::<Vec<_>>()
+        );
+
+        let value = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "files": ["c.ttf", "d.ttf"],
+                "matched_chars": 9,
+                "total_chars": 10,
+                "score_pct": 90.0
+            }),
+            value[1]
+        );
+        assert_eq!(json, Report::new(clusters).clusters_json());
+    }
+
+    #[test]
+    fn reports_families_without_exemplar() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("report.json");
+
+        let mut report = Report::new(vec![cluster(&["a.ttf", "b.ttf"], 9)]);
+        report.write(&file).unwrap();
+        assert!(!fs::read_to_string(&file)
+            .unwrap()
+            .contains("families_without_exemplar"));
+
+        report.families_without_exemplar = vec![FamilyWithoutExemplar {
+            dir: "ofl/abc".to_string(),
+            files: vec!["ofl/abc/Abc-Odd.ttf".to_string()],
+        }];
+        report.write(&file).unwrap();
+        assert_eq!(report, Report::load(&file).unwrap());
+    }
+
+    #[test]
+    fn versions_reports() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("report.json");
+
+        fs::write(&file, r#"{"clusters": []}"#).unwrap();
+        assert_eq!(1, Report::load(&file).unwrap().schema_version);
+
+        let newer = format!(
+            r#"{{"schema_version": {}, "clusters": []}}"#,
+            SCHEMA_VERSION + 1
+        );
+        fs::write(&file, newer).unwrap();
+        assert!(matches!(
+            Report::load(&file),
+            Err(ReportError::UnsupportedVersion(..))
+        ));
+
+        let schema = serde_json::to_value(Report::json_schema()).unwrap();
+        assert!(schema["properties"]["schema_version"].is_object());
+        assert!(schema["definitions"]["Cluster"].is_object());
+    }
+}
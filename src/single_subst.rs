@@ -0,0 +1,193 @@
+//! Alternate forms of a char reached by GSUB single substitutions, e.g. the isolated or
+//! initial form of an Arabic letter
+//!
+//! This is not shaping. Only single substitutions are applied, one glyph in and one glyph
+//! out, which keeps letterforms keyed by char. Multiple, ligature and contextual lookups,
+//! including those of ccmp, are ignored, as are mark positioning, reordering and language
+//! specific features. Right to left text and combining marks need a real shaper.
+
+use std::collections::BTreeSet;
+
+use skrifa::{
+    raw::{
+        tables::gsub::{ExtensionSubtable, Gsub, SingleSubst, SubstitutionLookup},
+        ReadError, TableProvider,
+    },
+    FontRef, GlyphId, Tag,
+};
+
+/// Features applied to a char standing alone, as well as any requested ones
+const DEFAULT_FEATURES: [Tag; 2] = [Tag::new(b"ccmp"), Tag::new(b"locl")];
+
+/// Features picking the form of a joining char by its position in a word. A char standing
+/// alone is isolated unless another position is requested.
+const POSITIONAL_FEATURES: [Tag; 7] = [
+    Tag::new(b"isol"),
+    Tag::new(b"init"),
+    Tag::new(b"medi"),
+    Tag::new(b"med2"),
+    Tag::new(b"fina"),
+    Tag::new(b"fin2"),
+    Tag::new(b"fin3"),
+];
+
+/// The single substitution lookups of some features of a font, in lookup order
+pub struct SingleSubstitutions<'a> {
+    /// The subtables of each lookup
+    lookups: Vec<Vec<SingleSubst<'a>>>,
+}
+
+impl<'a> SingleSubstitutions<'a> {
+    /// The single substitutions of the default features and features, from the default
+    /// language of every script
+    ///
+    /// A font without GSUB substitutes nothing.
+    pub fn new(font: &FontRef<'a>, features: &[Tag]) -> Result<Self, ReadError> {
+        let Ok(gsub) = font.gsub() else {
+            return Ok(Self {
+                lookups: Vec::new(),
+            });
+        };
+        let mut wanted = DEFAULT_FEATURES
+            .iter()
+            .chain(features)
+            .collect::<BTreeSet<_>>();
+        if !features.iter().any(|f| POSITIONAL_FEATURES.contains(f)) {
+            wanted.insert(&POSITIONAL_FEATURES[0]);
+        }
+        let lookup_list = gsub.lookup_list()?;
+        let mut lookups = Vec::new();
+        for index in lookup_indices(&gsub, &wanted)? {
+            let mut subtables = Vec::new();
+            match lookup_list.lookups().get(index as usize)? {
+                SubstitutionLookup::Single(lookup) => {
+                    for subtable in lookup.subtables().iter() {
+                        subtables.push(subtable?);
+                    }
+                }
+                SubstitutionLookup::Extension(lookup) => {
+                    for subtable in lookup.subtables().iter() {
+                        if let ExtensionSubtable::Single(extension) = subtable? {
+                            subtables.push(extension.extension()?);
+                        }
+                    }
+                }
+                _ => (),
+            }
+            if !subtables.is_empty() {
+                lookups.push(subtables);
+            }
+        }
+        Ok(Self { lookups })
+    }
+
+    /// The glyph gid becomes once every lookup has had a go at it
+    ///
+    /// Within a lookup only the first subtable covering the glyph applies.
+    pub fn apply(&self, gid: GlyphId) -> GlyphId {
+        self.lookups.iter().fold(gid, |gid, subtables| {
+            subtables
+                .iter()
+                .find_map(|subtable| substitute(subtable, gid))
+                .unwrap_or(gid)
+        })
+    }
+}
+
+/// The indices of the lookups of the wanted features in the default language of any
+/// script, in the order they apply
+fn lookup_indices(gsub: &Gsub, wanted: &BTreeSet<&Tag>) -> Result<BTreeSet<u16>, ReadError> {
+    let script_list = gsub.script_list()?;
+    let feature_list = gsub.feature_list()?;
+    let mut feature_indices = BTreeSet::new();
+    for record in script_list.script_records() {
+        let script = record.script(script_list.offset_data())?;
+        if let Some(lang_sys) = script.default_lang_sys() {
+            feature_indices.extend(lang_sys?.feature_indices().iter().map(|i| i.get()));
+        }
+    }
+    let mut lookup_indices = BTreeSet::new();
+    for index in feature_indices {
+        let Some(record) = feature_list.feature_records().get(index as usize) else {
+            continue;
+        };
+        if wanted.contains(&record.feature_tag()) {
+            let feature = record.feature(feature_list.offset_data())?;
+            lookup_indices.extend(feature.lookup_list_indices().iter().map(|i| i.get()));
+        }
+    }
+    Ok(lookup_indices)
+}
+
+/// What subtable substitutes gid with, if it covers gid
+fn substitute(subtable: &SingleSubst, gid: GlyphId) -> Option<GlyphId> {
+    match subtable {
+        SingleSubst::Format1(format1) => {
+            format1.coverage().ok()?.get(gid)?;
+            let delta = format1.delta_glyph_id() as i32;
+            Some(GlyphId::new((gid.to_u16() as i32 + delta) as u16))
+        }
+        SingleSubst::Format2(format2) => {
+            let index = format2.coverage().ok()?.get(gid)?;
+            Some(format2.substitute_glyph_ids().get(index as usize)?.get())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kurbo::{Rect, Shape};
+    use skrifa::{FontRef, GlyphId, Tag};
+
+    use crate::{
+        single_subst::SingleSubstitutions,
+        test_fonts::{build_font_with_forms, build_font_with_lookup},
+    };
+
+    #[test]
+    fn substitutes_positional_forms() {
+        let square = |size: f64| Rect::new(0.0, 0.0, size, size).to_path(0.1);
+        // beh, then its isolated and initial forms
+        let font = build_font_with_forms(
+            1000,
+            &[('\u{0628}', square(100.0))],
+            &[("isol", square(200.0)), ("init", square(300.0))],
+        );
+        let font = FontRef::new(&font).unwrap();
+        let nominal = GlyphId::new(1);
+
+        let isolated = SingleSubstitutions::new(&font, &[]).unwrap();
+        assert_eq!(GlyphId::new(2), isolated.apply(nominal));
+
+        let initial = SingleSubstitutions::new(&font, &[Tag::new(b"init")]).unwrap();
+        assert_eq!(GlyphId::new(3), initial.apply(nominal));
+
+        // nothing covers a glyph that's already a form
+        assert_eq!(GlyphId::new(3), isolated.apply(GlyphId::new(3)));
+    }
+
+    #[test]
+    fn first_covering_subtable_applies() {
+        let square = |size: f64| Rect::new(0.0, 0.0, size, size).to_path(0.1);
+        // a -> b then b -> c in the same lookup
+        let font = build_font_with_lookup(
+            1000,
+            &[
+                ('a', square(100.0)),
+                ('b', square(200.0)),
+                ('c', square(300.0)),
+            ],
+            "isol",
+            &[(1, 2), (2, 3)],
+        );
+        let font = FontRef::new(&font).unwrap();
+        let substitutions = SingleSubstitutions::new(&font, &[]).unwrap();
+        assert_eq!(
+            (GlyphId::new(2), GlyphId::new(3)),
+            (
+                substitutions.apply(GlyphId::new(1)),
+                substitutions.apply(GlyphId::new(2))
+            )
+        );
+    }
+}
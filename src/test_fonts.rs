@@ -0,0 +1,580 @@
+//! Minimal fonts built on the fly so tests don't need binary fixtures
+
+use std::{collections::BTreeSet, io::Write};
+
+use flate2::{write::ZlibEncoder, Compression};
+use kurbo::BezPath;
+use skrifa::raw::{tables::glyf as read, FontRef, TableProvider};
+use write_fonts::{
+    tables::{
+        cmap::Cmap,
+        fvar::{AxisInstanceArrays, Fvar, InstanceRecord, VariationAxisRecord},
+        glyf::{GlyfLocaBuilder, Glyph, SimpleGlyph},
+        gsub::{Gsub, SingleSubst, SingleSubstFormat2, SubstitutionLookup},
+        gvar::{GlyphDelta, GlyphDeltas, GlyphVariations, Gvar},
+        head::Head,
+        hhea::Hhea,
+        hmtx::{Hmtx, LongMetric},
+        layout::{
+            CoverageTableBuilder, Feature, FeatureList, FeatureRecord, LangSys, Lookup, LookupFlag,
+            LookupList, Script, ScriptList, ScriptRecord,
+        },
+        maxp::Maxp,
+        name::{Name, NameRecord},
+        post::Post,
+        variations::Tuple,
+    },
+    types::{F2Dot14, Fixed, GlyphId, NameId, Tag},
+    FontBuilder,
+};
+
+use crate::about_the_same::{
+    ComparisonMetric, RulesOfSimilarity, DEFAULT_MAX_SAMPLES, DEFAULT_MIN_SAMPLES,
+    DEFAULT_SAMPLES_PER_1000_UNITS,
+};
+
+/// The rules tests compare letterforms by unless they're testing a rule
+pub const RULES: RulesOfSimilarity = RulesOfSimilarity {
+    equivalence: 2.0,
+    budget: 100.0,
+    error: 25.0,
+    advance_tolerance: None,
+    nearest_slack: 0.0,
+    symmetric: true,
+    metric: ComparisonMetric::Budget,
+    samples_per_1000_units: DEFAULT_SAMPLES_PER_1000_UNITS,
+    min_samples: DEFAULT_MIN_SAMPLES,
+    max_samples: DEFAULT_MAX_SAMPLES,
+    same_contour_count: false,
+};
+
+/// The tables shared by all our test fonts: .notdef and then one glyph per entry,
+/// mapped to the given char
+///
+/// Paths must be made of lines and quadratic curves.
+fn font_builder<'a>(
+    upem: u16,
+    glyphs: impl Iterator<Item = (char, &'a BezPath)>,
+) -> FontBuilder<'static> {
+    let advance = upem / 2;
+    let mut glyf = GlyfLocaBuilder::new();
+    glyf.add_glyph(&Glyph::Empty).unwrap();
+    let mut mappings = Vec::new();
+    let mut metrics = vec![LongMetric::new(advance, 0)];
+    for (i, (c, path)) in glyphs.enumerate() {
+        glyf.add_glyph(&SimpleGlyph::from_bezpath(path).unwrap())
+            .unwrap();
+        mappings.push((c, GlyphId::new(i as u16 + 1)));
+        metrics.push(LongMetric::new(advance, 0));
+    }
+    let (glyf, loca, loca_format) = glyf.build();
+
+    let head = Head {
+        units_per_em: upem,
+        index_to_loc_format: loca_format as i16,
+        ..Default::default()
+    };
+    let hhea = Hhea {
+        ascender: (upem as i16 / 10 * 8).into(),
+        descender: (upem as i16 / 10 * -2).into(),
+        number_of_long_metrics: metrics.len() as u16,
+        ..Default::default()
+    };
+    let num_glyphs = metrics.len() as u16;
+    let mut builder = FontBuilder::new();
+    builder
+        .add_table(&head)
+        .unwrap()
+        .add_table(&hhea)
+        .unwrap()
+        .add_table(&Hmtx::new(metrics, Vec::new()))
+        .unwrap()
+        .add_table(&Maxp::new(num_glyphs))
+        .unwrap()
+        .add_table(&Cmap::from_mappings(mappings))
+        .unwrap()
+        .add_table(&glyf)
+        .unwrap()
+        .add_table(&loca)
+        .unwrap();
+    builder
+}
+
+/// A static glyf font
+pub(crate) fn build_font(upem: u16, glyphs: &[(char, BezPath)]) -> Vec<u8> {
+    font_builder(upem, glyphs.iter().map(|(c, p)| (*c, p))).build()
+}
+
+/// A static glyf font whose .notdef is drawn and that maps notdef_chars to it, as fonts
+/// that show tofu via the cmap do
+pub(crate) fn build_font_with_notdef(
+    upem: u16,
+    notdef: &BezPath,
+    glyphs: &[(char, BezPath)],
+    notdef_chars: &[char],
+) -> Vec<u8> {
+    let mut builder = font_builder(upem, glyphs.iter().map(|(c, p)| (*c, p)));
+    let mut glyf = GlyfLocaBuilder::new();
+    let mut mappings = notdef_chars
+        .iter()
+        .map(|c| (*c, GlyphId::NOTDEF))
+        .collect::<Vec<_>>();
+    for (i, path) in [notdef]
+        .into_iter()
+        .chain(glyphs.iter().map(|(_, p)| p))
+        .enumerate()
+    {
+        glyf.add_glyph(&SimpleGlyph::from_bezpath(path).unwrap())
+            .unwrap();
+        if i > 0 {
+            mappings.push((glyphs[i - 1].0, GlyphId::new(i as u16)));
+        }
+    }
+    let (glyf, loca, loca_format) = glyf.build();
+    let head = Head {
+        units_per_em: upem,
+        index_to_loc_format: loca_format as i16,
+        ..Default::default()
+    };
+    builder
+        .add_table(&head)
+        .unwrap()
+        .add_table(&Cmap::from_mappings(mappings))
+        .unwrap()
+        .add_table(&glyf)
+        .unwrap()
+        .add_table(&loca)
+        .unwrap();
+    builder.build()
+}
+
+/// A font with no head table, which can't be normalized
+pub(crate) fn build_font_without_head() -> Vec<u8> {
+    let mut builder = FontBuilder::new();
+    builder.add_table(&Maxp::new(1)).unwrap();
+    builder.build()
+}
+
+/// A static glyf font with a post table naming each glyph, after .notdef
+pub(crate) fn build_named_font(upem: u16, glyphs: &[(char, BezPath)], names: &[&str]) -> Vec<u8> {
+    let mut builder = font_builder(upem, glyphs.iter().map(|(c, p)| (*c, p)));
+    builder
+        .add_table(&Post::new_v2(
+            [".notdef"].into_iter().chain(names.iter().copied()),
+        ))
+        .unwrap();
+    builder.build()
+}
+
+/// A static glyf font whose first glyph has unencoded forms, each substituted for it by
+/// a single substitution under the feature tag given with it
+pub(crate) fn build_font_with_forms(
+    upem: u16,
+    glyphs: &[(char, BezPath)],
+    forms: &[(&str, BezPath)],
+) -> Vec<u8> {
+    // forms are encoded in the private use area for font_builder, then the cmap is replaced
+    let all_glyphs = glyphs.iter().map(|(c, p)| (*c, p)).chain(
+        forms
+            .iter()
+            .enumerate()
+            .map(|(i, (_, p))| (char::from_u32(0xE000 + i as u32).unwrap(), p)),
+    );
+    let mut builder = font_builder(upem, all_glyphs);
+    let mappings = glyphs
+        .iter()
+        .enumerate()
+        .map(|(i, (c, _))| (*c, GlyphId::new(i as u16 + 1)));
+    let lookups = forms
+        .iter()
+        .enumerate()
+        .map(|(i, (tag, _))| {
+            let form = GlyphId::new((glyphs.len() + i) as u16 + 1);
+            (*tag, vec![(GlyphId::new(1), form)])
+        })
+        .collect::<Vec<_>>();
+    builder
+        .add_table(&Cmap::from_mappings(mappings))
+        .unwrap()
+        .add_table(&gsub(&lookups))
+        .unwrap();
+    builder.build()
+}
+
+/// A static glyf font with one GSUB lookup under feature tag, which has a single
+/// substitution subtable for each (from, to) glyph id pair, in order
+pub(crate) fn build_font_with_lookup(
+    upem: u16,
+    glyphs: &[(char, BezPath)],
+    tag: &str,
+    subtables: &[(u16, u16)],
+) -> Vec<u8> {
+    let mut builder = font_builder(upem, glyphs.iter().map(|(c, p)| (*c, p)));
+    let subtables = subtables
+        .iter()
+        .map(|(from, to)| (GlyphId::new(*from), GlyphId::new(*to)))
+        .collect();
+    builder.add_table(&gsub(&[(tag, subtables)])).unwrap();
+    builder.build()
+}
+
+/// A GSUB with a lookup of single substitution subtables, one per (from, to) pair, for
+/// each feature tag, all in the default language of arab
+fn gsub(lookups: &[(&str, Vec<(GlyphId, GlyphId)>)]) -> Gsub {
+    let features = lookups
+        .iter()
+        .enumerate()
+        .map(|(i, (tag, _))| {
+            FeatureRecord::new(
+                Tag::new_checked(tag.as_bytes()).unwrap(),
+                Feature::new(None, vec![i as u16]),
+            )
+        })
+        .collect();
+    let lookups = lookups
+        .iter()
+        .map(|(_, subtables)| {
+            let subtables = subtables
+                .iter()
+                .map(|(from, to)| {
+                    SingleSubst::from(SingleSubstFormat2::new(
+                        CoverageTableBuilder::from_glyphs(vec![*from]).build(),
+                        vec![*to],
+                    ))
+                })
+                .collect();
+            SubstitutionLookup::Single(Lookup::new(LookupFlag::empty(), subtables, 0))
+        })
+        .collect::<Vec<_>>();
+    let lang_sys = LangSys::new((0..lookups.len() as u16).collect());
+    let scripts = ScriptList::new(vec![ScriptRecord::new(
+        Tag::new(b"arab"),
+        Script::new(Some(lang_sys), Vec::new()),
+    )]);
+    Gsub::new(
+        scripts,
+        FeatureList::new(features),
+        LookupList::new(lookups),
+    )
+}
+
+/// A variable glyf font with a wght axis from 100 (default) to 900
+///
+/// Each glyph is given at wght 100 and 900, which must be point compatible.
+/// Named instances are (name, wght).
+pub(crate) fn build_variable_font(
+    upem: u16,
+    glyphs: &[(char, BezPath, BezPath)],
+    instances: &[(&str, f64)],
+) -> Vec<u8> {
+    let glyphs = glyphs
+        .iter()
+        .map(|(c, min, max)| (*c, min.clone(), None, max.clone()))
+        .collect::<Vec<_>>();
+    build_variable_font_with_middle(upem, &glyphs, instances)
+}
+
+/// [`build_variable_font`] where a glyph may also have a master at wght 500, drawn
+/// independently of those at 100 and 900 rather than interpolated
+pub(crate) fn build_variable_font_with_middle(
+    upem: u16,
+    glyphs: &[(char, BezPath, Option<BezPath>, BezPath)],
+    instances: &[(&str, f64)],
+) -> Vec<u8> {
+    let mut builder = font_builder(upem, glyphs.iter().map(|(c, p, ..)| (*c, p)));
+
+    let wght = Tag::new(b"wght");
+    let axis = VariationAxisRecord::new(
+        wght,
+        Fixed::from_f64(100.0),
+        Fixed::from_f64(100.0),
+        Fixed::from_f64(900.0),
+        0,
+        NameId::new(256),
+    );
+    let mut names = BTreeSet::from([NameRecord::new(
+        3,
+        1,
+        0x409,
+        NameId::new(256),
+        "Weight".to_string().into(),
+    )]);
+    let instances = instances
+        .iter()
+        .enumerate()
+        .map(|(i, (name, wght))| {
+            let name_id = NameId::new(257 + i as u16);
+            names.insert(NameRecord::new(
+                3,
+                1,
+                0x409,
+                name_id,
+                name.to_string().into(),
+            ));
+            InstanceRecord {
+                subfamily_name_id: name_id,
+                coordinates: vec![Fixed::from_f64(*wght)],
+                ..Default::default()
+            }
+        })
+        .collect();
+    let fvar = Fvar::new(AxisInstanceArrays::new(vec![axis], instances));
+
+    let tuple = |v: f32| Tuple::new(vec![F2Dot14::from_f32(v)]);
+    let points = |path: &BezPath| {
+        let glyph = SimpleGlyph::from_bezpath(path).unwrap();
+        glyph
+            .contours()
+            .iter()
+            .flat_map(|c| c.iter())
+            .map(|p| (p.x as f64, p.y as f64))
+            .collect::<Vec<_>>()
+    };
+    // phantom points don't move
+    let deltas = |deltas: Vec<(f64, f64)>| {
+        deltas
+            .into_iter()
+            .map(|(x, y)| GlyphDelta::required(x.round() as i16, y.round() as i16))
+            .chain([GlyphDelta::required(0, 0); 4])
+            .collect::<Vec<_>>()
+    };
+    let mut variations = vec![GlyphVariations::new(GlyphId::new(0), Vec::new())];
+    for (i, (_, min, middle, max)) in glyphs.iter().enumerate() {
+        let (min, max) = (points(min), points(max));
+        let to_max = min
+            .iter()
+            .zip(&max)
+            .map(|(min, max)| (max.0 - min.0, max.1 - min.1))
+            .collect::<Vec<_>>();
+        let mut tuples = vec![GlyphDeltas::new(tuple(1.0), deltas(to_max.clone()), None)];
+        if let Some(middle) = middle {
+            // wght 500 is normalized 0.5, where half of to_max already applies
+            let to_middle = points(middle)
+                .iter()
+                .zip(min.iter().zip(&to_max))
+                .map(|(mid, (min, to_max))| {
+                    (
+                        mid.0 - min.0 - to_max.0 / 2.0,
+                        mid.1 - min.1 - to_max.1 / 2.0,
+                    )
+                })
+                .collect();
+            tuples.push(GlyphDeltas::new(
+                tuple(0.5),
+                deltas(to_middle),
+                Some((tuple(0.0), tuple(1.0))),
+            ));
+        }
+        variations.push(GlyphVariations::new(GlyphId::new(i as u16 + 1), tuples));
+    }
+
+    builder
+        .add_table(&fvar)
+        .unwrap()
+        .add_table(&Gvar::new(variations).unwrap())
+        .unwrap()
+        .add_table(&Name::new(names))
+        .unwrap();
+    builder.build()
+}
+
+/// A WOFF with every table zlib compressed, unless that would make it larger
+pub(crate) fn build_woff(ttf: &[u8]) -> Vec<u8> {
+    let font = FontRef::new(ttf).unwrap();
+    let records = font.table_directory.table_records();
+    let mut directory = Vec::new();
+    let mut data = Vec::new();
+    let data_start = 44 + 20 * records.len();
+    for record in records {
+        let table = font.table_data(record.tag()).unwrap();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(table.as_bytes()).unwrap();
+        let mut compressed = encoder.finish().unwrap();
+        if compressed.len() >= table.len() {
+            compressed = table.as_bytes().to_vec();
+        }
+        directory.extend(record.tag().to_be_bytes());
+        directory.extend(((data_start + data.len()) as u32).to_be_bytes());
+        directory.extend((compressed.len() as u32).to_be_bytes());
+        directory.extend((table.len() as u32).to_be_bytes());
+        directory.extend(record.checksum().to_be_bytes());
+        data.extend(compressed);
+        data.resize(data.len().next_multiple_of(4), 0);
+    }
+
+    let mut woff = Vec::new();
+    woff.extend(b"wOFF");
+    woff.extend(font.table_directory.sfnt_version().to_be_bytes());
+    woff.extend(((data_start + data.len()) as u32).to_be_bytes());
+    woff.extend((records.len() as u16).to_be_bytes());
+    woff.extend([0; 2]); // reserved
+    woff.extend((ttf.len() as u32).to_be_bytes());
+    woff.extend([0, 1, 0, 0]); // version
+    woff.extend([0; 20]); // no metadata or private data
+    woff.extend(directory);
+    woff.extend(data);
+    woff
+}
+
+/// A WOFF2 with glyf and loca transformed, all points in the 4-byte triplet form
+pub(crate) fn build_woff2(ttf: &[u8]) -> Vec<u8> {
+    fn base128(mut value: u32, out: &mut Vec<u8>) {
+        let mut bytes = vec![(value & 0x7F) as u8];
+        value >>= 7;
+        while value > 0 {
+            bytes.push((value & 0x7F) as u8 | 0x80);
+            value >>= 7;
+        }
+        out.extend(bytes.iter().rev());
+    }
+    fn u255_16(value: u16, out: &mut Vec<u8>) {
+        if value < 253 {
+            out.push(value as u8);
+        } else {
+            out.push(253);
+            out.extend(value.to_be_bytes());
+        }
+    }
+
+    let font = FontRef::new(ttf).unwrap();
+    let glyf = font.glyf().unwrap();
+    let loca = font.loca(None).unwrap();
+    let num_glyphs = font.maxp().unwrap().num_glyphs();
+    let mut n_contours = Vec::new();
+    let mut n_points = Vec::new();
+    let mut flags = Vec::new();
+    let mut glyphs = Vec::new();
+    for gid in 0..num_glyphs {
+        let glyph = loca.get_glyf(GlyphId::new(gid), &glyf).unwrap();
+        let Some(read::Glyph::Simple(glyph)) = glyph else {
+            assert!(glyph.is_none(), "Only simple glyphs are supported");
+            n_contours.extend(0i16.to_be_bytes());
+            continue;
+        };
+        n_contours.extend(glyph.number_of_contours().to_be_bytes());
+        let mut start = 0;
+        for end in glyph.end_pts_of_contours() {
+            u255_16(end.get() + 1 - start, &mut n_points);
+            start = end.get() + 1;
+        }
+        let (mut x, mut y) = (0i16, 0i16);
+        for point in glyph.points() {
+            let (dx, dy) = (point.x - x, point.y - y);
+            (x, y) = (point.x, point.y);
+            let mut flag = 124 | (dx >= 0) as u8 | ((dy >= 0) as u8) << 1;
+            if !point.on_curve {
+                flag |= 0x80;
+            }
+            flags.push(flag);
+            glyphs.extend(dx.unsigned_abs().to_be_bytes());
+            glyphs.extend(dy.unsigned_abs().to_be_bytes());
+        }
+        u255_16(0, &mut glyphs); // no instructions
+    }
+    let bbox_bitmap = vec![0u8; (num_glyphs as usize).div_ceil(32) * 4];
+
+    let mut transformed_glyf = Vec::new();
+    transformed_glyf.extend([0; 4]); // reserved, optionFlags
+    transformed_glyf.extend(num_glyphs.to_be_bytes());
+    transformed_glyf.extend((font.head().unwrap().index_to_loc_format() as u16).to_be_bytes());
+    for stream in [
+        &n_contours,
+        &n_points,
+        &flags,
+        &glyphs,
+        &Vec::new(),
+        &bbox_bitmap,
+        &Vec::new(),
+    ] {
+        transformed_glyf.extend((stream.len() as u32).to_be_bytes());
+    }
+    for stream in [n_contours, n_points, flags, glyphs, bbox_bitmap] {
+        transformed_glyf.extend(stream);
+    }
+
+    // loca must follow glyf
+    let mut tags = font
+        .table_directory
+        .table_records()
+        .iter()
+        .map(|r| r.tag())
+        .filter(|t| *t != Tag::new(b"loca"))
+        .collect::<Vec<_>>();
+    let glyf_index = tags.iter().position(|t| *t == Tag::new(b"glyf")).unwrap();
+    tags.insert(glyf_index + 1, Tag::new(b"loca"));
+    let mut directory = Vec::new();
+    let mut data = Vec::new();
+    for tag in tags {
+        let table = font.table_data(tag).unwrap();
+        let known = match &tag.to_be_bytes() {
+            b"glyf" => 10,
+            b"loca" => 11,
+            b"cmap" => 0,
+            b"head" => 1,
+            b"hhea" => 2,
+            b"hmtx" => 3,
+            b"maxp" => 4,
+            _ => 63,
+        };
+        directory.push(known);
+        if known == 63 {
+            directory.extend(tag.to_be_bytes());
+        }
+        base128(table.len() as u32, &mut directory);
+        match known {
+            10 => {
+                base128(transformed_glyf.len() as u32, &mut directory);
+                data.extend(&transformed_glyf);
+            }
+            11 => base128(0, &mut directory),
+            _ => data.extend(table.as_bytes()),
+        }
+    }
+    let mut compressed = Vec::new();
+    {
+        let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 11, 22);
+        encoder.write_all(&data).unwrap();
+    }
+
+    let mut woff2 = Vec::new();
+    woff2.extend(b"wOF2");
+    woff2.extend(font.table_directory.sfnt_version().to_be_bytes());
+    let length = 48 + directory.len() + compressed.len();
+    woff2.extend((length as u32).to_be_bytes());
+    woff2.extend((font.table_directory.num_tables()).to_be_bytes());
+    woff2.extend([0; 2]); // reserved
+    woff2.extend((ttf.len() as u32).to_be_bytes());
+    woff2.extend((compressed.len() as u32).to_be_bytes());
+    woff2.extend([0, 1, 0, 0]); // version
+    woff2.extend([0; 20]); // no metadata or private data
+    woff2.extend(directory);
+    woff2.extend(compressed);
+    woff2
+}
+
+/// A TrueType collection of the given fonts
+pub(crate) fn build_collection(fonts: &[Vec<u8>]) -> Vec<u8> {
+    let mut ttc = Vec::new();
+    ttc.extend(b"ttcf");
+    ttc.extend([0, 1, 0, 0]); // version
+    ttc.extend((fonts.len() as u32).to_be_bytes());
+    let mut offset = ttc.len() + 4 * fonts.len();
+    let mut faces = Vec::new();
+    for font in fonts {
+        ttc.extend((offset as u32).to_be_bytes());
+        // table offsets are from the start of the file
+        let mut face = font.clone();
+        let num_tables = u16::from_be_bytes([font[4], font[5]]) as usize;
+        for record in (12..12 + 16 * num_tables).step_by(16) {
+            let table_offset =
+                u32::from_be_bytes(face[record + 8..record + 12].try_into().unwrap());
+            face[record + 8..record + 12]
+                .copy_from_slice(&(table_offset + offset as u32).to_be_bytes());
+        }
+        offset += face.len().next_multiple_of(4);
+        face.resize(face.len().next_multiple_of(4), 0);
+        faces.extend(face);
+    }
+    ttc.extend(faces);
+    ttc
+}
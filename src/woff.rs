@@ -0,0 +1,679 @@
+//! Decoding of WOFF and WOFF2 web fonts to the sfnt that [`FontRef`](skrifa::FontRef) reads
+//!
+//! See <https://www.w3.org/TR/WOFF/> and <https://www.w3.org/TR/WOFF2/>.
+
+use std::{collections::BTreeMap, io::Read};
+
+use thiserror::Error;
+use write_fonts::{types::Tag, FontBuilder};
+
+#[derive(Error, Debug)]
+pub enum WoffError {
+    #[error("Malformed {0}")]
+    Malformed(&'static str),
+    #[error("Unable to decompress {0}: {1}")]
+    Decompress(&'static str, std::io::Error),
+    #[error("WOFF2 font collections are not supported")]
+    Collection,
+}
+
+/// The sfnt bytes of a font file, decompressing WOFF and WOFF2
+///
+/// Anything else is returned unchanged for [`FontRef`](skrifa::FontRef) to make sense of.
+pub fn decode_font_bytes(bytes: Vec<u8>) -> Result<Vec<u8>, WoffError> {
+    match bytes.get(..4) {
+        Some(b"wOFF") => decode_woff(&bytes),
+        Some(b"wOF2") => decode_woff2(&bytes),
+        _ => Ok(bytes),
+    }
+}
+
+/// Reads big endian values, failing with what is being read if the data runs out
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    what: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], what: &'static str) -> Self {
+        Self { data, pos: 0, what }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], WoffError> {
+        let bytes = self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or(WoffError::Malformed(self.what))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, WoffError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, WoffError> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn i16(&mut self) -> Result<i16, WoffError> {
+        Ok(self.u16()? as i16)
+    }
+
+    fn u32(&mut self) -> Result<u32, WoffError> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    /// A WOFF2 UIntBase128
+    fn base128(&mut self) -> Result<u32, WoffError> {
+        let mut value = 0u32;
+        for i in 0..5 {
+            let byte = self.u8()?;
+            if (i == 0 && byte == 0x80) || value & 0xFE00_0000 != 0 {
+                return Err(WoffError::Malformed(self.what));
+            }
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(WoffError::Malformed(self.what))
+    }
+
+    /// A WOFF2 255UInt16
+    fn u255_16(&mut self) -> Result<u16, WoffError> {
+        Ok(match self.u8()? {
+            253 => self.u16()?,
+            254 => self.u8()? as u16 + 253 * 2,
+            255 => self.u8()? as u16 + 253,
+            code => code as u16,
+        })
+    }
+}
+
+fn decode_woff(bytes: &[u8]) -> Result<Vec<u8>, WoffError> {
+    let mut header = Reader::new(bytes, "WOFF header");
+    header.bytes(12)?; // signature, flavor, length
+    let num_tables = header.u16()?;
+    header.bytes(30)?; // the rest of the header
+
+    let mut builder = FontBuilder::new();
+    for _ in 0..num_tables {
+        let tag = Tag::from_be_bytes(header.bytes(4)?.try_into().unwrap());
+        let offset = header.u32()? as usize;
+        let comp_length = header.u32()? as usize;
+        let orig_length = header.u32()? as usize;
+        header.u32()?; // checksum
+        let data = Reader::new(bytes, "WOFF table data")
+            .bytes(offset + comp_length)?
+            .get(offset..)
+            .unwrap();
+        if comp_length < orig_length {
+            let mut table = Vec::with_capacity(orig_length);
+            flate2::read::ZlibDecoder::new(data)
+                .read_to_end(&mut table)
+                .map_err(|e| WoffError::Decompress("WOFF table", e))?;
+            if table.len() != orig_length {
+                return Err(WoffError::Malformed("WOFF table length"));
+            }
+            builder.add_raw(tag, table);
+        } else {
+            builder.add_raw(tag, data.to_vec());
+        }
+    }
+    Ok(builder.build())
+}
+
+/// The tags of tables WOFF2 identifies by index, see
+/// <https://www.w3.org/TR/WOFF2/#table_dir_format>
+const KNOWN_TAGS: [&[u8; 4]; 63] = [
+    b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"OS/2", b"post", b"cvt ", b"fpgm",
+    b"glyf", b"loca", b"prep", b"CFF ", b"VORG", b"EBDT", b"EBLC", b"gasp", b"hdmx", b"kern",
+    b"LTSH", b"PCLT", b"VDMX", b"vhea", b"vmtx", b"BASE", b"GDEF", b"GPOS", b"GSUB", b"EBSC",
+    b"JSTF", b"MATH", b"CBDT", b"CBLC", b"COLR", b"CPAL", b"SVG ", b"sbix", b"acnt", b"avar",
+    b"bdat", b"bloc", b"bsln", b"cvar", b"fdsc", b"feat", b"fmtx", b"fvar", b"gvar", b"hsty",
+    b"just", b"lcar", b"mort", b"morx", b"opbd", b"prop", b"trak", b"Zapf", b"Silf", b"Glat",
+    b"Gloc", b"Feat", b"Sill",
+];
+
+const GLYF: Tag = Tag::new(b"glyf");
+const LOCA: Tag = Tag::new(b"loca");
+const HMTX: Tag = Tag::new(b"hmtx");
+const HHEA: Tag = Tag::new(b"hhea");
+
+/// A table of a WOFF2 font, still transformed if transformed is set
+struct Woff2Table<'a> {
+    data: &'a [u8],
+    transformed: bool,
+}
+
+fn decode_woff2(bytes: &[u8]) -> Result<Vec<u8>, WoffError> {
+    let mut header = Reader::new(bytes, "WOFF2 header");
+    header.bytes(4)?; // signature
+    if header.bytes(4)? == b"ttcf" {
+        return Err(WoffError::Collection);
+    }
+    header.u32()?; // length
+    let num_tables = header.u16()?;
+    header.bytes(6)?; // reserved, totalSfntSize
+    let compressed_size = header.u32()? as usize;
+    header.bytes(24)?; // version, metadata and private data
+
+    // (tag, length in the decompressed stream, transformed)
+    let mut directory = Vec::with_capacity(num_tables as usize);
+    for _ in 0..num_tables {
+        let flags = header.u8()?;
+        let tag = match flags & 0x3F {
+            63 => Tag::from_be_bytes(header.bytes(4)?.try_into().unwrap()),
+            i => Tag::new(KNOWN_TAGS[i as usize]),
+        };
+        let version = flags >> 6;
+        let orig_length = header.base128()?;
+        // glyf and loca use version 3 for no transform, everything else version 0
+        let transformed = if tag == GLYF || tag == LOCA {
+            version != 3
+        } else {
+            version != 0
+        };
+        let length = if transformed {
+            header.base128()?
+        } else {
+            orig_length
+        };
+        directory.push((tag, length as usize, transformed));
+    }
+
+    let mut stream = Vec::new();
+    brotli_decompressor::Decompressor::new(header.bytes(compressed_size)?, 4096)
+        .read_to_end(&mut stream)
+        .map_err(|e| WoffError::Decompress("WOFF2 table data", e))?;
+    let mut stream = Reader::new(&stream, "WOFF2 table data");
+    let mut tables = BTreeMap::new();
+    for (tag, length, transformed) in directory {
+        let data = stream.bytes(length)?;
+        tables.insert(tag, Woff2Table { data, transformed });
+    }
+
+    let mut builder = FontBuilder::new();
+    let mut x_mins = None;
+    if let Some(glyf) = tables.remove(&GLYF) {
+        if glyf.transformed {
+            let reconstructed = reconstruct_glyf(glyf.data)?;
+            builder.add_raw(GLYF, reconstructed.glyf);
+            builder.add_raw(LOCA, reconstructed.loca);
+            x_mins = Some(reconstructed.x_mins);
+            tables.remove(&LOCA);
+        } else {
+            builder.add_raw(GLYF, glyf.data.to_vec());
+        }
+    }
+    if let Some(hmtx) = tables.remove(&HMTX) {
+        if hmtx.transformed {
+            let x_mins = x_mins
+                .as_deref()
+                .ok_or(WoffError::Malformed("WOFF2 hmtx transformed without glyf"))?;
+            let num_h_metrics = tables
+                .get(&HHEA)
+                .and_then(|hhea| hhea.data.get(34..36))
+                .map(|v| u16::from_be_bytes(v.try_into().unwrap()))
+                .ok_or(WoffError::Malformed("WOFF2 hhea"))?;
+            builder.add_raw(HMTX, reconstruct_hmtx(hmtx.data, num_h_metrics, x_mins)?);
+        } else {
+            builder.add_raw(HMTX, hmtx.data.to_vec());
+        }
+    }
+    for (tag, table) in tables {
+        if table.transformed {
+            return Err(WoffError::Malformed("WOFF2 transform"));
+        }
+        builder.add_raw(tag, table.data.to_vec());
+    }
+    Ok(builder.build())
+}
+
+/// The glyf and loca tables rebuilt from a transformed glyf, and the xMin of each glyph
+struct ReconstructedGlyf {
+    glyf: Vec<u8>,
+    loca: Vec<u8>,
+    x_mins: Vec<i16>,
+}
+
+/// Rebuild glyf and loca, see <https://www.w3.org/TR/WOFF2/#glyf_table_format>
+fn reconstruct_glyf(data: &[u8]) -> Result<ReconstructedGlyf, WoffError> {
+    let mut header = Reader::new(data, "WOFF2 glyf header");
+    header.u16()?; // reserved
+    let option_flags = header.u16()?;
+    let num_glyphs = header.u16()? as usize;
+    let index_format = header.u16()?;
+    let mut sizes = [0usize; 7];
+    for size in sizes.iter_mut() {
+        *size = header.u32()? as usize;
+    }
+    // the streams follow the header in the same order as their sizes
+    let mut stream = {
+        let mut rest = Reader::new(data, "WOFF2 glyf streams");
+        rest.bytes(36)?;
+        rest
+    };
+    let mut streams = Vec::with_capacity(sizes.len());
+    for (size, what) in sizes.into_iter().zip([
+        "WOFF2 nContour stream",
+        "WOFF2 nPoints stream",
+        "WOFF2 flag stream",
+        "WOFF2 glyph stream",
+        "WOFF2 composite stream",
+        "WOFF2 bbox stream",
+        "WOFF2 instruction stream",
+    ]) {
+        streams.push(Reader::new(stream.bytes(size)?, what));
+    }
+    let [mut n_contours, mut n_points, mut flags, mut glyphs, mut composites, mut bboxes, mut instructions]: [Reader; 7] =
+        streams.try_into().map_err(|_| WoffError::Malformed("WOFF2 glyf"))?;
+    let overlap_bitmap = if option_flags & 1 != 0 {
+        Some(stream.bytes(num_glyphs.div_ceil(8))?)
+    } else {
+        None
+    };
+    let bbox_bitmap = bboxes.bytes(num_glyphs.div_ceil(32) * 4)?;
+    let bit = |bitmap: &[u8], i: usize| bitmap[i >> 3] & (0x80 >> (i & 7)) != 0;
+
+    let mut glyf = Vec::new();
+    let mut offsets = Vec::with_capacity(num_glyphs + 1);
+    let mut x_mins = Vec::with_capacity(num_glyphs);
+    for i in 0..num_glyphs {
+        offsets.push(glyf.len());
+        let num_contours = n_contours.i16()?;
+        let explicit_bbox = bit(bbox_bitmap, i);
+        let mut read_bbox = || -> Result<[i16; 4], WoffError> {
+            Ok([bboxes.i16()?, bboxes.i16()?, bboxes.i16()?, bboxes.i16()?])
+        };
+        if num_contours == 0 {
+            x_mins.push(0);
+            continue;
+        }
+        if num_contours < 0 {
+            let start = composites.pos;
+            let mut has_instructions = false;
+            loop {
+                let flags = composites.u16()?;
+                composites.u16()?; // glyph index
+                let args = if flags & 0x0001 != 0 { 4 } else { 2 };
+                let scale = if flags & 0x0008 != 0 {
+                    2
+                } else if flags & 0x0040 != 0 {
+                    4
+                } else if flags & 0x0080 != 0 {
+                    8
+                } else {
+                    0
+                };
+                composites.bytes(args + scale)?;
+                has_instructions |= flags & 0x0100 != 0;
+                if flags & 0x0020 == 0 {
+                    break;
+                }
+            }
+            let components = &composites.data[start..composites.pos];
+            if !explicit_bbox {
+                return Err(WoffError::Malformed("WOFF2 composite without bbox"));
+            }
+            let bbox = read_bbox()?;
+            glyf.extend(num_contours.to_be_bytes());
+            glyf.extend(bbox.iter().flat_map(|v| v.to_be_bytes()));
+            glyf.extend(components);
+            if has_instructions {
+                let len = glyphs.u255_16()?;
+                glyf.extend(len.to_be_bytes());
+                glyf.extend(instructions.bytes(len as usize)?);
+            }
+            x_mins.push(bbox[0]);
+        } else {
+            let mut end_pts = Vec::with_capacity(num_contours as usize);
+            let mut total = 0u16;
+            for _ in 0..num_contours {
+                total = total
+                    .checked_add(n_points.u255_16()?)
+                    .ok_or(WoffError::Malformed("WOFF2 nPoints stream"))?;
+                end_pts.push(total.wrapping_sub(1));
+            }
+            let (mut x, mut y) = (0i16, 0i16);
+            let mut points = Vec::with_capacity(total as usize);
+            for _ in 0..total {
+                let flag = flags.u8()?;
+                let (dx, dy) = decode_triplet(flag & 0x7F, &mut glyphs)?;
+                x = x.wrapping_add(dx);
+                y = y.wrapping_add(dy);
+                points.push((x, y, flag & 0x80 == 0));
+            }
+            let instruction_len = glyphs.u255_16()?;
+            let bbox = if explicit_bbox {
+                read_bbox()?
+            } else {
+                points.iter().fold(
+                    [i16::MAX, i16::MAX, i16::MIN, i16::MIN],
+                    |[x0, y0, x1, y1], (x, y, _)| [x0.min(*x), y0.min(*y), x1.max(*x), y1.max(*y)],
+                )
+            };
+            glyf.extend(num_contours.to_be_bytes());
+            glyf.extend(bbox.iter().flat_map(|v| v.to_be_bytes()));
+            glyf.extend(end_pts.iter().flat_map(|v| v.to_be_bytes()));
+            glyf.extend(instruction_len.to_be_bytes());
+            glyf.extend(instructions.bytes(instruction_len as usize)?);
+            let overlap = overlap_bitmap.is_some_and(|bitmap| bit(bitmap, i));
+            for (j, (_, _, on_curve)) in points.iter().enumerate() {
+                // coordinates are written as plain 16-bit deltas
+                let mut flag = if *on_curve { 0x01 } else { 0x00 };
+                if j == 0 && overlap {
+                    flag |= 0x40;
+                }
+                glyf.push(flag);
+            }
+            let mut previous = (0i16, 0i16);
+            let mut y_deltas = Vec::with_capacity(points.len() * 2);
+            for (x, y, _) in points.iter() {
+                glyf.extend(x.wrapping_sub(previous.0).to_be_bytes());
+                y_deltas.extend(y.wrapping_sub(previous.1).to_be_bytes());
+                previous = (*x, *y);
+            }
+            glyf.extend(y_deltas);
+            x_mins.push(bbox[0]);
+        }
+        glyf.resize(glyf.len().next_multiple_of(4), 0);
+    }
+    offsets.push(glyf.len());
+
+    let loca = if index_format == 0 {
+        offsets
+            .iter()
+            .map(|o| u16::try_from(o / 2).map_err(|_| WoffError::Malformed("WOFF2 short loca")))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .flat_map(u16::to_be_bytes)
+            .collect()
+    } else {
+        offsets
+            .iter()
+            .flat_map(|o| (*o as u32).to_be_bytes())
+            .collect()
+    };
+    Ok(ReconstructedGlyf { glyf, loca, x_mins })
+}
+
+/// A point delta from the glyph stream, see <https://www.w3.org/TR/WOFF2/#triplet_decoding>
+fn decode_triplet(flag: u8, glyphs: &mut Reader) -> Result<(i16, i16), WoffError> {
+    let with_sign = |flag: u8, value: i32| {
+        if flag & 1 != 0 {
+            value as i16
+        } else {
+            -value as i16
+        }
+    };
+    let flag32 = flag as i32;
+    Ok(match flag {
+        0..=9 => {
+            let b0 = glyphs.u8()? as i32;
+            (0, with_sign(flag, ((flag32 & 14) << 7) + b0))
+        }
+        10..=19 => {
+            let b0 = glyphs.u8()? as i32;
+            (with_sign(flag, (((flag32 - 10) & 14) << 7) + b0), 0)
+        }
+        20..=83 => {
+            let b0 = flag32 - 20;
+            let b1 = glyphs.u8()? as i32;
+            (
+                with_sign(flag, 1 + (b0 & 0x30) + (b1 >> 4)),
+                with_sign(flag >> 1, 1 + ((b0 & 0x0C) << 2) + (b1 & 0x0F)),
+            )
+        }
+        84..=119 => {
+            let b0 = flag32 - 84;
+            let data = glyphs.bytes(2)?;
+            (
+                with_sign(flag, 1 + ((b0 / 12) << 8) + data[0] as i32),
+                with_sign(flag >> 1, 1 + (((b0 % 12) >> 2) << 8) + data[1] as i32),
+            )
+        }
+        120..=123 => {
+            let data = glyphs.bytes(3)?;
+            let b2 = data[1] as i32;
+            (
+                with_sign(flag, ((data[0] as i32) << 4) + (b2 >> 4)),
+                with_sign(flag >> 1, ((b2 & 0x0F) << 8) + data[2] as i32),
+            )
+        }
+        _ => {
+            let data = glyphs.bytes(4)?;
+            (
+                with_sign(flag, ((data[0] as i32) << 8) + data[1] as i32),
+                with_sign(flag >> 1, ((data[2] as i32) << 8) + data[3] as i32),
+            )
+        }
+    })
+}
+
+/// Rebuild hmtx, see <https://www.w3.org/TR/WOFF2/#hmtx_table_format>
+fn reconstruct_hmtx(data: &[u8], num_h_metrics: u16, x_mins: &[i16]) -> Result<Vec<u8>, WoffError> {
+    let num_h_metrics = num_h_metrics as usize;
+    if num_h_metrics > x_mins.len() {
+        return Err(WoffError::Malformed("WOFF2 hmtx"));
+    }
+    let mut hmtx = Reader::new(data, "WOFF2 hmtx");
+    let flags = hmtx.u8()?;
+    let advances = (0..num_h_metrics)
+        .map(|_| hmtx.u16())
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut lsbs = Vec::with_capacity(x_mins.len());
+    for (i, x_min) in x_mins.iter().enumerate() {
+        let omitted = if i < num_h_metrics {
+            flags & 1 != 0
+        } else {
+            flags & 2 != 0
+        };
+        lsbs.push(if omitted { *x_min } else { hmtx.i16()? });
+    }
+    let mut table = Vec::with_capacity(num_h_metrics * 4 + (x_mins.len() - num_h_metrics) * 2);
+    for (i, lsb) in lsbs.iter().enumerate() {
+        if let Some(advance) = advances.get(i) {
+            table.extend(advance.to_be_bytes());
+        }
+        table.extend(lsb.to_be_bytes());
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use kurbo::{BezPath, Rect, Shape};
+    use skrifa::{
+        instance::{LocationRef, Size},
+        raw::{tables::glyf::Glyph, TableProvider},
+        FontRef, GlyphId, MetadataProvider,
+    };
+    use write_fonts::pens::BezPathPen;
+
+    use crate::{
+        letterform::Letterform,
+        test_fonts::{build_font, build_woff, build_woff2},
+        woff::{decode_font_bytes, decode_triplet, reconstruct_hmtx, Reader, WoffError},
+    };
+
+    fn ttf() -> Vec<u8> {
+        let mut bowl = BezPath::new();
+        bowl.move_to((0.0, 0.0));
+        bowl.quad_to((250.0, 500.0), (500.0, 0.0));
+        bowl.line_to((400.0, 0.0));
+        bowl.quad_to((250.0, 300.0), (100.0, 0.0));
+        bowl.close_path();
+        let mut two = Rect::new(0.0, 0.0, 100.0, 600.0).to_path(0.1);
+        two.extend(Rect::new(300.0, -1200.0, 1500.0, 2000.0).to_path(0.1));
+        build_font(
+            1000,
+            &[
+                ('a', bowl),
+                ('l', Rect::new(0.0, 0.0, 100.0, 600.0).to_path(0.1)),
+                ('m', two),
+            ],
+        )
+    }
+
+    fn assert_same_outlines(expected: &[u8], actual: &[u8]) {
+        let expected = FontRef::new(expected).unwrap();
+        let actual = FontRef::new(actual).unwrap();
+        for c in ['a', 'l', 'm'] {
+            let expected = Letterform::create(&expected, c, 1.0, LocationRef::default()).unwrap();
+            let actual = Letterform::create(&actual, c, 1.0, LocationRef::default()).unwrap();
+            assert_eq!(
+                (expected.path().elements(), expected.advance),
+                (actual.path().elements(), actual.advance),
+                "{c}"
+            );
+        }
+    }
+
+    #[test]
+    fn passes_sfnt_through() {
+        assert_eq!(ttf(), decode_font_bytes(ttf()).unwrap());
+    }
+
+    #[test]
+    fn decodes_woff() {
+        let woff = build_woff(&ttf());
+        assert_eq!(b"wOFF", &woff[..4]);
+        assert_same_outlines(&ttf(), &decode_font_bytes(woff).unwrap());
+    }
+
+    #[test]
+    fn decodes_woff2() {
+        let woff2 = build_woff2(&ttf());
+        assert_eq!(b"wOF2", &woff2[..4]);
+        assert_same_outlines(&ttf(), &decode_font_bytes(woff2).unwrap());
+    }
+
+    #[test]
+    fn decodes_reference_woff2() {
+        // Made by a reference encoder, with a transformed glyf and composites. The tables
+        // the transform leaves alone say what the reconstructed glyphs should be.
+        let sfnt = decode_font_bytes(
+            include_bytes!("../resources/testdata/OpenSans-Regular.woff2").to_vec(),
+        )
+        .unwrap();
+        let font = FontRef::new(&sfnt).unwrap();
+        let head = font.head().unwrap();
+        let os2 = font.os2().unwrap();
+        let metrics = font.glyph_metrics(Size::unscaled(), LocationRef::default());
+        let glyf = font.glyf().unwrap();
+        let loca = font.loca(None).unwrap();
+        let outlines = font.outline_glyphs();
+        let cmap = font.charmap();
+
+        let mut bounds = Rect::new(f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+        let mut composites = 0;
+        for gid in (0..font.maxp().unwrap().num_glyphs()).map(GlyphId::new) {
+            let mut pen = BezPathPen::new();
+            outlines
+                .get(gid)
+                .unwrap()
+                .draw(Size::unscaled(), &mut pen)
+                .unwrap();
+            let Some(glyph) = loca.get_glyf(gid, &glyf).unwrap() else {
+                continue;
+            };
+            let cbox = pen.into_inner().control_box();
+            let header = Rect::new(
+                glyph.x_min() as f64,
+                glyph.y_min() as f64,
+                glyph.x_max() as f64,
+                glyph.y_max() as f64,
+            );
+            match glyph {
+                Glyph::Simple(..) => assert_eq!(cbox, header, "{gid}"),
+                Glyph::Composite(..) => composites += 1,
+            }
+            assert_eq!(
+                Some(glyph.x_min() as f32),
+                metrics.left_side_bearing(gid),
+                "lsb of {gid} is its xMin"
+            );
+            bounds = bounds.union(header);
+        }
+        assert!(composites > 0);
+        assert_eq!(
+            Rect::new(
+                head.x_min() as f64,
+                head.y_min() as f64,
+                head.x_max() as f64,
+                head.y_max() as f64
+            ),
+            bounds
+        );
+        let top = |c| {
+            let mut pen = BezPathPen::new();
+            outlines
+                .get(cmap.map(c).unwrap())
+                .unwrap()
+                .draw(Size::unscaled(), &mut pen)
+                .unwrap();
+            pen.into_inner().control_box().max_y() as i16
+        };
+        assert_eq!(
+            (os2.s_cap_height(), os2.sx_height()),
+            (Some(top('H')), Some(top('x')))
+        );
+    }
+
+    #[test]
+    fn decodes_every_triplet_form() {
+        // (flag, bytes, (dx, dy)) worked from the formulas in the WOFF2 spec, the low flag
+        // bits choose signs and the high ones how many bytes follow and their meaning
+        let cases: [(u8, &[u8], (i16, i16)); 12] = [
+            (0, &[5], (0, -5)),
+            (9, &[255], (0, 1279)),
+            (10, &[5], (-5, 0)),
+            (19, &[255], (1279, 0)),
+            (20, &[0x00], (-1, -1)),
+            (83, &[0xFF], (64, 64)),
+            (84, &[0, 0], (-1, -1)),
+            (119, &[255, 255], (768, 768)),
+            (120, &[0x12, 0x34, 0x56], (-291, -1110)),
+            (123, &[0xFF, 0xFF, 0xFF], (4095, 4095)),
+            (124, &[0x01, 0x00, 0x02, 0x00], (-256, -512)),
+            (127, &[0x7F, 0xFF, 0x7F, 0xFF], (32767, 32767)),
+        ];
+        for (flag, bytes, expected) in cases {
+            let mut glyphs = Reader::new(bytes, "test");
+            assert_eq!(
+                expected,
+                decode_triplet(flag, &mut glyphs).unwrap(),
+                "{flag}"
+            );
+            assert_eq!(bytes.len(), glyphs.pos, "{flag} reads every byte");
+        }
+    }
+
+    #[test]
+    fn reconstructs_transformed_hmtx() {
+        // two long metrics then one lsb; flags 1 omits the long metrics' lsbs only
+        let data = [1, 0x01, 0xF4, 0x02, 0x58, 0xFF, 0xF6];
+        assert_eq!(
+            vec![0x01, 0xF4, 0, 10, 0x02, 0x58, 0, 20, 0xFF, 0xF6],
+            reconstruct_hmtx(&data, 2, &[10, 20, 30]).unwrap()
+        );
+        // 3 omits every lsb
+        assert_eq!(
+            vec![0x01, 0xF4, 0, 10, 0x02, 0x58, 0, 20, 0, 30],
+            reconstruct_hmtx(&[3, 0x01, 0xF4, 0x02, 0x58], 2, &[10, 20, 30]).unwrap()
+        );
+    }
+
+    #[test]
+    fn truncated_woff2_is_an_error() {
+        let woff2 = build_woff2(&ttf());
+        assert!(matches!(
+            decode_font_bytes(woff2[..woff2.len() / 2].to_vec()),
+            Err(WoffError::Malformed(..))
+        ));
+    }
+}